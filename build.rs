@@ -0,0 +1,82 @@
+//! Packs every image in `assets/` into a sprite atlas ahead of time. Writes the atlas pages as
+//! `sprite_atlas_<page>.png` and a table of named rects as `sprite_atlas.rs` to `OUT_DIR`, both
+//! included by `src/sprite_atlas.rs`.
+
+use std::{env, ffi::OsStr, fs, path::Path};
+
+use anyhow::{format_err, Error};
+use image::RgbaImage;
+
+#[allow(dead_code)]
+#[path = "src/texture_atlas.rs"]
+mod texture_atlas;
+
+use texture_atlas::{AtlasRect, TextureAtlas};
+
+// must match graphics::TEXTURE_ATLAS_SIZE
+const ATLAS_SIZE: (u32, u32) = (1024, 1024);
+// keeps nearest sampling at sprite edges from picking up neighbouring sprites
+const ATLAS_PADDING: u32 = 1;
+
+fn main() -> Result<(), Error> {
+    println!("cargo:rerun-if-changed=assets");
+    println!("cargo:rerun-if-changed=src/texture_atlas.rs");
+
+    let mut images = Vec::new();
+    for entry in fs::read_dir("assets")? {
+        let path = entry?.path();
+        if path.extension() == Some(OsStr::new("png")) {
+            println!("cargo:rerun-if-changed={}", path.display());
+            let name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| format_err!("bad image name {}", path.display()))?
+                .to_owned();
+            images.push((name, image::open(&path)?.to_rgba()));
+        }
+    }
+    // tallest first packs the rows tightest, names keep the output the same between builds
+    images.sort_by(|(a_name, a), (b_name, b)| {
+        b.height().cmp(&a.height()).then_with(|| a_name.cmp(b_name))
+    });
+
+    let mut atlas = TextureAtlas::new(ATLAS_SIZE, ATLAS_PADDING);
+    let mut pages = Vec::new();
+    let mut rects = Vec::new();
+    for (name, image) in images.iter() {
+        let rect = atlas
+            .add_texture(image.dimensions())
+            .map_err(|e| format_err!("could not pack {}: {}", name, e))?;
+        if rect.page == pages.len() {
+            pages.push(RgbaImage::new(ATLAS_SIZE.0, ATLAS_SIZE.1));
+        }
+        image::imageops::replace(&mut pages[rect.page], image, rect.rect[0], rect.rect[1]);
+        rects.push((name, rect));
+    }
+    rects.sort();
+
+    let out_dir = env::var("OUT_DIR")?;
+    let out_dir = Path::new(&out_dir);
+    let mut table = String::from("pub const SPRITE_ATLAS_PAGES: &[&[u8]] = &[\n");
+    for (i, page) in pages.iter().enumerate() {
+        let file_name = format!("sprite_atlas_{}.png", i);
+        page.save(out_dir.join(&file_name))?;
+        table += &format!(
+            "    include_bytes!(concat!(env!(\"OUT_DIR\"), \"/{}\")),\n",
+            file_name
+        );
+    }
+    table += "];\n\n";
+
+    table += "pub const SPRITE_RECTS: &[(&str, AtlasRect)] = &[\n";
+    for (name, AtlasRect { page, rect }) in rects {
+        table += &format!(
+            "    ({:?}, AtlasRect {{ page: {}, rect: [{}, {}, {}, {}] }}),\n",
+            name, page, rect[0], rect[1], rect[2], rect[3]
+        );
+    }
+    table += "];\n";
+    fs::write(out_dir.join("sprite_atlas.rs"), table)?;
+
+    Ok(())
+}
@@ -0,0 +1,448 @@
+use std::{fmt, io::Write, str::FromStr};
+
+use anyhow::{format_err, Error};
+use euclid::default::Point2D;
+
+use crate::input::{InputEvent, Key};
+
+/// 64 bit FNV-1a. Unlike the `std` hashers its output is fully specified, so hashes taken on
+/// native and on wasm can be compared directly.
+pub struct StateHasher(u64);
+
+impl StateHasher {
+    pub fn new() -> StateHasher {
+        StateHasher(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub fn write_u64(&mut self, x: u64) {
+        self.write_bytes(&x.to_le_bytes());
+    }
+
+    // usize is 32 bits on wasm, always hash it as 64 bits
+    pub fn write_usize(&mut self, x: usize) {
+        self.write_u64(x as u64);
+    }
+
+    pub fn write_i32(&mut self, x: i32) {
+        self.write_bytes(&x.to_le_bytes());
+    }
+
+    pub fn write_bool(&mut self, x: bool) {
+        self.write_bytes(&[x as u8]);
+    }
+
+    // hash the exact bits, any float difference at all is a desync
+    pub fn write_f32(&mut self, x: f32) {
+        self.write_bytes(&x.to_bits().to_le_bytes());
+    }
+
+    pub fn write_point(&mut self, p: Point2D<f32>) {
+        self.write_f32(p.x);
+        self.write_f32(p.y);
+    }
+
+    pub fn write_tile(&mut self, p: Point2D<i32>) {
+        self.write_i32(p.x);
+        self.write_i32(p.y);
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+pub trait HashState {
+    fn hash_state(&self, hasher: &mut StateHasher);
+}
+
+pub fn hash_of<T: HashState>(value: &T) -> u64 {
+    let mut hasher = StateHasher::new();
+    value.hash_state(&mut hasher);
+    hasher.finish()
+}
+
+/// Identifies a piece of simulation state in a `TickHash`. Entities stored in hash maps are keyed
+/// by their tile, bulbs by the tile they spawn on, so ids are stable between runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EntityId {
+    Game,
+    Ghost(usize),
+    Button((i32, i32)),
+    Door((i32, i32)),
+    Teleporter((i32, i32)),
+    Bulb((i32, i32)),
+    TheMachine,
+}
+
+impl fmt::Display for EntityId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntityId::Game => write!(f, "game"),
+            EntityId::Ghost(i) => write!(f, "ghost/{}", i),
+            EntityId::Button(p) => write!(f, "button/{}/{}", p.0, p.1),
+            EntityId::Door(p) => write!(f, "door/{}/{}", p.0, p.1),
+            EntityId::Teleporter(p) => write!(f, "teleporter/{}/{}", p.0, p.1),
+            EntityId::Bulb(p) => write!(f, "bulb/{}/{}", p.0, p.1),
+            EntityId::TheMachine => write!(f, "the_machine"),
+        }
+    }
+}
+
+impl FromStr for EntityId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<EntityId, Error> {
+        let parts: Vec<&str> = s.split('/').collect();
+        let tile = || -> Result<(i32, i32), Error> {
+            match parts.as_slice() {
+                [_, x, y] => Ok((x.parse()?, y.parse()?)),
+                _ => Err(format_err!("expected a tile position in entity id {}", s)),
+            }
+        };
+        match parts[0] {
+            "game" => Ok(EntityId::Game),
+            "ghost" if parts.len() == 2 => Ok(EntityId::Ghost(parts[1].parse()?)),
+            "button" => Ok(EntityId::Button(tile()?)),
+            "door" => Ok(EntityId::Door(tile()?)),
+            "teleporter" => Ok(EntityId::Teleporter(tile()?)),
+            "bulb" => Ok(EntityId::Bulb(tile()?)),
+            "the_machine" => Ok(EntityId::TheMachine),
+            _ => Err(format_err!("unknown entity id {}", s)),
+        }
+    }
+}
+
+/// Hashes of every entity after one update, sorted by entity id.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TickHash {
+    pub tick: usize,
+    pub entities: Vec<(EntityId, u64)>,
+}
+
+/// One recorded update: the inputs it was given and the state it ended up in.
+#[derive(Clone, Debug)]
+pub struct TraceEntry {
+    pub inputs: Vec<InputEvent>,
+    pub hash: TickHash,
+}
+
+#[derive(Debug)]
+pub struct Divergence {
+    pub update: usize,
+    pub tick: usize,
+    pub entity: EntityId,
+    pub expected: Option<u64>,
+    pub actual: Option<u64>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hash = |h: Option<u64>| match h {
+            Some(h) => format!("{:016x}", h),
+            None => "missing".to_owned(),
+        };
+        write!(
+            f,
+            "desync at update {} (tick {}) in {}: expected {} got {}",
+            self.update,
+            self.tick,
+            self.entity,
+            hash(self.expected),
+            hash(self.actual)
+        )
+    }
+}
+
+impl TickHash {
+    /// Finds the first entity whose hash differs, including entities only present on one side.
+    pub fn first_difference(
+        &self,
+        actual: &TickHash,
+    ) -> Option<(EntityId, Option<u64>, Option<u64>)> {
+        let find = |entities: &[(EntityId, u64)], id: EntityId| {
+            entities.iter().find(|(e, _)| *e == id).map(|(_, h)| *h)
+        };
+        let mut ids: Vec<EntityId> = self
+            .entities
+            .iter()
+            .chain(actual.entities.iter())
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort();
+        ids.dedup();
+
+        // the tick counter lives in the game entity, but check it explicitly so a tick mismatch
+        // is reported even if the hashes happen to collide
+        if self.tick != actual.tick {
+            return Some((
+                EntityId::Game,
+                find(&self.entities, EntityId::Game),
+                find(&actual.entities, EntityId::Game),
+            ));
+        }
+        ids.into_iter().find_map(|id| {
+            let expected = find(&self.entities, id);
+            let actual = find(&actual.entities, id);
+            if expected != actual {
+                Some((id, expected, actual))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+impl TraceEntry {
+    /// Serializes to a single line: `tick;inputs;entity=hash ...`, inputs as `+Key`/`-Key`.
+    pub fn to_line(&self) -> String {
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .filter_map(|input| match input {
+                InputEvent::KeyDown(key) => Some(format!("+{:?}", key)),
+                InputEvent::KeyUp(key) => Some(format!("-{:?}", key)),
+                _ => None,
+            })
+            .collect();
+        let hashes: Vec<String> = self
+            .hash
+            .entities
+            .iter()
+            .map(|(id, hash)| format!("{}={:016x}", id, hash))
+            .collect();
+        format!(
+            "{};{};{}",
+            self.hash.tick,
+            inputs.join(" "),
+            hashes.join(" ")
+        )
+    }
+
+    pub fn from_line(line: &str) -> Result<TraceEntry, Error> {
+        let mut parts = line.trim().split(';');
+        let (tick, inputs, hashes) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(tick), Some(inputs), Some(hashes)) => (tick, inputs, hashes),
+            _ => return Err(format_err!("malformed trace line: {}", line)),
+        };
+
+        let inputs = inputs
+            .split_whitespace()
+            .map(|input| {
+                let (sign, key) = input.split_at(1);
+                let key: Key = key.parse().map_err(|e: String| format_err!(e))?;
+                match sign {
+                    "+" => Ok(InputEvent::KeyDown(key)),
+                    "-" => Ok(InputEvent::KeyUp(key)),
+                    _ => Err(format_err!("malformed input {}", input)),
+                }
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let entities = hashes
+            .split_whitespace()
+            .map(|entry| {
+                let mut split = entry.splitn(2, '=');
+                match (split.next(), split.next()) {
+                    (Some(id), Some(hash)) => Ok((id.parse()?, u64::from_str_radix(hash, 16)?)),
+                    _ => Err(format_err!("malformed hash entry {}", entry)),
+                }
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(TraceEntry {
+            inputs,
+            hash: TickHash {
+                tick: tick.parse()?,
+                entities,
+            },
+        })
+    }
+}
+
+pub fn parse_trace(text: &str) -> Result<Vec<TraceEntry>, Error> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(TraceEntry::from_line)
+        .collect()
+}
+
+/// Selected by the platform layer at startup.
+pub enum DeterminismMode {
+    Off,
+    /// Write a trace line for every update.
+    Record(Box<dyn Write>),
+    /// Re-simulate a recorded trace and report the first divergence.
+    Check(Vec<TraceEntry>),
+}
+
+pub struct TraceRecorder {
+    out: Option<Box<dyn Write>>,
+}
+
+impl TraceRecorder {
+    pub fn new(out: Option<Box<dyn Write>>) -> TraceRecorder {
+        TraceRecorder { out }
+    }
+
+    pub fn record(&mut self, inputs: &[InputEvent], hash: TickHash) {
+        if let Some(out) = self.out.as_mut() {
+            let entry = TraceEntry {
+                inputs: inputs.to_vec(),
+                hash,
+            };
+            if let Err(e) = writeln!(out, "{}", entry.to_line()).and_then(|_| out.flush()) {
+                log::error!("Could not write state trace, recording stopped: {}", e);
+                self.out = None;
+            }
+        }
+    }
+}
+
+/// Feeds the recorded inputs through `update` one entry at a time and compares the resulting
+/// state hashes. Returns the number of updates checked, or the first divergence.
+pub fn check_trace(
+    trace: &[TraceEntry],
+    mut update: impl FnMut(&[InputEvent]) -> TickHash,
+) -> Result<usize, Divergence> {
+    for (i, entry) in trace.iter().enumerate() {
+        let actual = update(&entry.inputs);
+        if let Some((entity, expected, actual_hash)) = entry.hash.first_difference(&actual) {
+            return Err(Divergence {
+                update: i,
+                tick: entry.hash.tick,
+                entity,
+                expected,
+                actual: actual_hash,
+            });
+        }
+    }
+    Ok(trace.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_trace, parse_trace, EntityId, TickHash, TraceEntry};
+    use crate::input::{InputEvent, Key};
+
+    // a stand in for the game: each key held moves one entity, every update ticks
+    fn simulate() -> impl FnMut(&[InputEvent]) -> TickHash {
+        let mut tick = 0;
+        let mut ghost = 0u64;
+        let mut door = 0u64;
+        move |inputs| {
+            for input in inputs {
+                match input {
+                    InputEvent::KeyDown(Key::W) => ghost += 1,
+                    InputEvent::KeyDown(Key::Space) => door ^= 1,
+                    _ => (),
+                }
+            }
+            tick += 1;
+            TickHash {
+                tick,
+                entities: vec![
+                    (EntityId::Game, tick as u64),
+                    (EntityId::Ghost(0), ghost),
+                    (EntityId::Door((3, -2)), door),
+                    (EntityId::TheMachine, 0xffff_ffff_ffff_ffff),
+                ],
+            }
+        }
+    }
+
+    fn record(updates: &[&[InputEvent]]) -> Vec<TraceEntry> {
+        let mut update = simulate();
+        updates
+            .iter()
+            .map(|inputs| TraceEntry {
+                inputs: inputs.to_vec(),
+                hash: update(inputs),
+            })
+            .collect()
+    }
+
+    fn trace() -> Vec<TraceEntry> {
+        record(&[
+            &[InputEvent::KeyDown(Key::W)],
+            &[],
+            &[InputEvent::KeyUp(Key::W), InputEvent::KeyDown(Key::Space)],
+            &[InputEvent::KeyDown(Key::W)],
+            &[InputEvent::KeyUp(Key::Space)],
+        ])
+    }
+
+    #[test]
+    fn trace_lines_round_trip() {
+        let entry = TraceEntry {
+            inputs: vec![
+                InputEvent::KeyDown(Key::W),
+                InputEvent::KeyUp(Key::Space),
+                InputEvent::KeyDown(Key::R),
+            ],
+            hash: TickHash {
+                tick: 12,
+                entities: vec![
+                    (EntityId::Game, 0),
+                    (EntityId::Ghost(2), 0x0123_4567_89ab_cdef),
+                    (EntityId::Button((-1, 4)), 1),
+                    (EntityId::Door((0, 0)), 2),
+                    (EntityId::Teleporter((5, -6)), 3),
+                    (EntityId::Bulb((7, 8)), u64::MAX),
+                    (EntityId::TheMachine, 4),
+                ],
+            },
+        };
+        let line = entry.to_line();
+        let parsed = TraceEntry::from_line(&line).unwrap();
+        assert_eq!(parsed.hash, entry.hash);
+        assert_eq!(
+            format!("{:?}", parsed.inputs),
+            format!("{:?}", entry.inputs)
+        );
+        assert_eq!(parsed.to_line(), line);
+
+        let text: String = trace()
+            .iter()
+            .map(|entry| format!("{}\n\n", entry.to_line()))
+            .collect();
+        let parsed = parse_trace(&text).unwrap();
+        assert_eq!(parsed.len(), 5);
+        for (parsed, entry) in parsed.iter().zip(trace().iter()) {
+            assert_eq!(parsed.to_line(), entry.to_line());
+        }
+        assert!(TraceEntry::from_line("1;+W").is_err());
+    }
+
+    #[test]
+    fn matching_trace_checks_every_update() {
+        let trace = trace();
+        assert_eq!(check_trace(&trace, simulate()).unwrap(), trace.len());
+    }
+
+    #[test]
+    fn divergence_names_the_update_tick_and_entity() {
+        let mut trace = trace();
+        trace[3].hash.entities[2].1 ^= 1;
+        let divergence = check_trace(&trace, simulate()).unwrap_err();
+        assert_eq!(divergence.update, 3);
+        assert_eq!(divergence.tick, trace[3].hash.tick);
+        assert_eq!(divergence.entity, EntityId::Door((3, -2)));
+        assert_eq!(divergence.expected, Some(0));
+        assert_eq!(divergence.actual, Some(1));
+
+        // entities missing on one side are reported too
+        let mut trace = self::trace();
+        trace[1].hash.entities.remove(1);
+        let divergence = check_trace(&trace, simulate()).unwrap_err();
+        assert_eq!(divergence.update, 1);
+        assert_eq!(divergence.entity, EntityId::Ghost(0));
+        assert_eq!(divergence.expected, None);
+    }
+}
@@ -0,0 +1,48 @@
+use std::{fmt, time::Duration};
+
+/// Frames averaged over in each report.
+const REPORT_FRAMES: usize = 300;
+
+/// Logs how long frames took, every `REPORT_FRAMES` frames. The time spent updating and drawing
+/// includes any wait for the GPU when a buffer write has to sync with draws still using it.
+/// Only native builds measure anything, the web has no clock to measure with.
+pub struct FrameTimes {
+    work: Vec<Duration>,
+    since_report: f32,
+}
+
+impl FrameTimes {
+    pub fn new() -> Self {
+        Self {
+            work: Vec::with_capacity(REPORT_FRAMES),
+            since_report: 0.,
+        }
+    }
+
+    /// `work` is the time spent updating and drawing a frame, `dt` the time since the last.
+    /// `label` names what the frames since the last report were drawn with. Returns whether
+    /// this frame was reported on.
+    pub fn frame(&mut self, label: fmt::Arguments, work: Duration, dt: f32) -> bool {
+        self.work.push(work);
+        self.since_report += dt;
+        if self.work.len() < REPORT_FRAMES {
+            return false;
+        }
+
+        let millis = |duration: Duration| duration.as_micros() as f32 / 1000.;
+        let total: Duration = self.work.iter().sum();
+        let worst = self.work.iter().max().copied().unwrap_or_default();
+        log::info!(
+            "{} frames with {}: update and draw {:.2} ms on average, {:.2} ms at worst, {:.2} ms \
+             per frame",
+            REPORT_FRAMES,
+            label,
+            millis(total) / REPORT_FRAMES as f32,
+            millis(worst),
+            self.since_report * 1000. / REPORT_FRAMES as f32
+        );
+        self.work.clear();
+        self.since_report = 0.;
+        true
+    }
+}
@@ -1,31 +1,65 @@
 use std::{
     collections::{HashMap, HashSet},
+    f32::consts::TAU,
+    fs, io,
+    path::Path,
+    rc::Rc,
     sync::Arc,
 };
 
+use anyhow::{format_err, Error};
+
 use euclid::{
-    default::{Point2D, Transform2D, Vector2D},
-    point2, vec2,
+    default::{Point2D, Size2D, Transform2D, Vector2D},
+    point2, size2, vec2,
 };
 
 use crate::{
     constants::{SCREEN_SIZE, TICK_DT, ZOOM_LEVEL},
+    determinism::{hash_of, EntityId, HashState, StateHasher, TickHash},
     gl,
-    graphics::{load_image, render_sprite, Sprite, Vertex, TEXTURE_ATLAS_SIZE},
+    graphics::{
+        animation::{Animation, Animator},
+        create_quad_index_buffer, create_sprite_program, load_sprite_atlas, render_sprite,
+        render_target_quad, sprite_vertex_shader,
+        text::{render_text, Align, Font, TextStyle},
+        SortKey, Sprite, SpriteBatch, SpriteBuffer,
+    },
+    hot_reload::{source_path, Watcher},
     input::{InputEvent, Key},
-    level::{create_level, generate_tile_buffer, DoorTile, Level, Tile, TILE_SIZE},
+    level::{create_level, generate_tile_batch, DoorTile, LayerDepth, Level, Tile, TILE_SIZE},
+    lighting::{Light, Lighting},
+    minimap::{Marker, Minimap},
     mixer::{Audio, Mixer},
-    texture_atlas::{TextureAtlas, TextureRect},
+    particles::{EmitMode, Emitter, Particles},
+    post_process::RewindEffect,
+    sprite_atlas::sprite_rect,
+    texture_atlas::AtlasRect,
 };
 
 pub struct Game {
     program: gl::Program,
-    ground_buffer: gl::VertexBuffer,
-    vertex_buffer: gl::VertexBuffer,
+    atlas: Vec<gl::Texture>,
+    index_buffer: gl::IndexBuffer,
+    ground: SpriteBatch,
+    ground_buffer: SpriteBuffer,
+    overhang: SpriteBatch,
+    overhang_buffer: SpriteBuffer,
+    sprites: SpriteBatch,
+    vertex_buffer: SpriteBuffer,
+    world_target: gl::RenderTarget,
+    target_quad: SpriteBatch,
+    target_quad_buffer: SpriteBuffer,
+    lighting: Lighting,
+    lights: Vec<Light>,
+    particles: Particles,
+    rewind_effect: RewindEffect,
     assets: Assets,
 
     tick: usize,
     rewind: bool,
+    // tick the current rewind started from, only used to fade the rewind effect
+    rewind_from: Option<usize>,
     clear_players: bool,
     paused: bool,
 
@@ -34,9 +68,11 @@ pub struct Game {
     ui_time_bar_bg: Sprite,
     ui_time_bar: Sprite,
     ui_win_screen: Sprite,
-    ui_vertex_buffer: gl::VertexBuffer,
+    ui_sprites: SpriteBatch,
+    ui_vertex_buffer: SpriteBuffer,
 
     level: Level,
+    minimap: Minimap,
     controls: Controls,
 
     players: Vec<Ghost>,
@@ -47,206 +83,126 @@ pub struct Game {
     the_machine: TheMachine,
 
     mixer: Arc<Mixer>,
+    watcher: Option<Watcher>,
 }
 
 impl Game {
     pub fn new(gl_context: &mut gl::Context, mixer: Arc<Mixer>) -> Self {
-        let vertex_shader = unsafe {
-            gl_context
-                .create_shader(gl::ShaderType::Vertex, include_str!("shaders/shader.vert"))
-                .unwrap()
-        };
-        let fragment_shader = unsafe {
-            gl_context
-                .create_shader(
-                    gl::ShaderType::Fragment,
-                    include_str!("shaders/shader.frag"),
-                )
-                .unwrap()
+        let program = unsafe {
+            create_sprite_program(
+                gl_context,
+                sprite_vertex_shader(gl_context).1,
+                include_str!("shaders/shader.frag"),
+                &[],
+            )
+            .unwrap()
         };
 
-        let mut program = unsafe {
-            gl_context
-                .create_program(&gl::ProgramDescriptor {
-                    vertex_shader: &vertex_shader,
-                    fragment_shader: &fragment_shader,
-                    uniforms: &[
-                        gl::UniformEntry {
-                            name: "u_transform",
-                            ty: gl::UniformType::Mat3,
-                        },
-                        gl::UniformEntry {
-                            name: "u_texture",
-                            ty: gl::UniformType::Texture,
-                        },
-                    ],
-                    vertex_format: gl::VertexFormat {
-                        stride: std::mem::size_of::<Vertex>(),
-                        attributes: &[
-                            gl::VertexAttribute {
-                                name: "a_pos",
-                                ty: gl::VertexAttributeType::Float,
-                                size: 2,
-                                offset: 0,
-                            },
-                            gl::VertexAttribute {
-                                name: "a_uv",
-                                ty: gl::VertexAttributeType::Float,
-                                size: 2,
-                                offset: 2 * 4,
-                            },
-                            gl::VertexAttribute {
-                                name: "a_color",
-                                ty: gl::VertexAttributeType::Float,
-                                size: 4,
-                                offset: 4 * 4,
-                            },
-                        ],
-                    },
-                })
-                .unwrap()
-        };
+        let atlas = unsafe { load_sprite_atlas(gl_context).unwrap() };
 
-        let mut texture = unsafe {
-            gl_context
-                .create_texture(
-                    gl::TextureFormat::RGBAFloat,
-                    TEXTURE_ATLAS_SIZE.width,
-                    TEXTURE_ATLAS_SIZE.height,
-                )
-                .unwrap()
-        };
-        let mut atlas = TextureAtlas::new((TEXTURE_ATLAS_SIZE.width, TEXTURE_ATLAS_SIZE.height));
-        program
-            .set_uniform(1, gl::Uniform::Texture(&texture))
-            .unwrap();
-
-        let vertex_buffer = unsafe { gl_context.create_vertex_buffer().unwrap() };
+        let index_buffer = unsafe { create_quad_index_buffer(gl_context).unwrap() };
+        let vertex_buffer =
+            unsafe { SpriteBuffer::new(gl_context, gl::BufferUsage::Stream).unwrap() };
 
-        let assets = unsafe {
-            Assets {
-                ghost: load_image(
-                    include_bytes!("../assets/player.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
-                .unwrap(),
-                ghost_shadow: load_image(
-                    include_bytes!("../assets/ghost_shadow.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
-                .unwrap(),
-                ground: load_image(
-                    include_bytes!("../assets/ground.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
-                .unwrap(),
-                walls: load_image(
-                    include_bytes!("../assets/walls.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
-                .unwrap(),
-                door_h: load_image(
-                    include_bytes!("../assets/door.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
-                .unwrap(),
-                door_v: load_image(
-                    include_bytes!("../assets/door_v.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
-                .unwrap(),
-                button: load_image(
-                    include_bytes!("../assets/button.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
-                .unwrap(),
-                teleporter: load_image(
-                    include_bytes!("../assets/teleporter.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
-                .unwrap(),
-                bulb: load_image(
-                    include_bytes!("../assets/bulb.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
-                .unwrap(),
-                bulb_shadow: load_image(
-                    include_bytes!("../assets/bulb_shadow.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
-                .unwrap(),
-                the_machine: load_image(
-                    include_bytes!("../assets/the_machine.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
+        let animation = |name: &str, json: &str| {
+            Rc::new(Animation::from_aseprite_json(json, sprite_rect(name).unwrap()).unwrap())
+        };
+        let assets = Assets {
+            ghost: animation("player", include_str!("../assets/player.json")),
+            ghost_shadow: sprite_rect("ghost_shadow").unwrap(),
+            door_h: sprite_rect("door").unwrap(),
+            door_v: sprite_rect("door_v").unwrap(),
+            button: sprite_rect("button").unwrap(),
+            teleporter: animation("teleporter", include_str!("../assets/teleporter.json")),
+            bulb: animation("bulb", include_str!("../assets/bulb.json")),
+            bulb_shadow: sprite_rect("bulb_shadow").unwrap(),
+            the_machine: animation("the_machine", include_str!("../assets/the_machine.json")),
+            the_machine_slots: sprite_rect("the_machine_slots").unwrap(),
+            ui: sprite_rect("ui").unwrap(),
+            ui_bulb: sprite_rect("ui_bulb").unwrap(),
+            ui_time_bar: sprite_rect("ui_time_bar").unwrap(),
+            ui_time_bar_bg: sprite_rect("ui_time_bar_bg").unwrap(),
+            win_screen: sprite_rect("win_screen").unwrap(),
+            font: Font::new(sprite_rect("font").unwrap(), size2(4, 6), ' '),
+            teleport_particles: Rc::new(Emitter {
+                mode: EmitMode::Burst(16),
+                lifetime: (0.3, 0.6),
+                speed: (1., 3.),
+                spread: std::f32::consts::PI,
+                radius: 0.3,
+                colors: vec![[0.6, 0.85, 1., 1.], [0.1, 0.3, 0.8, 0.]],
+                ..particle_emitter()
+            }),
+            pickup_particles: Rc::new(Emitter {
+                mode: EmitMode::Burst(10),
+                lifetime: (0.4, 0.8),
+                speed: (0.5, 1.5),
+                direction: std::f32::consts::FRAC_PI_2,
+                spread: 1.,
+                acceleration: vec2(0., -2.),
+                colors: vec![[1., 0.95, 0.7, 1.], [1., 0.7, 0.3, 0.]],
+                ..particle_emitter()
+            }),
+            machine_particles: Rc::new(Emitter {
+                mode: EmitMode::Burst(24),
+                lifetime: (0.5, 1.),
+                speed: (1.5, 3.5),
+                direction: std::f32::consts::FRAC_PI_2,
+                spread: 1.2,
+                radius: 0.5,
+                acceleration: vec2(0., -4.),
+                colors: vec![[1., 1., 0.8, 1.], [1., 0.8, 0.4, 1.], [0.8, 0.4, 0.1, 0.]],
+                ..particle_emitter()
+            }),
+            machine_sparks: Rc::new(Emitter {
+                mode: EmitMode::Continuous {
+                    rate: 12.,
+                    duration: 1.5,
+                },
+                lifetime: (0.3, 0.6),
+                speed: (0.3, 0.8),
+                direction: std::f32::consts::FRAC_PI_2,
+                spread: 0.6,
+                radius: 0.8,
+                colors: vec![[1., 0.9, 0.6, 1.], [1., 0.6, 0.2, 0.]],
+                ..particle_emitter()
+            }),
+
+            door_sound: mixer
+                .load_ogg(include_bytes!("../assets/door.ogg"))
                 .unwrap(),
-                the_machine_slots: load_image(
-                    include_bytes!("../assets/the_machine_slots.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
+            drop_sound: mixer
+                .load_ogg(include_bytes!("../assets/drop.ogg"))
                 .unwrap(),
-                ui: load_image(include_bytes!("../assets/ui.png"), &mut atlas, &mut texture)
-                    .unwrap(),
-                ui_bulb: load_image(
-                    include_bytes!("../assets/ui_bulb.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
+            pickup_sound: mixer
+                .load_ogg(include_bytes!("../assets/pickup.ogg"))
                 .unwrap(),
-                ui_time_bar: load_image(
-                    include_bytes!("../assets/ui_time_bar.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
+            rewind_sound: mixer
+                .load_ogg(include_bytes!("../assets/rewind.ogg"))
                 .unwrap(),
-                ui_time_bar_bg: load_image(
-                    include_bytes!("../assets/ui_time_bar_bg.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
+            start_sound: mixer
+                .load_ogg(include_bytes!("../assets/start.ogg"))
                 .unwrap(),
-                win_screen: load_image(
-                    include_bytes!("../assets/win_screen.png"),
-                    &mut atlas,
-                    &mut texture,
-                )
+            teleport_sound: mixer
+                .load_ogg(include_bytes!("../assets/teleport.ogg"))
                 .unwrap(),
-
-                door_sound: mixer
-                    .load_ogg(include_bytes!("../assets/door.ogg"))
-                    .unwrap(),
-                drop_sound: mixer
-                    .load_ogg(include_bytes!("../assets/drop.ogg"))
-                    .unwrap(),
-                pickup_sound: mixer
-                    .load_ogg(include_bytes!("../assets/pickup.ogg"))
-                    .unwrap(),
-                rewind_sound: mixer
-                    .load_ogg(include_bytes!("../assets/rewind.ogg"))
-                    .unwrap(),
-                start_sound: mixer
-                    .load_ogg(include_bytes!("../assets/start.ogg"))
-                    .unwrap(),
-                teleport_sound: mixer
-                    .load_ogg(include_bytes!("../assets/teleport.ogg"))
-                    .unwrap(),
-            }
         };
 
         let level = create_level();
-        let ground_buffer = generate_tile_buffer(&level, assets.ground, assets.walls, gl_context);
+        let minimap = unsafe { Minimap::new(gl_context, &level).unwrap() };
+        let ground = generate_tile_batch(&level, &[LayerDepth::Ground, LayerDepth::Decoration]);
+        let ground_buffer = unsafe {
+            let mut ground_buffer = SpriteBuffer::new(gl_context, gl::BufferUsage::Static).unwrap();
+            ground.upload(&mut ground_buffer);
+            ground_buffer
+        };
+        let overhang = generate_tile_batch(&level, &[LayerDepth::Overhang]);
+        let overhang_buffer = unsafe {
+            let mut overhang_buffer =
+                SpriteBuffer::new(gl_context, gl::BufferUsage::Static).unwrap();
+            overhang.upload(&mut overhang_buffer);
+            overhang_buffer
+        };
 
         let mut buttons = HashMap::new();
         for (position, button_tile) in level.buttons.iter() {
@@ -275,7 +231,7 @@ impl Game {
             teleporters.insert(
                 *position,
                 Teleporter::new(
-                    assets.teleporter,
+                    &assets.teleporter,
                     *position,
                     teleporter_tile.connection.expect("unconnected teleporter"),
                 ),
@@ -285,22 +241,22 @@ impl Game {
         let mut bulbs = Vec::new();
         for position in level.bulbs.iter() {
             bulbs.push(Bulb::new(
-                assets.bulb,
+                &assets.bulb,
                 assets.bulb_shadow,
                 position.to_f32() + vec2(0.5, 0.5),
             ));
         }
 
         let players = vec![Ghost::new(
-            assets.ghost,
+            &assets.ghost,
             assets.ghost_shadow,
             level.player_start,
         )];
 
         let the_machine = TheMachine::new(
-            assets.the_machine,
+            &assets.the_machine,
             assets.the_machine_slots,
-            assets.bulb,
+            &assets.bulb,
             level.the_machine.to_f32(),
         );
 
@@ -310,16 +266,46 @@ impl Game {
         let ui_time_bar_bg = Sprite::new(assets.ui_time_bar_bg, 1, point2(0., 0.));
         let ui_win_screen = Sprite::new(assets.win_screen, 1, point2(0., 0.));
 
-        let ui_vertex_buffer = unsafe { gl_context.create_vertex_buffer() }.unwrap();
+        let ui_vertex_buffer =
+            unsafe { SpriteBuffer::new(gl_context, gl::BufferUsage::Stream) }.unwrap();
+
+        let world_target = unsafe {
+            let size = world_target_size();
+            gl_context
+                .create_render_target(size.width, size.height)
+                .unwrap()
+        };
+        let target_quad_buffer =
+            unsafe { SpriteBuffer::new(gl_context, gl::BufferUsage::Stream) }.unwrap();
+        let lighting = unsafe {
+            Lighting::new(gl_context, world_target.width(), world_target.height()).unwrap()
+        };
+        let rewind_effect = unsafe {
+            RewindEffect::new(gl_context, SCREEN_SIZE.width, SCREEN_SIZE.height).unwrap()
+        };
 
         Self {
             program,
+            atlas,
+            index_buffer,
+            ground,
             ground_buffer,
+            overhang,
+            overhang_buffer,
+            sprites: SpriteBatch::new(),
             vertex_buffer,
+            world_target,
+            target_quad: SpriteBatch::new(),
+            target_quad_buffer,
+            lighting,
+            lights: Vec::new(),
+            particles: Particles::new(),
+            rewind_effect,
             assets,
 
             tick: 0,
             rewind: false,
+            rewind_from: None,
             clear_players: false,
             paused: true,
 
@@ -328,9 +314,11 @@ impl Game {
             ui_time_bar,
             ui_time_bar_bg,
             ui_win_screen,
+            ui_sprites: SpriteBatch::new(),
             ui_vertex_buffer,
 
             level,
+            minimap,
             controls: Controls::default(),
 
             players,
@@ -341,10 +329,22 @@ impl Game {
             the_machine,
 
             mixer,
+            // tests draw what was compiled in
+            watcher: if cfg!(test) {
+                None
+            } else {
+                Watcher::new(&["assets", "src/shaders"])
+            },
         }
     }
 
     pub fn update(&mut self, inputs: &[InputEvent]) {
+        // the minimap can be toggled on the win screen too, it shows once the screen is gone
+        for input in inputs {
+            if let InputEvent::KeyDown(Key::M) = input {
+                self.minimap.toggle();
+            }
+        }
         if self.the_machine.slots_occupied == 6 && self.tick == 0 {
             return;
         }
@@ -377,8 +377,9 @@ impl Game {
                 }
                 InputEvent::KeyDown(Key::Escape) => {
                     self.rewind = false;
+                    self.rewind_from = None;
                     self.players = vec![Ghost::new(
-                        self.assets.ghost,
+                        &self.assets.ghost,
                         self.assets.ghost_shadow,
                         self.level.player_start,
                     )];
@@ -405,14 +406,16 @@ impl Game {
 
         // only current player gets new inputs
         if self.rewind {
+            self.rewind_from.get_or_insert(self.tick);
             self.tick = self.tick.saturating_sub(5);
 
             if self.tick == 0 {
                 self.rewind = false;
+                self.rewind_from = None;
 
                 if self.clear_players {
                     self.players = vec![Ghost::new(
-                        self.assets.ghost,
+                        &self.assets.ghost,
                         self.assets.ghost_shadow,
                         self.level.player_start,
                     )];
@@ -427,7 +430,7 @@ impl Game {
                         .unwrap()
                         .set_color([1.0, 1.0, 1.0, 0.5]);
                     self.players.push(Ghost::new(
-                        self.assets.ghost,
+                        &self.assets.ghost,
                         self.assets.ghost_shadow,
                         self.level.player_start,
                     ));
@@ -490,11 +493,22 @@ impl Game {
                 &self.assets,
             );
         }
+        for teleporter in self.teleporters.values() {
+            if teleporter.just_activated() {
+                for tile in &[teleporter.position, teleporter.destination] {
+                    self.particles.emit(
+                        &self.assets.teleport_particles,
+                        tile.to_f32() + vec2(0.5, 0.5),
+                    );
+                }
+            }
+        }
         for teleporter in self.teleporters.values_mut() {
             teleporter.update();
         }
 
         for bulb in self.bulbs.iter_mut() {
+            let was_picked_up = bulb.picked_up.is_some();
             bulb.update(
                 self.tick,
                 self.paused,
@@ -504,7 +518,16 @@ impl Game {
                 &self.mixer,
                 &self.assets,
             );
+            if !was_picked_up && bulb.picked_up.is_some() {
+                self.particles
+                    .emit(&self.assets.pickup_particles, bulb.position(self.tick));
+            }
             if bulb.inserted {
+                let machine_top = self.the_machine.position + vec2(0., 1.);
+                self.particles
+                    .emit(&self.assets.machine_particles, machine_top);
+                self.particles
+                    .emit(&self.assets.machine_sparks, machine_top);
                 self.the_machine.add_bulb();
                 self.rewind = true;
                 self.mixer.play(&self.assets.drop_sound, 0.125, false);
@@ -515,70 +538,174 @@ impl Game {
         self.bulbs.retain(|bulb| !bulb.inserted);
 
         self.the_machine.update();
+        self.particles.update(TICK_DT);
+    }
+
+    /// Adds `count` ghosts walking in small squares around the start, and starts the loop so
+    /// they move without any input. Used to measure frame times with many sprites.
+    pub fn add_bench_ghosts(&mut self, count: usize) {
+        for i in 0..count {
+            let mut ghost = Ghost::new(
+                &self.assets.ghost,
+                self.assets.ghost_shadow,
+                self.level.player_start,
+            );
+            ghost.set_color([1.0, 1.0, 1.0, 0.5]);
+            for tick in 0..LOOP_TICKS {
+                let side = (tick / 20 + i) % 4;
+                ghost.push_controls(Controls {
+                    up: side == 0,
+                    right: side == 1,
+                    down: side == 2,
+                    left: side == 3,
+                });
+            }
+            // the player stays the last ghost
+            let player = self.players.len() - 1;
+            self.players.insert(player, ghost);
+        }
+        self.paused = false;
+    }
+
+    /// Recreates the buffers sprites are written to every frame with `usage`, so benchmarks can
+    /// compare how each usage performs.
+    pub unsafe fn set_sprite_buffer_usage(
+        &mut self,
+        context: &mut gl::Context,
+        usage: gl::BufferUsage,
+    ) -> Result<(), Error> {
+        self.vertex_buffer = SpriteBuffer::new(context, usage)?;
+        self.ui_vertex_buffer = SpriteBuffer::new(context, usage)?;
+        Ok(())
+    }
+
+    /// Hashes all simulation state after the last update, used to detect desyncs between runs.
+    pub fn state_hash(&self) -> TickHash {
+        let mut game = StateHasher::new();
+        game.write_usize(self.tick);
+        game.write_bool(self.rewind);
+        game.write_bool(self.clear_players);
+        game.write_bool(self.paused);
+        self.controls.hash_state(&mut game);
+
+        let mut entities = vec![(EntityId::Game, game.finish())];
+        for (i, player) in self.players.iter().enumerate() {
+            entities.push((EntityId::Ghost(i), hash_of(player)));
+        }
+        for (position, button) in self.buttons.iter() {
+            entities.push((EntityId::Button(position.to_tuple()), hash_of(button)));
+        }
+        for (position, door) in self.doors.iter() {
+            entities.push((EntityId::Door(position.to_tuple()), hash_of(door)));
+        }
+        for (position, teleporter) in self.teleporters.iter() {
+            entities.push((
+                EntityId::Teleporter(position.to_tuple()),
+                hash_of(teleporter),
+            ));
+        }
+        for bulb in self.bulbs.iter() {
+            entities.push((EntityId::Bulb(bulb.spawn_tile().to_tuple()), hash_of(bulb)));
+        }
+        entities.push((EntityId::TheMachine, hash_of(&self.the_machine)));
+
+        // hash maps iterate in a random order
+        entities.sort_by_key(|(id, _)| *id);
+
+        TickHash {
+            tick: self.tick,
+            entities,
+        }
+    }
+
+    // 0 to 1, fading in as the rewind starts and out as it reaches the start of the loop
+    fn rewind_effect_intensity(&self) -> f32 {
+        match self.rewind_from {
+            Some(from) if self.rewind && from > 0 => {
+                let progress = 1. - self.tick as f32 / from as f32;
+                (progress / 0.15).min((1. - progress) / 0.25).clamp(0., 1.)
+            }
+            _ => 0.,
+        }
     }
 
     pub fn draw(&mut self, context: &mut gl::Context) {
-        let mut vertices = Vec::new();
+        unsafe { self.hot_reload(context) };
+
+        let vertices = &mut self.sprites;
+        vertices.clear();
 
         for button in self.buttons.values() {
-            button.draw(&mut vertices);
+            button.draw(vertices);
         }
         for door in self.doors.values() {
-            door.draw(&mut vertices);
+            door.draw(vertices);
         }
         for teleporter in self.teleporters.values() {
-            teleporter.draw(&mut vertices);
+            teleporter.draw(vertices);
         }
-        self.the_machine.draw(&mut vertices);
+        self.the_machine.draw(vertices);
 
-        // draw all shadows first
         for player in self.players.iter() {
-            player.draw_shadow(self.tick, &mut vertices);
-        }
-
-        // then players
-        for player in self.players.iter() {
-            player.draw(self.tick, &mut vertices);
+            player.draw_shadow(self.tick, vertices);
+            player.draw(self.tick, vertices);
         }
 
         for bulb in self.bulbs.iter() {
-            bulb.draw(self.tick, &mut vertices);
+            bulb.draw(self.tick, vertices);
         }
 
+        vertices.set_sort_key(SortKey::new(EFFECTS_LAYER, 0.));
+        self.particles.draw(vertices);
+        vertices.sort();
+
+        let tick = self.tick;
+        self.lights.clear();
+        self.lights
+            .extend(self.bulbs.iter().filter_map(|bulb| bulb.light(tick)));
+        self.lights.extend(
+            self.teleporters
+                .values()
+                .map(|teleporter| teleporter.light()),
+        );
+        self.lights.push(self.the_machine.light());
+
         // ui stuff
-        let mut ui_vertices = Vec::new();
+        let ui_vertices = &mut self.ui_sprites;
+        ui_vertices.clear();
 
-        if self.the_machine.slots_occupied == 6 && self.tick == 0 {
-            render_sprite(&self.ui_win_screen, 0, point2(0., 0.), &mut ui_vertices);
+        let won = self.the_machine.slots_occupied == 6 && self.tick == 0;
+        if won {
+            render_sprite(&self.ui_win_screen, 0, point2(0., 0.), ui_vertices);
         } else {
-            render_sprite(&self.ui_time_bar_bg, 0, point2(244., 67.), &mut ui_vertices);
+            render_sprite(&self.ui_time_bar_bg, 0, point2(244., 67.), ui_vertices);
 
             self.ui_time_bar
                 .set_transform(Transform2D::create_translation(
                     0.,
                     self.tick as f32 / LOOP_TICKS as f32 * -80.,
                 ));
-            render_sprite(&self.ui_time_bar, 0, point2(244., 67.), &mut ui_vertices);
+            render_sprite(&self.ui_time_bar, 0, point2(244., 67.), ui_vertices);
 
-            render_sprite(&self.ui, 0, point2(0., 0.), &mut ui_vertices);
+            render_sprite(&self.ui, 0, point2(0., 0.), ui_vertices);
             if self.the_machine.slots_occupied >= 1 {
-                render_sprite(&self.ui_bulb, 0, point2(207., 176.), &mut ui_vertices);
+                render_sprite(&self.ui_bulb, 0, point2(207., 176.), ui_vertices);
             }
             if self.the_machine.slots_occupied >= 2 {
-                render_sprite(&self.ui_bulb, 0, point2(207. + 20., 176.), &mut ui_vertices);
+                render_sprite(&self.ui_bulb, 0, point2(207. + 20., 176.), ui_vertices);
             }
             if self.the_machine.slots_occupied >= 3 {
-                render_sprite(&self.ui_bulb, 0, point2(207. + 39., 176.), &mut ui_vertices);
+                render_sprite(&self.ui_bulb, 0, point2(207. + 39., 176.), ui_vertices);
             }
             if self.the_machine.slots_occupied >= 4 {
-                render_sprite(&self.ui_bulb, 0, point2(207., 176. - 22.), &mut ui_vertices);
+                render_sprite(&self.ui_bulb, 0, point2(207., 176. - 22.), ui_vertices);
             }
             if self.the_machine.slots_occupied >= 5 {
                 render_sprite(
                     &self.ui_bulb,
                     0,
                     point2(207. + 20., 176. - 22.),
-                    &mut ui_vertices,
+                    ui_vertices,
                 );
             }
             if self.the_machine.slots_occupied >= 6 {
@@ -586,37 +713,63 @@ impl Game {
                     &self.ui_bulb,
                     0,
                     point2(207. + 39., 176. - 22.),
-                    &mut ui_vertices,
+                    ui_vertices,
                 );
             }
+
+            let font = &self.assets.font;
+            let text = TextStyle {
+                shadow: Some([0., 0., 0., 0.75]),
+                ..TextStyle::default()
+            };
+            render_text(font, &self.level.name, point2(6., 194.), &text, ui_vertices);
+            let ghosts = format!("Ghosts {}", self.players.len());
+            render_text(font, &ghosts, point2(6., 187.), &text, ui_vertices);
+
+            let seconds_left = (LOOP_TICKS - self.tick) as f32 * TICK_DT;
+            let right = TextStyle {
+                align: Align::Right,
+                ..text
+            };
+            let time = format!("{:.1}", seconds_left);
+            render_text(font, &time, point2(192., 194.), &right, ui_vertices);
+
+            if self.paused && !self.rewind && self.tick == 0 {
+                let hint = TextStyle {
+                    align: Align::Center,
+                    max_width: Some(100.),
+                    ..text
+                };
+                let hint_text = "Move with WASD to start the loop";
+                // the last line sits at the same height however many lines the hint wraps to
+                let top = 18. + font.measure(hint_text, &hint).height;
+                render_text(font, hint_text, point2(99., top), &hint, ui_vertices);
+            }
         }
 
+        let atlas: Vec<&gl::Texture> = self.atlas.iter().collect();
         unsafe {
-            self.vertex_buffer.write(&vertices);
-            self.ui_vertex_buffer.write(&ui_vertices);
-
-            context.clear([75. / 255., 58. / 255., 58. / 255., 1.]);
-
-            let mut camera_pos = self.players.last().unwrap().position(self.tick);
-            // Fixes tile gaps but causes stuttery camera RIP
-            camera_pos.x = (camera_pos.x * ZOOM_LEVEL * TILE_SIZE as f32).floor()
-                / ZOOM_LEVEL
-                / TILE_SIZE as f32;
-            camera_pos.y = (camera_pos.y * ZOOM_LEVEL * TILE_SIZE as f32).floor()
-                / ZOOM_LEVEL
-                / TILE_SIZE as f32;
-            let transform = Transform2D::create_translation(-camera_pos.x - 3., -camera_pos.y - 0.)
-                .post_scale(
-                    1.0 / SCREEN_SIZE.width as f32,
-                    1.0 / SCREEN_SIZE.height as f32,
-                )
-                .post_scale(ZOOM_LEVEL, ZOOM_LEVEL)
-                .post_scale(TILE_SIZE as f32, TILE_SIZE as f32)
-                .post_scale(2., 2.);
+            self.sprites.upload(&mut self.vertex_buffer);
+            self.ui_sprites.upload(&mut self.ui_vertex_buffer);
+
+            // The world is drawn one texel per pixel of art, with the camera snapped to whole
+            // pixels so tiles meet exactly. The remaining fraction of a pixel is applied when
+            // the target is scaled up to the screen, the margin fills in the edge this uncovers.
+            let camera_pos = self.players.last().unwrap().position(self.tick) + vec2(3., 0.);
+            let (snapped, offset) = snap_camera(camera_pos);
+
+            let target_width = self.world_target.width() as f32;
+            let target_height = self.world_target.height() as f32;
+            let transform = Transform2D::create_translation(
+                -snapped.x / TILE_SIZE as f32,
+                -snapped.y / TILE_SIZE as f32,
+            )
+            .post_scale(TILE_SIZE as f32, TILE_SIZE as f32)
+            .post_scale(2. / target_width, 2. / target_height);
 
             self.program
                 .set_uniform(
-                    0,
+                    "u_transform",
                     gl::Uniform::Mat3([
                         [transform.m11, transform.m12, 0.0],
                         [transform.m21, transform.m22, 0.0],
@@ -625,8 +778,106 @@ impl Game {
                 )
                 .unwrap();
 
-            self.program.render_vertices(&self.ground_buffer).unwrap();
-            self.program.render_vertices(&self.vertex_buffer).unwrap();
+            context.bind_render_target(Some(&self.world_target));
+            context.clear([75. / 255., 58. / 255., 58. / 255., 1.]);
+            self.ground
+                .draw(
+                    &mut self.program,
+                    &atlas,
+                    &self.ground_buffer,
+                    &self.index_buffer,
+                    &gl::DrawState::default(),
+                )
+                .unwrap();
+            self.sprites
+                .draw(
+                    &mut self.program,
+                    &atlas,
+                    &self.vertex_buffer,
+                    &self.index_buffer,
+                    &gl::DrawState::default(),
+                )
+                .unwrap();
+            self.overhang
+                .draw(
+                    &mut self.program,
+                    &atlas,
+                    &self.overhang_buffer,
+                    &self.index_buffer,
+                    &gl::DrawState::default(),
+                )
+                .unwrap();
+            if let Some(ambient) = self.level.ambient_light {
+                self.lighting
+                    .draw(
+                        context,
+                        ambient,
+                        &self.lights,
+                        &transform,
+                        &self.index_buffer,
+                    )
+                    .unwrap();
+
+                self.target_quad.clear();
+                render_target_quad(point2(-1., -1.), point2(1., 1.), &mut self.target_quad);
+                self.target_quad.upload(&mut self.target_quad_buffer);
+                self.program
+                    .set_uniform(
+                        "u_transform",
+                        gl::Uniform::Mat3([[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]]),
+                    )
+                    .unwrap();
+
+                context.bind_render_target(Some(&self.world_target));
+                self.target_quad
+                    .draw(
+                        &mut self.program,
+                        &[self.lighting.light_map()],
+                        &self.target_quad_buffer,
+                        &self.index_buffer,
+                        &gl::DrawState {
+                            blend_mode: gl::BlendMode::Multiply,
+                            ..Default::default()
+                        },
+                    )
+                    .unwrap();
+            }
+            let rewind_effect = self.rewind_effect_intensity();
+            if rewind_effect > 0. {
+                context.bind_render_target(Some(self.rewind_effect.frame_target()));
+                context.clear([0., 0., 0., 1.]);
+            } else {
+                context.bind_render_target(None);
+            }
+
+            let (min, max) = world_target_quad(size2(target_width, target_height), offset);
+            self.target_quad.clear();
+            render_target_quad(min, max, &mut self.target_quad);
+            self.target_quad.upload(&mut self.target_quad_buffer);
+
+            let screen_transform = Transform2D::create_scale(
+                2. * ZOOM_LEVEL / SCREEN_SIZE.width as f32,
+                2. * ZOOM_LEVEL / SCREEN_SIZE.height as f32,
+            );
+            self.program
+                .set_uniform(
+                    "u_transform",
+                    gl::Uniform::Mat3([
+                        [screen_transform.m11, screen_transform.m12, 0.0],
+                        [screen_transform.m21, screen_transform.m22, 0.0],
+                        [screen_transform.m31, screen_transform.m32, 1.0],
+                    ]),
+                )
+                .unwrap();
+            self.target_quad
+                .draw(
+                    &mut self.program,
+                    &[self.world_target.texture()],
+                    &self.target_quad_buffer,
+                    &self.index_buffer,
+                    &gl::DrawState::default(),
+                )
+                .unwrap();
 
             let ui_transform = Transform2D::create_scale(
                 1.0 / SCREEN_SIZE.width as f32,
@@ -637,7 +888,7 @@ impl Game {
             .post_translate(vec2(-1., -1.));
             self.program
                 .set_uniform(
-                    0,
+                    "u_transform",
                     gl::Uniform::Mat3([
                         [ui_transform.m11, ui_transform.m12, 0.0],
                         [ui_transform.m21, ui_transform.m22, 0.0],
@@ -645,31 +896,187 @@ impl Game {
                     ]),
                 )
                 .unwrap();
-            self.program
-                .render_vertices(&self.ui_vertex_buffer)
+            self.ui_sprites
+                .draw(
+                    &mut self.program,
+                    &atlas,
+                    &self.ui_vertex_buffer,
+                    &self.index_buffer,
+                    &gl::DrawState::default(),
+                )
                 .unwrap();
+
+            if self.minimap.visible() && !won {
+                self.draw_minimap();
+            }
+
+            if rewind_effect > 0. {
+                self.rewind_effect
+                    .draw(
+                        context,
+                        rewind_effect,
+                        self.tick as f32 * TICK_DT,
+                        &self.index_buffer,
+                    )
+                    .unwrap();
+            }
         }
     }
+
+    // in the bottom right corner of the world view, at two screen pixels per minimap pixel
+    unsafe fn draw_minimap(&mut self) {
+        let minimap = &mut self.minimap;
+        minimap.begin();
+        for (position, door) in self.doors.iter() {
+            minimap.mark(position.to_f32(), Marker::Door { open: door.open });
+        }
+        for position in self.teleporters.keys() {
+            minimap.mark(position.to_f32(), Marker::Teleporter);
+        }
+        for bulb in self.bulbs.iter() {
+            minimap.mark(bulb.position(self.tick), Marker::Bulb);
+        }
+        minimap.mark(self.the_machine.position, Marker::TheMachine);
+        let player = self.players.len() - 1;
+        for (i, ghost) in self.players.iter().enumerate() {
+            let marker = if i == player {
+                Marker::Player
+            } else {
+                Marker::Ghost
+            };
+            minimap.mark(ghost.position(self.tick), marker);
+        }
+        minimap.upload();
+
+        let (width, height) = minimap.size();
+        let right = (SCREEN_SIZE.width - SIDE_PANEL_WIDTH) as f32 - MINIMAP_MARGIN;
+        let max = point2(right, MINIMAP_MARGIN + height as f32 * 2.);
+        let min = point2(max.x - width as f32 * 2., MINIMAP_MARGIN);
+        let transform = Transform2D::create_scale(
+            2. / SCREEN_SIZE.width as f32,
+            2. / SCREEN_SIZE.height as f32,
+        )
+        .post_translate(vec2(-1., -1.));
+        self.program
+            .set_uniform(
+                "u_transform",
+                gl::Uniform::Mat3([
+                    [transform.m11, transform.m12, 0.0],
+                    [transform.m21, transform.m22, 0.0],
+                    [transform.m31, transform.m32, 1.0],
+                ]),
+            )
+            .unwrap();
+        minimap
+            .draw(min, max, &mut self.program, &self.index_buffer)
+            .unwrap();
+    }
+
+    // debug builds reload what changes on disk, see `Watcher`
+    unsafe fn hot_reload(&mut self, context: &mut gl::Context) {
+        let changed = match &mut self.watcher {
+            Some(watcher) => watcher.changed(),
+            None => return,
+        };
+        for path in changed {
+            let name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+            let result = match path.extension().and_then(|e| e.to_str()) {
+                Some("vert") | Some("frag") => self.reload_shaders(context, name, |file| {
+                    fs::read_to_string(source_path("src/shaders").join(file))
+                }),
+                Some("png") => self.reload_sprite(name, &path),
+                Some("ogg") => self.reload_sound(name, &path),
+                _ => continue,
+            };
+            match result {
+                Ok(()) => log::info!("Reloaded {}", path.display()),
+                Err(e) => log::error!("Could not reload {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    // programs are only replaced once the new sources compile, `source` reads a file of
+    // src/shaders
+    unsafe fn reload_shaders(
+        &mut self,
+        context: &mut gl::Context,
+        name: &str,
+        source: impl Fn(&str) -> io::Result<String>,
+    ) -> Result<(), Error> {
+        let sprite_vertex = source(sprite_vertex_shader(context).0)?;
+        match name {
+            "shader" | "sprite_instanced" => {
+                self.program =
+                    create_sprite_program(context, &sprite_vertex, &source("shader.frag")?, &[])?;
+                self.rewind_effect
+                    .reload_shaders(context, &sprite_vertex, &source("rewind.frag")?)
+            }
+            "rewind" => {
+                self.rewind_effect
+                    .reload_shaders(context, &sprite_vertex, &source("rewind.frag")?)
+            }
+            "light" => self.lighting.reload_shaders(
+                context,
+                &source("light.vert")?,
+                &source("light.frag")?,
+            ),
+            _ => Err(format_err!("no program uses {}", name)),
+        }
+    }
+
+    // writes over the sprite's rect in the atlas, repacking needs a rebuild
+    unsafe fn reload_sprite(&mut self, name: &str, path: &Path) -> Result<(), Error> {
+        let image = image::open(path)?.to_rgba();
+        let AtlasRect { page, rect } = sprite_rect(name)?;
+        let size = (rect[2] - rect[0], rect[3] - rect[1]);
+        if image.dimensions() != size {
+            return Err(format_err!(
+                "size changed from {:?} to {:?}, rebuild to pack it again",
+                size,
+                image.dimensions()
+            ));
+        }
+        self.atlas[page].write(rect[0], rect[1], size.0, size.1, &image.into_raw());
+        Ok(())
+    }
+
+    fn reload_sound(&mut self, name: &str, path: &Path) -> Result<(), Error> {
+        let audio = self.mixer.load_ogg(&fs::read(path)?)?;
+        let sound = match name {
+            "door" => &mut self.assets.door_sound,
+            "drop" => &mut self.assets.drop_sound,
+            "pickup" => &mut self.assets.pickup_sound,
+            "rewind" => &mut self.assets.rewind_sound,
+            "start" => &mut self.assets.start_sound,
+            "teleport" => &mut self.assets.teleport_sound,
+            _ => return Err(format_err!("no sound named {}", name)),
+        };
+        *sound = audio;
+        Ok(())
+    }
 }
 
 struct Assets {
-    ghost: TextureRect,
-    ghost_shadow: TextureRect,
-    ground: TextureRect,
-    walls: TextureRect,
-    door_h: TextureRect,
-    door_v: TextureRect,
-    button: TextureRect,
-    teleporter: TextureRect,
-    bulb: TextureRect,
-    bulb_shadow: TextureRect,
-    the_machine: TextureRect,
-    the_machine_slots: TextureRect,
-    ui: TextureRect,
-    ui_bulb: TextureRect,
-    ui_time_bar: TextureRect,
-    ui_time_bar_bg: TextureRect,
-    win_screen: TextureRect,
+    ghost: Rc<Animation>,
+    ghost_shadow: AtlasRect,
+    door_h: AtlasRect,
+    door_v: AtlasRect,
+    button: AtlasRect,
+    teleporter: Rc<Animation>,
+    bulb: Rc<Animation>,
+    bulb_shadow: AtlasRect,
+    the_machine: Rc<Animation>,
+    the_machine_slots: AtlasRect,
+    ui: AtlasRect,
+    ui_bulb: AtlasRect,
+    ui_time_bar: AtlasRect,
+    ui_time_bar_bg: AtlasRect,
+    win_screen: AtlasRect,
+    font: Font,
+    teleport_particles: Rc<Emitter>,
+    pickup_particles: Rc<Emitter>,
+    machine_particles: Rc<Emitter>,
+    machine_sparks: Rc<Emitter>,
 
     door_sound: Audio,
     drop_sound: Audio,
@@ -687,17 +1094,26 @@ struct Controls {
     right: bool,
 }
 
+impl HashState for Controls {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_bool(self.up);
+        hasher.write_bool(self.left);
+        hasher.write_bool(self.down);
+        hasher.write_bool(self.right);
+    }
+}
+
 struct Ghost {
     sprite: Sprite,
     shadow: Sprite,
     controls: Vec<Controls>,
     positions: Vec<Point2D<f32>>,
-    animation_timer: f32,
+    animator: Animator,
 }
 
 impl Ghost {
-    pub fn new(image: TextureRect, shadow: TextureRect, position: Point2D<f32>) -> Self {
-        let mut sprite = Sprite::new(image, GHOST_ANIMATION_FRAMES, point2(6., -4.0));
+    pub fn new(animation: &Rc<Animation>, shadow: AtlasRect, position: Point2D<f32>) -> Self {
+        let mut sprite = animation.sprite(point2(6., -4.0));
         let mut shadow = Sprite::new(shadow, 1, point2(6., 3.));
 
         let transform = Transform2D::create_scale(1. / TILE_SIZE as f32, 1. / TILE_SIZE as f32);
@@ -709,7 +1125,7 @@ impl Ghost {
             shadow,
             controls: Vec::new(),
             positions: vec![position],
-            animation_timer: 0.,
+            animator: Animator::new(animation.clone(), "float").unwrap(),
         }
     }
 
@@ -719,7 +1135,7 @@ impl Ghost {
 
     pub fn reset(&mut self, position: Point2D<f32>) {
         self.positions = vec![position];
-        self.animation_timer = 0.;
+        self.animator.restart();
     }
 
     pub fn push_controls(&mut self, controls: Controls) {
@@ -779,31 +1195,25 @@ impl Ghost {
             }
         }
 
-        self.animation_timer = (self.animation_timer + TICK_DT) % GHOST_ANIMATION_TIME;
+        self.animator.update(TICK_DT);
     }
 
-    pub fn draw_shadow(&self, tick: usize, out: &mut Vec<Vertex>) {
+    pub fn draw_shadow(&self, tick: usize, out: &mut SpriteBatch) {
         let position = *self
             .positions
             .get(tick + 1)
             .unwrap_or(self.positions.last().expect("positions vec is empty"));
+        out.set_sort_key(SortKey::new(FLOOR_LAYER, position.y));
         render_sprite(&self.shadow, 0, position, out);
     }
 
-    pub fn draw(&self, tick: usize, out: &mut Vec<Vertex>) {
-        let frame = (self.animation_timer / GHOST_ANIMATION_TIME * GHOST_ANIMATION_FRAMES as f32)
-            .floor() as usize;
-        let mut position = *self
+    pub fn draw(&self, tick: usize, out: &mut SpriteBatch) {
+        let frame = self.animator.frame();
+        let position = *self
             .positions
             .get(tick + 1)
             .unwrap_or(self.positions.last().expect("positions vec is empty"));
-
-        // fixes stuttery player caused by doing this same thing to the camera
-        // give me my programming diploma please
-        position.x =
-            (position.x * ZOOM_LEVEL * TILE_SIZE as f32).floor() / ZOOM_LEVEL / TILE_SIZE as f32;
-        position.y =
-            (position.y * ZOOM_LEVEL * TILE_SIZE as f32).floor() / ZOOM_LEVEL / TILE_SIZE as f32;
+        out.set_sort_key(SortKey::new(ENTITY_LAYER, position.y));
         render_sprite(&self.sprite, frame, position, out);
     }
 
@@ -812,6 +1222,20 @@ impl Ghost {
     }
 }
 
+impl HashState for Ghost {
+    // entries are only ever pushed, changed while they are the last one, or all dropped by
+    // `reset`, so a difference in an earlier entry was already hashed on the tick it was last
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_usize(self.controls.len());
+        if let Some(controls) = self.controls.last() {
+            controls.hash_state(hasher);
+        }
+        hasher.write_usize(self.positions.len());
+        hasher.write_point(*self.positions.last().expect("positions vec is empty"));
+        self.animator.hash_state(hasher);
+    }
+}
+
 struct Button {
     sprite: Sprite,
     position: Point2D<i32>,
@@ -820,7 +1244,7 @@ struct Button {
 }
 
 impl Button {
-    pub fn new(image: TextureRect, position: Point2D<i32>, connections: Vec<Point2D<i32>>) -> Self {
+    pub fn new(image: AtlasRect, position: Point2D<i32>, connections: Vec<Point2D<i32>>) -> Self {
         let mut sprite = Sprite::new(image, 2, point2(0., 0.));
         let transform = Transform2D::create_scale(1. / TILE_SIZE as f32, 1. / TILE_SIZE as f32);
         sprite.set_transform(transform);
@@ -879,7 +1303,8 @@ impl Button {
         }
     }
 
-    pub fn draw(&self, out: &mut Vec<Vertex>) {
+    pub fn draw(&self, out: &mut SpriteBatch) {
+        out.set_sort_key(SortKey::new(FLOOR_LAYER, self.position.y as f32));
         render_sprite(
             &self.sprite,
             if self.active { 1 } else { 0 },
@@ -889,6 +1314,13 @@ impl Button {
     }
 }
 
+impl HashState for Button {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_tile(self.position);
+        hasher.write_bool(self.active);
+    }
+}
+
 struct Door {
     sprite: Sprite,
     position: Point2D<i32>,
@@ -896,7 +1328,7 @@ struct Door {
 }
 
 impl Door {
-    pub fn new(image: TextureRect, position: Point2D<i32>) -> Self {
+    pub fn new(image: AtlasRect, position: Point2D<i32>) -> Self {
         let mut sprite = Sprite::new(image, 2, point2(0., 0.));
         let transform = Transform2D::create_scale(1. / TILE_SIZE as f32, 1. / TILE_SIZE as f32);
         sprite.set_transform(transform);
@@ -911,7 +1343,10 @@ impl Door {
         self.open
     }
 
-    pub fn draw(&self, out: &mut Vec<Vertex>) {
+    pub fn draw(&self, out: &mut SpriteBatch) {
+        // open doors are flat on the floor, closed ones stand up like walls
+        let layer = if self.open { FLOOR_LAYER } else { ENTITY_LAYER };
+        out.set_sort_key(SortKey::new(layer, self.position.y as f32));
         render_sprite(
             &self.sprite,
             if self.open { 1 } else { 0 },
@@ -921,16 +1356,28 @@ impl Door {
     }
 }
 
+impl HashState for Door {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_tile(self.position);
+        hasher.write_bool(self.open);
+    }
+}
+
 struct Teleporter {
     sprite: Sprite,
     position: Point2D<i32>,
     destination: Point2D<i32>,
     active_timer: f32,
+    animator: Animator,
 }
 
 impl Teleporter {
-    pub fn new(image: TextureRect, position: Point2D<i32>, destination: Point2D<i32>) -> Self {
-        let mut sprite = Sprite::new(image, 2, point2(0., 0.));
+    pub fn new(
+        animation: &Rc<Animation>,
+        position: Point2D<i32>,
+        destination: Point2D<i32>,
+    ) -> Self {
+        let mut sprite = animation.sprite(point2(0., 0.));
         let transform = Transform2D::create_scale(1. / TILE_SIZE as f32, 1. / TILE_SIZE as f32);
         sprite.set_transform(transform);
         Self {
@@ -938,11 +1385,19 @@ impl Teleporter {
             position,
             destination,
             active_timer: 0.,
+            animator: Animator::new(animation.clone(), "idle").unwrap(),
         }
     }
 
     pub fn update(&mut self) {
         self.active_timer = (self.active_timer - TICK_DT).max(0.);
+        let clip = if self.active_timer > 0. {
+            "active"
+        } else {
+            "idle"
+        };
+        self.animator.play(clip).unwrap();
+        self.animator.update(TICK_DT);
     }
 
     pub fn activate(
@@ -951,7 +1406,7 @@ impl Teleporter {
         players: &mut Vec<Ghost>,
     ) {
         if self.active_timer <= 0. {
-            self.active_timer = 0.5;
+            self.active_timer = TELEPORTER_ACTIVE_TIME;
             if let Some(teleport_entities) = players_spatial.get(&self.position) {
                 for i in teleport_entities {
                     players[*i].teleport(self.destination.to_f32() + vec2(0.5, 0.5));
@@ -960,28 +1415,51 @@ impl Teleporter {
         }
     }
 
-    pub fn draw(&self, out: &mut Vec<Vertex>) {
+    // true during the update a player stepped on the teleporter's button
+    pub fn just_activated(&self) -> bool {
+        self.active_timer >= TELEPORTER_ACTIVE_TIME
+    }
+
+    pub fn draw(&self, out: &mut SpriteBatch) {
+        out.set_sort_key(SortKey::new(FLOOR_LAYER, self.position.y as f32));
         render_sprite(
             &self.sprite,
-            if self.active_timer > 0. { 1 } else { 0 },
+            self.animator.frame(),
             self.position.to_f32(),
             out,
         );
     }
+
+    pub fn light(&self) -> Light {
+        Light {
+            position: self.position.to_f32() + vec2(0.5, 0.5),
+            radius: if self.active_timer > 0. { 2.5 } else { 1.5 },
+            color: [0.3, 0.6, 1.],
+        }
+    }
+}
+
+impl HashState for Teleporter {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_tile(self.position);
+        hasher.write_tile(self.destination);
+        hasher.write_f32(self.active_timer);
+        self.animator.hash_state(hasher);
+    }
 }
 
 struct Bulb {
     sprite: Sprite,
     shadow: Sprite,
     positions: Vec<Point2D<f32>>,
-    bob_timer: f32,
     picked_up: Option<(usize, usize)>,
     inserted: bool,
+    animator: Animator,
 }
 
 impl Bulb {
-    pub fn new(image: TextureRect, shadow: TextureRect, position: Point2D<f32>) -> Self {
-        let mut sprite = Sprite::new(image, 2, point2(4., -2.));
+    pub fn new(animation: &Rc<Animation>, shadow: AtlasRect, position: Point2D<f32>) -> Self {
+        let mut sprite = animation.sprite(point2(4., -2.));
         let mut shadow = Sprite::new(shadow, 1, point2(2., 1.5));
         let transform = Transform2D::create_scale(1. / TILE_SIZE as f32, 1. / TILE_SIZE as f32);
         sprite.set_transform(transform);
@@ -990,9 +1468,9 @@ impl Bulb {
             sprite,
             shadow,
             positions: vec![position],
-            bob_timer: 0.,
             picked_up: None,
             inserted: false,
+            animator: Animator::new(animation.clone(), "idle").unwrap(),
         }
     }
 
@@ -1014,9 +1492,13 @@ impl Bulb {
         assets: &Assets,
     ) {
         let picked_up = self.picked_up.map(|(t, _)| t <= tick).unwrap_or(false);
+        self.animator
+            .play(if picked_up { "carried" } else { "idle" })
+            .unwrap();
+        self.animator.update(TICK_DT);
         if !picked_up {
-            self.bob_timer = (self.bob_timer + TICK_DT) % 1.0;
-            let height = ((self.bob_timer * 6.28).sin() + 1.) * 2.;
+            let bob = self.animator.elapsed() / BULB_BOB_PERIOD * TAU;
+            let height = (bob.sin() + 1.) * 2.;
             let transform = Transform2D::create_translation(0., height)
                 .post_scale(1. / TILE_SIZE as f32, 1. / TILE_SIZE as f32);
             self.sprite.set_transform(transform);
@@ -1067,23 +1549,61 @@ impl Bulb {
         }
     }
 
+    pub fn spawn_tile(&self) -> Point2D<i32> {
+        let spawn = self.positions.first().expect("positions vec is empty");
+        point2(spawn.x.floor() as i32, spawn.y.floor() as i32)
+    }
+
     pub fn reset(&mut self) {
         let pos = *self.positions.first().unwrap();
         self.positions = vec![pos];
         self.picked_up = None;
     }
 
-    pub fn draw(&self, tick: usize, out: &mut Vec<Vertex>) {
+    pub fn draw(&self, tick: usize, out: &mut SpriteBatch) {
         let picked_up = self.picked_up.map(|(t, _)| t <= tick).unwrap_or(false);
+        let position = self.position(tick);
         if !picked_up {
-            render_sprite(&self.shadow, 0, self.position(tick).to_f32(), out);
+            out.set_sort_key(SortKey::new(FLOOR_LAYER, position.y));
+            render_sprite(&self.shadow, 0, position, out);
         }
-        render_sprite(
-            &self.sprite,
-            if picked_up { 1 } else { 0 },
-            self.position(tick).to_f32(),
-            out,
-        );
+        // a carried bulb is held in front of the ghost carrying it
+        let y = if picked_up {
+            position.y - 0.01
+        } else {
+            position.y
+        };
+        out.set_sort_key(SortKey::new(ENTITY_LAYER, y));
+        render_sprite(&self.sprite, self.animator.frame(), position, out);
+    }
+
+    // inserted bulbs give no light of their own, the machine's light replaces them
+    pub fn light(&self, tick: usize) -> Option<Light> {
+        if self.inserted {
+            return None;
+        }
+        Some(Light {
+            position: self.position(tick) + vec2(0., 0.5),
+            radius: 3.,
+            color: [1., 0.85, 0.5],
+        })
+    }
+}
+
+impl HashState for Bulb {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_usize(self.positions.len());
+        hasher.write_point(*self.positions.last().expect("positions vec is empty"));
+        match self.picked_up {
+            Some((tick, player)) => {
+                hasher.write_bool(true);
+                hasher.write_usize(tick);
+                hasher.write_usize(player);
+            }
+            None => hasher.write_bool(false),
+        }
+        hasher.write_bool(self.inserted);
+        self.animator.hash_state(hasher);
     }
 }
 
@@ -1091,21 +1611,21 @@ struct TheMachine {
     sprite: Sprite,
     slots: Sprite,
     bulb: Sprite,
-    animation_timer: f32,
+    animator: Animator,
     position: Point2D<f32>,
     slots_occupied: usize,
 }
 
 impl TheMachine {
     pub fn new(
-        image: TextureRect,
-        slots: TextureRect,
-        bulb: TextureRect,
+        animation: &Rc<Animation>,
+        slots: AtlasRect,
+        bulb: &Rc<Animation>,
         position: Point2D<f32>,
     ) -> Self {
-        let mut sprite = Sprite::new(image, 3, point2(15., 0.));
+        let mut sprite = animation.sprite(point2(15., 0.));
         let mut slots = Sprite::new(slots, 6, point2(15., -17.));
-        let mut bulb = Sprite::new(bulb, 2, point2(16., -18.));
+        let mut bulb = bulb.sprite(point2(16., -18.));
         let transform = Transform2D::create_scale(1. / TILE_SIZE as f32, 1. / TILE_SIZE as f32);
         sprite.set_transform(transform);
         slots.set_transform(transform);
@@ -1114,23 +1634,36 @@ impl TheMachine {
             sprite,
             slots,
             bulb,
-            animation_timer: 0.,
+            animator: Animator::new(animation.clone(), "run").unwrap(),
             position,
             slots_occupied: 0,
         }
     }
 
+    pub fn light(&self) -> Light {
+        Light {
+            position: self.position + vec2(0., 1.),
+            radius: 2. + self.slots_occupied as f32 * 0.75,
+            color: [1., 0.85, 0.5],
+        }
+    }
+
     pub fn add_bulb(&mut self) {
         self.slots_occupied += 1;
     }
 
     pub fn update(&mut self) {
-        self.animation_timer = (self.animation_timer + TICK_DT) % 0.25;
+        self.animator.update(TICK_DT);
     }
 
-    pub fn draw(&mut self, out: &mut Vec<Vertex>) {
-        let frame = ((self.animation_timer / 0.25) * 3.).floor() as usize;
-        render_sprite(&self.sprite, frame, self.position.to_f32(), out);
+    pub fn draw(&mut self, out: &mut SpriteBatch) {
+        out.set_sort_key(SortKey::new(ENTITY_LAYER, self.position.y));
+        render_sprite(
+            &self.sprite,
+            self.animator.frame(),
+            self.position.to_f32(),
+            out,
+        );
 
         if self.slots_occupied > 0 {
             render_sprite(
@@ -1150,10 +1683,435 @@ impl TheMachine {
     }
 }
 
+impl HashState for TheMachine {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_point(self.position);
+        hasher.write_usize(self.slots_occupied);
+        self.animator.hash_state(hasher);
+    }
+}
+
+// The particle sheet with its frames centered, scaled to tiles. Emitters start from this.
+fn particle_emitter() -> Emitter {
+    let mut sprite = Sprite::new(sprite_rect("particles").unwrap(), 4, point2(2.5, 2.5));
+    sprite.set_transform(Transform2D::create_scale(
+        1. / TILE_SIZE as f32,
+        1. / TILE_SIZE as f32,
+    ));
+    Emitter {
+        sprite,
+        mode: EmitMode::Burst(1),
+        lifetime: (1., 1.),
+        speed: (0., 0.),
+        direction: 0.,
+        spread: 0.,
+        radius: 0.,
+        acceleration: vec2(0., 0.),
+        colors: vec![[1., 1., 1., 1.]],
+    }
+}
+
+// Sort layers of world sprites: things lying on the floor, things standing on it, and effects
+// above everything.
+// the screen in pixels of art, with the margin around it
+fn world_target_size() -> Size2D<u32> {
+    size2(
+        (SCREEN_SIZE.width as f32 / ZOOM_LEVEL) as u32 + WORLD_MARGIN * 2,
+        (SCREEN_SIZE.height as f32 / ZOOM_LEVEL) as u32 + WORLD_MARGIN * 2,
+    )
+}
+
+// The camera in pixels of art, split into the whole pixels the world target is drawn from and
+// the fraction left over.
+fn snap_camera(camera_pos: Point2D<f32>) -> (Point2D<f32>, Vector2D<f32>) {
+    let camera_pixels = camera_pos * TILE_SIZE as f32;
+    let snapped = camera_pixels.floor();
+    (snapped, camera_pixels - snapped)
+}
+
+// Corners of the world target drawn to the screen, in pixels of art from its center. Shifting by
+// the camera's fraction of a pixel uncovers up to a pixel, which the margin fills in.
+fn world_target_quad(target: Size2D<f32>, offset: Vector2D<f32>) -> (Point2D<f32>, Point2D<f32>) {
+    let half = target.to_vector() / 2.;
+    (
+        point2(-half.x, -half.y) - offset,
+        point2(half.x, half.y) - offset,
+    )
+}
+
+const FLOOR_LAYER: i32 = 0;
+const ENTITY_LAYER: i32 = 1;
+const EFFECTS_LAYER: i32 = 2;
+
 // Time loops over 720 ticks, 12 seconds
 const LOOP_TICKS: usize = 720;
 
+// seconds a teleporter stays lit after teleporting
+const TELEPORTER_ACTIVE_TIME: f32 = 0.5;
+
+// pixels drawn around the visible world to cover subpixel camera offsets
+const WORLD_MARGIN: u32 = 1;
+
 const GHOST_SPEED: f32 = 5.;
 
-const GHOST_ANIMATION_FRAMES: u32 = 6;
-const GHOST_ANIMATION_TIME: f32 = 0.5;
+// seconds for a bulb lying on the floor to bob up and down, whatever its idle clip's length
+const BULB_BOB_PERIOD: f32 = 1.;
+
+// screen pixels covered by the side panel of the UI, the world is seen left of it
+const SIDE_PANEL_WIDTH: u32 = 200;
+
+// screen pixels between the minimap and the edges of the world view
+const MINIMAP_MARGIN: f32 = 8.;
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, fs, sync::Arc};
+
+    use image::RgbaImage;
+
+    use euclid::point2;
+
+    use super::{snap_camera, world_target_quad, world_target_size, Game};
+    use crate::{
+        constants::{SCREEN_SIZE, ZOOM_LEVEL},
+        determinism::{check_trace, parse_trace, EntityId, TraceEntry},
+        gl::{self, BlendMode, Call, CallLog, Draw, Handle, Instances, Recorder, UniformValue},
+        graphics::{
+            create_quad_index_buffer, create_sprite_program, load_sprite_atlas,
+            sprite_vertex_shader, SpriteBuffer, SpriteInstance, Vertex,
+        },
+        hot_reload::source_path,
+        input::{InputEvent, Key},
+        level::{create_level, generate_tile_batch, LayerDepth, TILE_SIZE},
+        mixer::Mixer,
+        rasterizer::{assert_golden, SoftwareBackend},
+    };
+
+    fn recording_context(recorder: Recorder) -> (gl::Context, CallLog) {
+        let calls = recorder.calls();
+        (gl::Context::new(Box::new(recorder)), calls)
+    }
+
+    fn draws(calls: &[Call]) -> Vec<&Draw> {
+        calls
+            .iter()
+            .filter_map(|call| match call {
+                Call::Draw(draw) => Some(draw),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn texture_of(draw: &Draw) -> Handle {
+        draw.uniforms
+            .iter()
+            .find_map(|uniform| match uniform {
+                UniformValue::Texture(texture) => Some(*texture),
+                _ => None,
+            })
+            .expect("draw without a texture")
+    }
+
+    // draws the level's ground tiles, returning how many there are, the atlas pages and the
+    // calls made uploading and drawing them
+    fn draw_ground_tiles(recorder: Recorder) -> (usize, Vec<Handle>, Vec<Call>) {
+        let (mut context, calls) = recording_context(recorder);
+        let level = create_level();
+        let depths = [LayerDepth::Ground, LayerDepth::Decoration];
+        let batch = generate_tile_batch(&level, &depths);
+        let tiles = level
+            .layers
+            .iter()
+            .filter(|layer| depths.contains(&layer.depth))
+            .flat_map(|layer| layer.tiles.iter().flatten())
+            .filter(|cell| cell.is_some())
+            .count();
+
+        unsafe {
+            let vertex_source = sprite_vertex_shader(&context).1;
+            let mut program = create_sprite_program(&mut context, vertex_source, "", &[]).unwrap();
+            let atlas = load_sprite_atlas(&mut context).unwrap();
+            let atlas: Vec<&gl::Texture> = atlas.iter().collect();
+            let index_buffer = create_quad_index_buffer(&mut context).unwrap();
+            let mut buffer = SpriteBuffer::new(&mut context, gl::BufferUsage::Static).unwrap();
+            let pages: Vec<Handle> = calls
+                .borrow()
+                .iter()
+                .filter_map(|call| match call {
+                    Call::CreateTexture { texture, .. } => Some(*texture),
+                    _ => None,
+                })
+                .collect();
+            calls.borrow_mut().clear();
+
+            let identity = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+            program
+                .set_uniform("u_transform", gl::Uniform::Mat3(identity))
+                .unwrap();
+            batch.upload(&mut buffer);
+            batch
+                .draw(
+                    &mut program,
+                    &atlas,
+                    &buffer,
+                    &index_buffer,
+                    &gl::DrawState::default(),
+                )
+                .unwrap();
+
+            let calls = calls.replace(Vec::new());
+            (tiles, pages, calls)
+        }
+    }
+
+    #[test]
+    fn tile_batch_draws_every_tile() {
+        let (tiles, pages, calls) = draw_ground_tiles(Recorder::new());
+        assert!(calls.contains(&Call::WriteVertices {
+            buffer: draws(&calls)[0].vertex_buffer,
+            offset: 0,
+            bytes: tiles * 4 * std::mem::size_of::<Vertex>(),
+        }));
+        // the draws cover the quads one after another, each from an atlas page
+        let mut next_vertex = 0;
+        for draw in draws(&calls) {
+            assert!(pages.contains(&texture_of(draw)));
+            assert_eq!(draw.instances, None);
+            assert_eq!(draw.base_vertex, next_vertex);
+            assert_eq!(draw.count % 6, 0);
+            next_vertex += draw.count / 6 * 4;
+        }
+        assert_eq!(next_vertex, tiles * 4);
+    }
+
+    #[test]
+    fn tile_batch_draws_every_tile_instanced() {
+        let (tiles, pages, calls) = draw_ground_tiles(Recorder::with_instancing());
+        let instance_buffer = draws(&calls)[0].instances.unwrap().buffer;
+        assert!(calls.contains(&Call::WriteVertices {
+            buffer: instance_buffer,
+            offset: 0,
+            bytes: tiles * std::mem::size_of::<SpriteInstance>(),
+        }));
+        // one unit quad per draw, instanced over each batch in turn
+        let mut next_instance = 0;
+        for draw in draws(&calls) {
+            assert!(pages.contains(&texture_of(draw)));
+            assert_eq!(draw.count, 6);
+            let Instances {
+                buffer,
+                first,
+                count,
+            } = draw.instances.unwrap();
+            assert_eq!(buffer, instance_buffer);
+            assert_eq!(first, next_instance);
+            next_instance += count;
+        }
+        assert_eq!(next_instance, tiles);
+    }
+
+    #[test]
+    fn recorded_trace_replays_on_a_new_game() {
+        let (mut context, _) = recording_context(Recorder::new());
+        let mut game = Game::new(&mut context, Arc::new(Mixer::default()));
+        let mut text = String::new();
+        for update in 0..240 {
+            let key = [Key::D, Key::S, Key::A, Key::W, Key::Space][update / 40 % 5];
+            let inputs = match update % 40 {
+                0 => vec![InputEvent::KeyDown(key)],
+                20 => vec![InputEvent::KeyUp(key)],
+                _ => Vec::new(),
+            };
+            game.update(&inputs);
+            let entry = TraceEntry {
+                inputs,
+                hash: game.state_hash(),
+            };
+            text.push_str(&entry.to_line());
+            text.push('\n');
+        }
+        let mut trace = parse_trace(&text).unwrap();
+
+        let mut replay = |trace: &[TraceEntry]| {
+            let mut game = Game::new(&mut context, Arc::new(Mixer::default()));
+            check_trace(trace, |inputs| {
+                game.update(inputs);
+                game.state_hash()
+            })
+        };
+        assert_eq!(replay(&trace).unwrap(), 240);
+
+        let (entity, hash) = trace[100]
+            .hash
+            .entities
+            .iter_mut()
+            .find(|(entity, _)| matches!(entity, EntityId::Ghost(_)))
+            .unwrap();
+        *hash ^= 1;
+        let entity = *entity;
+        let divergence = replay(&trace).unwrap_err();
+        assert_eq!(divergence.update, 100);
+        assert_eq!(divergence.tick, trace[100].hash.tick);
+        assert_eq!(divergence.entity, entity);
+    }
+
+    #[test]
+    fn draw_composites_the_world_then_the_ui() {
+        let (mut context, calls) = recording_context(Recorder::new());
+        let mut game = Game::new(&mut context, Arc::new(Mixer::default()));
+        let created = calls.replace(Vec::new());
+        game.draw(&mut context);
+        let calls = calls.borrow();
+
+        let bound = calls
+            .iter()
+            .position(|call| matches!(call, Call::BindRenderTarget(_)))
+            .unwrap();
+        let world_target = match calls[bound] {
+            Call::BindRenderTarget(Some(target)) => target,
+            ref call => panic!("world target not bound first, got {:?}", call),
+        };
+        let world_texture = created
+            .iter()
+            .find_map(|call| match call {
+                Call::CreateRenderTarget { target, texture } if *target == world_target => {
+                    Some(*texture)
+                }
+                _ => None,
+            })
+            .unwrap();
+        let cleared = calls[bound + 1..]
+            .iter()
+            .find(|call| !matches!(call, Call::SetViewport { .. }));
+        assert!(matches!(cleared, Some(Call::Clear(_))));
+        assert!(draws(&calls[..bound]).is_empty());
+
+        let to_window = calls
+            .iter()
+            .position(|call| *call == Call::BindRenderTarget(None))
+            .expect("nothing drawn to the window");
+        let world = draws(&calls[..to_window]);
+        let window = draws(&calls[to_window..]);
+
+        // lights add up into the light map, which then darkens the world
+        assert!(world
+            .iter()
+            .any(|draw| draw.state.blend_mode == BlendMode::Additive));
+        assert_eq!(world.last().unwrap().state.blend_mode, BlendMode::Multiply);
+        assert_eq!(texture_of(window[0]), world_texture);
+        assert!(window.len() > 1, "no ui drawn over the world");
+        assert!(window
+            .iter()
+            .all(|draw| draw.state.blend_mode == BlendMode::Premultiplied));
+    }
+
+    #[test]
+    fn added_ghosts_walk_without_input() {
+        let (mut context, _) = recording_context(Recorder::new());
+        let mut game = Game::new(&mut context, Arc::new(Mixer::default()));
+        let start = game.level.player_start;
+        game.add_bench_ghosts(100);
+        for _ in 0..30 {
+            game.update(&[]);
+        }
+
+        assert_eq!(game.players.len(), 101);
+        assert!(game.players[..100]
+            .iter()
+            .all(|ghost| ghost.position(game.tick) != start));
+        // the player got no input, so it hasn't moved
+        assert_eq!(game.players[100].position(game.tick), start);
+    }
+
+    #[test]
+    fn draw_matches_the_golden_frame() {
+        let (width, height) = (SCREEN_SIZE.width, SCREEN_SIZE.height);
+        let mut context = gl::Context::new(Box::new(SoftwareBackend::new(width, height)));
+        let mut game = Game::new(&mut context, Arc::new(Mixer::default()));
+        // walk a little with the minimap open, so the loop has started and every pass is drawn
+        let mut inputs = vec![InputEvent::KeyDown(Key::M), InputEvent::KeyDown(Key::D)];
+        for _ in 0..30 {
+            game.update(&inputs);
+            inputs.clear();
+        }
+        game.draw(&mut context);
+
+        let pixels = unsafe { context.read_pixels(0, 0, width, height) };
+        let frame = RgbaImage::from_raw(width, height, pixels).unwrap();
+        assert_golden("game_frame", &image::imageops::flip_vertical(&frame));
+    }
+
+    #[test]
+    fn failed_shader_compiles_keep_the_old_programs() {
+        let (mut context, calls) = recording_context(Recorder::new());
+        let mut game = Game::new(&mut context, Arc::new(Mixer::default()));
+        let programs = |game: &mut Game, context: &mut gl::Context| {
+            calls.borrow_mut().clear();
+            game.draw(context);
+            draws(&calls.borrow())
+                .iter()
+                .map(|draw| draw.program)
+                .collect::<HashSet<_>>()
+        };
+        let drawn = programs(&mut game, &mut context);
+        let source = |file: &str| fs::read_to_string(source_path("src/shaders").join(file));
+
+        let broken = unsafe {
+            game.reload_shaders(&mut context, "shader", |file| match file {
+                "shader.frag" => Ok("#error half written".to_owned()),
+                _ => source(file),
+            })
+        };
+        assert!(broken.is_err());
+        assert_eq!(programs(&mut game, &mut context), drawn);
+
+        unsafe { game.reload_shaders(&mut context, "shader", source) }.unwrap();
+        assert_ne!(programs(&mut game, &mut context), drawn);
+    }
+
+    #[test]
+    fn minimap_toggles_but_is_not_drawn_on_the_win_screen() {
+        let (mut context, calls) = recording_context(Recorder::new());
+        let mut game = Game::new(&mut context, Arc::new(Mixer::default()));
+        let (width, height) = game.minimap.size();
+        let minimap_drawn = |game: &mut Game, context: &mut gl::Context| {
+            calls.borrow_mut().clear();
+            game.draw(context);
+            calls.borrow().iter().any(|call| match call {
+                Call::WriteTexture { rect, .. } => *rect == (0, 0, width, height),
+                _ => false,
+            })
+        };
+
+        game.the_machine.slots_occupied = 6;
+        game.update(&[InputEvent::KeyDown(Key::M)]);
+        assert!(game.minimap.visible());
+        assert!(!minimap_drawn(&mut game, &mut context));
+
+        game.the_machine.slots_occupied = 5;
+        assert!(minimap_drawn(&mut game, &mut context));
+    }
+
+    #[test]
+    fn camera_snaps_to_whole_pixels_and_the_margin_covers_the_rest() {
+        let target = world_target_size().to_f32();
+        let screen = SCREEN_SIZE.to_f32() / ZOOM_LEVEL;
+        for &camera in &[point2(3.37, 5.81), point2(10.999, 0.03), point2(-0.4, 7.5)] {
+            let (snapped, offset) = snap_camera(camera);
+            assert_eq!(snapped, snapped.round());
+            assert!(offset.x >= 0. && offset.x < 1. && offset.y >= 0. && offset.y < 1.);
+            // tiles are drawn to whole texels, and the offset moves them the rest of the way
+            let tile_corner = point2(4., 6.) * TILE_SIZE as f32;
+            let texel = tile_corner - snapped;
+            assert_eq!(texel, texel.round());
+            let on_screen = texel - offset;
+            let expected = tile_corner - camera * TILE_SIZE as f32;
+            assert!((on_screen - expected).length() < 1e-3);
+
+            let (min, max) = world_target_quad(target, offset);
+            assert_eq!(max - min, target.to_vector());
+            assert!(min.x <= -screen.width / 2. && min.y <= -screen.height / 2.);
+            assert!(max.x >= screen.width / 2. && max.y >= screen.height / 2.);
+        }
+    }
+}
@@ -1,262 +1,483 @@
-use std::rc::Rc;
+mod glow_backend;
+mod recorder;
+
+pub use glow_backend::GlowBackend;
+pub use recorder::{Call, CallLog, Recorder};
+
+use std::{cell::RefCell, rc::Rc};
 
-use glow::HasContext;
 use thiserror::Error;
 use zerocopy::AsBytes;
 
-type VertexArrayId = <glow::Context as glow::HasContext>::VertexArray;
-type BufferId = <glow::Context as glow::HasContext>::Buffer;
-type UniformLocationId = <glow::Context as glow::HasContext>::UniformLocation;
-type ProgramId = <glow::Context as glow::HasContext>::Program;
-type ShaderId = <glow::Context as glow::HasContext>::Shader;
-type TextureId = <glow::Context as glow::HasContext>::Texture;
-
-pub struct Shader(Rc<ShaderId>);
+pub struct Shader(Owned);
 pub struct Texture {
-    context: Rc<glow::Context>,
-    texture_id: Rc<TextureId>,
-    format: TextureFormat,
+    // shared with the programs it is set on, so it lives as long as they might draw with it
+    texture: Rc<Owned>,
+    width: u32,
+    height: u32,
 }
 pub struct VertexBuffer {
-    context: Rc<glow::Context>,
-    vertex_array: Rc<VertexArrayId>,
-    buffer: Rc<BufferId>,
+    buffer: Owned,
+    storage: Storage,
     len: usize,
 }
+pub struct IndexBuffer {
+    buffer: Owned,
+    storage: Storage,
+    len: usize,
+}
+/// A texture that can be drawn into in place of the window.
+pub struct RenderTarget {
+    target: Owned,
+    texture: Texture,
+    width: u32,
+    height: u32,
+}
 
+/// Creates GL resources and draws with them through a `Backend`. Resources are deleted by
+/// `maintain` after their handles are dropped, or right away with their `destroy` method.
+/// Dropping the context deletes whatever is still queued, and in debug builds reports resources
+/// that outlived it.
 pub struct Context {
-    context: Rc<glow::Context>,
-    shaders: Vec<Rc<ShaderId>>,
-    programs: Vec<Rc<ProgramId>>,
-    vertex_arrays: Vec<Rc<VertexArrayId>>,
-    buffers: Vec<Rc<BufferId>>,
-    textures: Vec<Rc<TextureId>>,
+    context: Rc<Shared>,
+    // restored when drawing goes back to the window
+    viewport: Option<Rect>,
 }
 
 #[derive(Debug, Error)]
 #[error("OpenGL error: {0}")]
 pub struct GLError(String);
 
+/// Names an object created by a `Backend`, only meaningful to that backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Handle(pub u32);
+
+/// A rect in pixels from the bottom left: x, y, width and height.
+pub type Rect = (i32, i32, u32, u32);
+
+/// What a `Context` draws with: OpenGL through `GlowBackend`, or a `Recorder` in tests. Objects
+/// are checked by the `Context` before they get here, so handles are always of the right kind
+/// and uniforms are all set and of the right type.
+pub trait Backend {
+    /// Whether `Draw::instances` can be used, see `Context::supports_instancing`.
+    fn supports_instancing(&self) -> bool;
+
+    unsafe fn create_shader(&self, shader_type: ShaderType, source: &str)
+        -> Result<Handle, String>;
+    /// Links a program, uniforms are later given to `draw` in the order of `uniforms`. Backends
+    /// that can reflect on the linked program fail if its active uniforms and attributes don't
+    /// match the ones given, naming the first that doesn't.
+    unsafe fn create_program(
+        &self,
+        vertex_shader: Handle,
+        fragment_shader: Handle,
+        uniforms: &[UniformEntry],
+        vertex_format: &VertexFormat,
+        instance_format: Option<&VertexFormat>,
+    ) -> Result<Handle, String>;
+    unsafe fn create_vertex_buffer(&self) -> Result<Handle, String>;
+    unsafe fn create_index_buffer(&self) -> Result<Handle, String>;
+    unsafe fn create_texture(&self, desc: &TextureDescriptor) -> Result<Handle, String>;
+    /// Creates a render target that draws into `texture`.
+    unsafe fn create_render_target(&self, texture: Handle) -> Result<Handle, String>;
+
+    /// Gives `buffer` new storage for `size` bytes with undefined contents, dropping the old
+    /// storage once draws still using it are done.
+    unsafe fn allocate_vertices(&self, buffer: Handle, size: usize, usage: BufferUsage);
+    /// Writes `data` at byte `offset` of storage allocated by `allocate_vertices`.
+    unsafe fn write_vertices(&self, buffer: Handle, offset: usize, data: &[u8]);
+    /// Gives `buffer` new storage for `len` indices, like `allocate_vertices`.
+    unsafe fn allocate_indices(&self, buffer: Handle, len: usize, usage: BufferUsage);
+    unsafe fn write_indices(&self, buffer: Handle, first: usize, indices: &[u16]);
+    /// Writes `data` to an x, y, width and height rect of `texture`.
+    unsafe fn write_texture(&self, texture: Handle, rect: (u32, u32, u32, u32), data: &[u8]);
+    /// Reads back all of `texture` as RGBA8, rows from the bottom up.
+    unsafe fn read_texture(
+        &self,
+        texture: Handle,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<u8>, String>;
+
+    /// Directs drawing into a render target, or the window.
+    unsafe fn bind_render_target(&self, target: Option<Handle>);
+    /// Sets the area drawn into, and whether draws without a scissor rect are clipped to it.
+    unsafe fn set_viewport(&self, area: Rect, clip_to_area: bool);
+    /// Clears the whole area with all channels written.
+    unsafe fn clear(&self, color: [f32; 4]);
+    unsafe fn read_pixels(&self, rect: Rect) -> Vec<u8>;
+    unsafe fn draw(&self, draw: &Draw);
+
+    unsafe fn delete(&self, handle: Handle);
+}
+
+/// One draw call, as given to a `Backend`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Draw {
+    pub program: Handle,
+    /// Values of the program's uniforms, in the order they were declared.
+    pub uniforms: Vec<UniformValue>,
+    pub vertex_buffer: Handle,
+    /// Draws `count` indices of this index buffer, or else `count` vertices.
+    pub index_buffer: Option<Handle>,
+    pub base_vertex: usize,
+    pub count: usize,
+    /// Draws everything once per instance, with the program's instance attributes read from
+    /// this buffer.
+    pub instances: Option<Instances>,
+    pub state: DrawState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instances {
+    pub buffer: Handle,
+    pub first: usize,
+    pub count: usize,
+}
+
+/// The value of a uniform at a draw.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UniformValue {
+    Texture(Handle),
+    Int(i32),
+    Int2(i32, i32),
+    Int3(i32, i32, i32),
+    Int4(i32, i32, i32, i32),
+    Float(f32),
+    Float2(f32, f32),
+    Float3(f32, f32, f32),
+    Float4(f32, f32, f32, f32),
+    Mat2([[f32; 2]; 2]),
+    Mat3([[f32; 3]; 3]),
+    Mat4([[f32; 4]; 4]),
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Shader,
+    Program,
+    VertexBuffer,
+    IndexBuffer,
+    Texture,
+    RenderTarget,
+}
+
+const KIND_NAMES: [&str; 6] = [
+    "shaders",
+    "programs",
+    "vertex buffers",
+    "index buffers",
+    "textures",
+    "render targets",
+];
+
+// The backend, shared by the `Context` and every resource handle so handles can queue their
+// objects for deletion when dropped. Objects are deleted later rather than in `Drop` so that
+// nothing is deleted in the middle of a frame that might still draw with it.
+struct Shared {
+    backend: Box<dyn Backend>,
+    deletion_queue: RefCell<Vec<Handle>>,
+    // objects of each kind that exist and haven't been queued, indexed by `Kind`
+    live: RefCell<[usize; 6]>,
+}
+
+impl Shared {
+    unsafe fn delete_queued(&self) {
+        for handle in self.deletion_queue.borrow_mut().drain(..) {
+            self.backend.delete(handle);
+        }
+    }
+}
+
+impl Drop for Shared {
+    // the last handles outlived the `Context`, nothing else is left to delete their objects
+    fn drop(&mut self) {
+        unsafe { self.delete_queued() };
+    }
+}
+
+// An object owned by a resource handle, queued for deletion when dropped.
+struct Owned {
+    context: Rc<Shared>,
+    handle: Handle,
+    kind: Kind,
+}
+
+impl Owned {
+    fn new(context: &Rc<Shared>, handle: Handle, kind: Kind) -> Self {
+        context.live.borrow_mut()[kind as usize] += 1;
+        Owned {
+            context: context.clone(),
+            handle,
+            kind,
+        }
+    }
+}
+
+impl Drop for Owned {
+    fn drop(&mut self) {
+        self.context.live.borrow_mut()[self.kind as usize] -= 1;
+        self.context.deletion_queue.borrow_mut().push(self.handle);
+    }
+}
+
 impl Context {
-    pub fn from_glow_context(context: glow::Context) -> Context {
+    pub fn new(backend: Box<dyn Backend>) -> Context {
         Context {
-            context: Rc::new(context),
-            shaders: Vec::new(),
-            programs: Vec::new(),
-            vertex_arrays: Vec::new(),
-            buffers: Vec::new(),
-            textures: Vec::new(),
+            context: Rc::new(Shared {
+                backend,
+                deletion_queue: RefCell::new(Vec::new()),
+                live: RefCell::new([0; 6]),
+            }),
+            viewport: None,
         }
     }
 
+    /// Draws with `context`, instanced if its GL version has instancing built in.
+    pub fn from_glow_context(context: glow::Context) -> Context {
+        let instancing = glow_backend::has_instancing(&context);
+        Context::new(Box::new(GlowBackend::new(context, instancing)))
+    }
+
+    /// Whether `Program::render_instanced` can be used. GLES 3 and WebGL 2 always can, GLES 2
+    /// and WebGL 1 only through extensions.
+    pub fn supports_instancing(&self) -> bool {
+        self.context.backend.supports_instancing()
+    }
+
+    fn own(&self, handle: Result<Handle, String>, kind: Kind) -> Result<Owned, GLError> {
+        Ok(Owned::new(&self.context, handle.map_err(GLError)?, kind))
+    }
+
     pub unsafe fn create_shader(
         &mut self,
         shader_type: ShaderType,
         src: &str,
     ) -> Result<Shader, GLError> {
-        let shader_id = self
-            .context
-            .create_shader(shader_type as u32)
-            .map_err(GLError)?;
-        self.context.shader_source(shader_id, src);
-        self.context.compile_shader(shader_id);
-        if !self.context.get_shader_compile_status(shader_id) {
-            Err(GLError(self.context.get_shader_info_log(shader_id)))
-        } else {
-            let shader = Shader(Rc::new(shader_id));
-            self.shaders.push(shader.0.clone());
-            Ok(shader)
-        }
+        let handle = self.context.backend.create_shader(shader_type, src);
+        Ok(Shader(self.own(handle, Kind::Shader)?))
     }
 
+    /// Links a program, failing if the uniforms and attributes the shaders use don't match the
+    /// descriptor.
     pub unsafe fn create_program(&mut self, desc: &ProgramDescriptor) -> Result<Program, GLError> {
-        let program_id = self.context.create_program().map_err(GLError)?;
-        self.context
-            .attach_shader(program_id, *desc.vertex_shader.0);
-        self.context
-            .attach_shader(program_id, *desc.fragment_shader.0);
-        self.context.link_program(program_id);
-        if !self.context.get_program_link_status(program_id) {
-            return Err(GLError(self.context.get_program_info_log(program_id)));
-        }
-
-        let mut set_uniforms = Vec::new();
-        for entry in desc.uniforms {
-            let location = self
-                .context
-                .get_uniform_location(program_id, entry.name)
-                .ok_or_else(|| {
-                    GLError(format!("could not get location for uniform {}", entry.name))
-                })?;
-            set_uniforms.push((location, None));
-        }
-
-        let vertex_format = VertexFormatInner {
-            stride: desc.vertex_format.stride as i32,
-            attributes: desc
-                .vertex_format
-                .attributes
-                .iter()
-                .map(|attr_desc| {
-                    let location = self
-                        .context
-                        .get_attrib_location(program_id, attr_desc.name)
-                        .ok_or_else(|| {
-                            GLError(format!(
-                                "could not get location of attribute {}",
-                                attr_desc.name
-                            ))
-                        })?;
-                    let attribute = VertexAttributeInner {
-                        ty: attr_desc.ty,
-                        size: attr_desc.size,
-                        offset: attr_desc.offset,
-                    };
-                    Ok((location, attribute))
-                })
-                .collect::<Result<Vec<_>, GLError>>()?,
-        };
-
-        let program_id = Rc::new(program_id);
-        self.programs.push(program_id.clone());
+        for (i, entry) in desc.uniforms.iter().enumerate() {
+            if desc.uniforms[..i].iter().any(|e| e.name == entry.name) {
+                return Err(GLError(format!("uniform {} is declared twice", entry.name)));
+            }
+        }
+        let handle = self.context.backend.create_program(
+            desc.vertex_shader.0.handle,
+            desc.fragment_shader.0.handle,
+            desc.uniforms,
+            &desc.vertex_format,
+            desc.instance_format.as_ref(),
+        );
         Ok(Program {
-            context: self.context.clone(),
-            program_id: program_id,
-            vertex_shader: desc.vertex_shader.0.clone(),
-            fragment_shader: desc.fragment_shader.0.clone(),
-            uniform_entry_types: desc.uniforms.iter().map(|e| e.ty).collect(),
-            set_uniforms,
-            vertex_format,
+            program: self.own(handle, Kind::Program)?,
+            uniform_entries: desc
+                .uniforms
+                .iter()
+                .map(|e| (e.name.to_owned(), e.ty))
+                .collect(),
+            set_uniforms: desc.uniforms.iter().map(|_| None).collect(),
+            instanced: desc.instance_format.is_some(),
         })
     }
 
-    pub unsafe fn create_vertex_buffer(&mut self) -> Result<VertexBuffer, GLError> {
-        let vertex_array_id = Rc::new(self.context.create_vertex_array().map_err(GLError)?);
-        self.vertex_arrays.push(vertex_array_id.clone());
-        let buffer_id = Rc::new(self.context.create_buffer().map_err(GLError)?);
-        self.buffers.push(buffer_id.clone());
-
+    pub unsafe fn create_vertex_buffer(
+        &mut self,
+        usage: BufferUsage,
+    ) -> Result<VertexBuffer, GLError> {
+        let handle = self.context.backend.create_vertex_buffer();
         Ok(VertexBuffer {
-            context: self.context.clone(),
-            vertex_array: vertex_array_id,
-            buffer: buffer_id,
+            buffer: self.own(handle, Kind::VertexBuffer)?,
+            storage: Storage::new(usage),
             len: 0,
         })
     }
 
-    pub unsafe fn create_texture(
+    pub unsafe fn create_index_buffer(
         &mut self,
-        format: TextureFormat,
-        width: u32,
-        height: u32,
-    ) -> Result<Texture, GLError> {
-        let texture_id = self.context.create_texture().map_err(GLError)?;
-        self.context
-            .bind_texture(glow::TEXTURE_2D, Some(texture_id));
-        self.context.tex_parameter_i32(
-            glow::TEXTURE_2D,
-            glow::TEXTURE_MIN_FILTER,
-            glow::NEAREST as i32,
-        );
-        self.context.tex_parameter_i32(
-            glow::TEXTURE_2D,
-            glow::TEXTURE_MAG_FILTER,
-            glow::NEAREST as i32,
-        );
-        self.context.tex_parameter_i32(
-            glow::TEXTURE_2D,
-            glow::TEXTURE_WRAP_S,
-            glow::CLAMP_TO_EDGE as i32,
-        );
-        self.context.tex_parameter_i32(
-            glow::TEXTURE_2D,
-            glow::TEXTURE_WRAP_T,
-            glow::CLAMP_TO_EDGE as i32,
-        );
-
-        self.context.tex_image_2d(
-            glow::TEXTURE_2D,
-            0,
-            match format {
-                TextureFormat::RFloat | TextureFormat::RInt => glow::RED,
-                TextureFormat::RGFloat | TextureFormat::RGInt => glow::RG,
-                TextureFormat::RGBFloat | TextureFormat::RGBInt => glow::RGB,
-                TextureFormat::BGRFloat | TextureFormat::BGRInt => glow::BGR,
-                TextureFormat::RGBAFloat | TextureFormat::RGBAInt => glow::RGBA,
-                TextureFormat::BGRAFloat | TextureFormat::BGRAInt => glow::BGRA,
-            } as i32,
-            width as i32,
-            height as i32,
-            0,
-            match format {
-                TextureFormat::RFloat => glow::RED,
-                TextureFormat::RGFloat => glow::RG,
-                TextureFormat::RGBFloat => glow::RGB,
-                TextureFormat::BGRFloat => glow::BGR,
-                TextureFormat::RGBAFloat => glow::RGBA,
-                TextureFormat::BGRAFloat => glow::BGRA,
-                TextureFormat::RInt => glow::RED_INTEGER,
-                TextureFormat::RGInt => glow::RG_INTEGER,
-                TextureFormat::RGBInt => glow::RGB_INTEGER,
-                TextureFormat::BGRInt => glow::BGR_INTEGER,
-                TextureFormat::RGBAInt => glow::RGBA_INTEGER,
-                TextureFormat::BGRAInt => glow::BGRA_INTEGER,
-            },
-            glow::UNSIGNED_BYTE,
-            None,
-        );
+        usage: BufferUsage,
+    ) -> Result<IndexBuffer, GLError> {
+        let handle = self.context.backend.create_index_buffer();
+        Ok(IndexBuffer {
+            buffer: self.own(handle, Kind::IndexBuffer)?,
+            storage: Storage::new(usage),
+            len: 0,
+        })
+    }
 
-        let texture_id = Rc::new(texture_id);
-        self.textures.push(texture_id.clone());
+    pub unsafe fn create_texture(&mut self, desc: &TextureDescriptor) -> Result<Texture, GLError> {
+        let handle = self.context.backend.create_texture(desc);
         Ok(Texture {
-            context: self.context.clone(),
-            texture_id,
-            format,
+            texture: Rc::new(self.own(handle, Kind::Texture)?),
+            width: desc.width,
+            height: desc.height,
         })
     }
 
-    pub unsafe fn maintain(&mut self) {
-        for i in (0..self.programs.len()).rev() {
-            if Rc::strong_count(&self.programs[i]) == 1 {
-                let program = self.programs.swap_remove(i);
-                self.context.delete_program(*program);
-            }
-        }
-        for i in (0..self.shaders.len()).rev() {
-            if Rc::strong_count(&self.shaders[i]) == 1 {
-                let shader = self.shaders.swap_remove(i);
-                self.context.delete_shader(*shader);
-            }
-        }
-        for i in (0..self.vertex_arrays.len()).rev() {
-            if Rc::strong_count(&self.vertex_arrays[i]) == 1 {
-                let vertex_array = self.vertex_arrays.swap_remove(i);
-                self.context.delete_vertex_array(*vertex_array);
+    /// Reads back a texture as RGBA8 for debugging, rows from the bottom up. Channels the
+    /// texture doesn't have read as 0, or 255 for alpha. Integer textures can't be read back.
+    pub unsafe fn read_texture(&mut self, texture: &Texture) -> Result<Vec<u8>, GLError> {
+        self.context
+            .backend
+            .read_texture(texture.texture.handle, texture.width, texture.height)
+            .map_err(GLError)
+    }
+
+    /// Creates an RGBA render target, its texture is sampled nearest like any other texture.
+    /// Row 0 of the texture is the bottom of what was drawn into it.
+    pub unsafe fn create_render_target(
+        &mut self,
+        width: u32,
+        height: u32,
+    ) -> Result<RenderTarget, GLError> {
+        let texture = self.create_texture(&TextureDescriptor::new(
+            TextureFormat::RGBAByte,
+            width,
+            height,
+        ))?;
+        let handle = self
+            .context
+            .backend
+            .create_render_target(texture.texture.handle);
+        Ok(RenderTarget {
+            target: self.own(handle, Kind::RenderTarget)?,
+            texture,
+            width,
+            height,
+        })
+    }
+
+    /// Directs drawing and clearing into `target`, covering all of it, or back to the window
+    /// with the viewport last given to `set_viewport`.
+    pub unsafe fn bind_render_target(&mut self, target: Option<&RenderTarget>) {
+        let backend = &self.context.backend;
+        match target {
+            Some(target) => {
+                backend.bind_render_target(Some(target.target.handle));
+                backend.set_viewport((0, 0, target.width, target.height), false);
             }
-        }
-        for i in (0..self.buffers.len()).rev() {
-            if Rc::strong_count(&self.buffers[i]) == 1 {
-                let buffer = self.buffers.swap_remove(i);
-                self.context.delete_buffer(*buffer);
+            None => {
+                backend.bind_render_target(None);
+                if let Some(viewport) = self.viewport {
+                    backend.set_viewport(viewport, true);
+                }
             }
         }
-        for i in (0..self.textures.len()).rev() {
-            if Rc::strong_count(&self.textures[i]) == 1 {
-                let texture = self.textures.swap_remove(i);
-                self.context.delete_texture(*texture);
+    }
+
+    /// Deletes the resources dropped since the last call, once per frame after drawing.
+    pub unsafe fn maintain(&mut self) {
+        self.context.delete_queued();
+    }
+
+    /// Deletes a resource now rather than at the next `maintain`, along with anything else
+    /// already dropped. A texture is only deleted once the programs it was set on let go of it.
+    pub unsafe fn destroy(&mut self, resource: impl Resource) {
+        drop(resource);
+        self.context.delete_queued();
+    }
+
+    /// Restricts drawing and clearing to a rect of the window, in pixels from the bottom left.
+    pub unsafe fn set_viewport(&mut self, x: i32, y: i32, width: u32, height: u32) {
+        self.viewport = Some((x, y, width, height));
+        self.context
+            .backend
+            .set_viewport((x, y, width, height), true);
+    }
+
+    /// Clears all of the viewport or render target, whatever the last draw's scissor was.
+    pub unsafe fn clear(&mut self, color: [f32; 4]) {
+        self.context.backend.clear(color);
+    }
+
+    /// Reads back RGBA8 pixels of whatever drawing currently goes to, the window or a render
+    /// target. Like textures, rows go from the bottom up.
+    pub unsafe fn read_pixels(&self, x: i32, y: i32, width: u32, height: u32) -> Vec<u8> {
+        self.context.backend.read_pixels((x, y, width, height))
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        unsafe { self.context.delete_queued() };
+        if cfg!(debug_assertions) {
+            let live = self.context.live.borrow();
+            for (kind, &count) in KIND_NAMES.iter().zip(live.iter()) {
+                if count > 0 {
+                    log::warn!("{} {} outlived the GL context", count, kind);
+                }
             }
         }
     }
+}
 
-    pub unsafe fn clear(&mut self, color: [f32; 4]) {
-        self.context
-            .clear_color(color[0], color[1], color[2], color[3]);
-        self.context.clear(glow::COLOR_BUFFER_BIT);
+/// GL resources created by a `Context`.
+pub trait Resource {}
+
+impl Resource for Shader {}
+impl Resource for Program {}
+impl Resource for VertexBuffer {}
+impl Resource for IndexBuffer {}
+impl Resource for Texture {}
+impl Resource for RenderTarget {}
+
+/// Fixed function state of a draw. Only what differs from the previous draw is sent to GL.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DrawState {
+    pub blend_mode: BlendMode,
+    /// Clips drawing to a rect of the viewport or render target, in pixels from its bottom left.
+    pub scissor: Option<(i32, i32, u32, u32)>,
+    pub topology: Topology,
+    /// Whether the red, green, blue and alpha channels are written.
+    pub color_mask: [bool; 4],
+}
+
+impl Default for DrawState {
+    fn default() -> Self {
+        Self {
+            blend_mode: BlendMode::Premultiplied,
+            scissor: None,
+            topology: Topology::Triangles,
+            color_mask: [true; 4],
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// How vertices, or indices, are put together into primitives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    Points,
+    Lines,
+    LineStrip,
+    Triangles,
+    TriangleStrip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    /// Source colors already multiplied by alpha, drawn over the destination.
+    Premultiplied,
+    /// Source colors added to the destination.
+    Additive,
+    /// Destination colors multiplied by the source.
+    Multiply,
+}
+
+/// Channels and how each is stored. `Byte` channels are 8 bit, sampled as 0 to 1. `Float`
+/// channels are 32 bit floats. `Int` channels are 8 bit unsigned integers that need an integer
+/// sampler and can't be read back. BGR orders only change how uploaded data is read. GLES 2 and
+/// WebGL 1 can only create `RGBByte` and `RGBAByte` textures, and GLES has no BGR orders, so
+/// `Context::create_texture` fails for those there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TextureFormat {
+    RByte,
+    RGByte,
+    RGBByte,
+    RGBAByte,
     RFloat,
     RInt,
     RGFloat,
@@ -271,223 +492,329 @@ pub enum TextureFormat {
     BGRAInt,
 }
 
+/// How often a buffer is written. `Static` buffers are written once and are allocated to fit.
+/// `Dynamic` and `Stream` buffers grow to powers of two and are written in place while they fit.
+/// `Stream` buffers are rewritten every frame and are orphaned on each write, so the write
+/// doesn't wait for draws of the previous contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferUsage {
+    Static,
+    Dynamic,
+    Stream,
+}
+
+// the capacity allocated for a buffer, in vertex bytes or indices
+struct Storage {
+    usage: BufferUsage,
+    capacity: usize,
+}
+
+impl Storage {
+    fn new(usage: BufferUsage) -> Self {
+        Self { usage, capacity: 0 }
+    }
+
+    // how much to allocate before writing `size`, if anything
+    fn allocation_for(&mut self, size: usize) -> Option<usize> {
+        if size > self.capacity {
+            self.capacity = match self.usage {
+                BufferUsage::Static => size,
+                BufferUsage::Dynamic | BufferUsage::Stream => size.next_power_of_two(),
+            };
+            Some(self.capacity)
+        } else if self.usage == BufferUsage::Stream && size > 0 {
+            Some(self.capacity)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+/// What texture coordinates outside 0 to 1 sample. GLES 2 only repeats textures whose sides
+/// are powers of two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wrap {
+    ClampToEdge,
+    Repeat,
+    MirroredRepeat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureDescriptor {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub min_filter: Filter,
+    pub mag_filter: Filter,
+    pub wrap_s: Wrap,
+    pub wrap_t: Wrap,
+    /// Generates mipmaps after every write, sampled between with the given filter.
+    pub mipmaps: Option<Filter>,
+}
+
+impl TextureDescriptor {
+    /// Sampled nearest and clamped, without mipmaps, which suits pixel art.
+    pub fn new(format: TextureFormat, width: u32, height: u32) -> Self {
+        Self {
+            format,
+            width,
+            height,
+            min_filter: Filter::Nearest,
+            mag_filter: Filter::Nearest,
+            wrap_s: Wrap::ClampToEdge,
+            wrap_t: Wrap::ClampToEdge,
+            mipmaps: None,
+        }
+    }
+}
+
 impl VertexBuffer {
+    /// Replaces the contents of the buffer, reallocating it only when it needs to grow or is a
+    /// `Stream` buffer.
     pub unsafe fn write<V: AsBytes>(&mut self, vertices: &[V]) {
+        let data = vertices.as_bytes();
         self.len = vertices.len();
-        self.context.bind_vertex_array(Some(*self.vertex_array));
-        self.context
-            .bind_buffer(glow::ARRAY_BUFFER, Some(*self.buffer));
-        self.context.buffer_data_u8_slice(
-            glow::ARRAY_BUFFER,
-            vertices.as_bytes(),
-            glow::STATIC_DRAW,
-        );
+        let backend = &self.buffer.context.backend;
+        if let Some(size) = self.storage.allocation_for(data.len()) {
+            backend.allocate_vertices(self.buffer.handle, size, self.storage.usage);
+        }
+        if !data.is_empty() {
+            backend.write_vertices(self.buffer.handle, 0, data);
+        }
+    }
+}
+
+impl IndexBuffer {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    // u16 indices, WebGL1 only supports 32 bit indices through an extension
+    pub unsafe fn write(&mut self, indices: &[u16]) {
+        self.len = indices.len();
+        let backend = &self.buffer.context.backend;
+        if let Some(len) = self.storage.allocation_for(indices.len()) {
+            backend.allocate_indices(self.buffer.handle, len, self.storage.usage);
+        }
+        if !indices.is_empty() {
+            backend.write_indices(self.buffer.handle, 0, indices);
+        }
+    }
+}
+
+impl RenderTarget {
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
     }
 }
 
 impl Texture {
+    /// Writes pixel data laid out as the texture's format, float channels as their bytes.
     pub unsafe fn write(&mut self, x: u32, y: u32, width: u32, height: u32, data: &[u8]) {
-        self.context
-            .bind_texture(glow::TEXTURE_2D, Some(*self.texture_id));
-        self.context.tex_sub_image_2d_u8_slice(
-            glow::TEXTURE_2D,
-            0,
-            x as i32,
-            y as i32,
-            width as i32,
-            height as i32,
-            match self.format {
-                TextureFormat::RFloat | TextureFormat::RInt => glow::RED,
-                TextureFormat::RGFloat | TextureFormat::RGInt => glow::RG,
-                TextureFormat::RGBFloat | TextureFormat::RGBInt => glow::RGB,
-                TextureFormat::BGRFloat | TextureFormat::BGRInt => glow::BGR,
-                TextureFormat::RGBAFloat | TextureFormat::RGBAInt => glow::RGBA,
-                TextureFormat::BGRAFloat | TextureFormat::BGRAInt => glow::BGRA,
-            },
-            glow::UNSIGNED_BYTE,
-            Some(data),
+        self.texture.context.backend.write_texture(
+            self.texture.handle,
+            (x, y, width, height),
+            data,
         );
     }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
 }
 
 #[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ShaderType {
     Vertex = glow::VERTEX_SHADER,
     Fragment = glow::FRAGMENT_SHADER,
 }
 
-struct VertexFormatInner {
-    stride: i32,
-    attributes: Vec<(VertexAttributeLocation, VertexAttributeInner)>,
-}
-
-struct VertexAttributeInner {
-    pub ty: VertexAttributeType,
-    pub size: u32,
-    pub offset: u32,
-}
-
 pub struct Program {
-    context: Rc<glow::Context>,
-    program_id: Rc<ProgramId>,
-    vertex_shader: Rc<ShaderId>,
-    fragment_shader: Rc<ShaderId>,
-    uniform_entry_types: Vec<UniformType>,
-    set_uniforms: Vec<(UniformLocationId, Option<SetUniformValue>)>,
-    vertex_format: VertexFormatInner,
+    program: Owned,
+    uniform_entries: Vec<(String, UniformType)>,
+    // textures are kept alive for as long as they are set
+    set_uniforms: Vec<Option<(UniformValue, Option<Rc<Owned>>)>>,
+    instanced: bool,
 }
 
 impl Program {
-    pub fn set_uniform(&mut self, index: usize, value: Uniform<'_>) -> Result<(), GLError> {
-        if index > self.set_uniforms.len() {
-            return Err(GLError(format!("Uniform index {} is out of range", index)));
-        }
-        if value.uniform_type() != self.uniform_entry_types[index] {
+    /// Sets the uniform declared as `name` in the program's descriptor, until it is set again.
+    pub fn set_uniform(&mut self, name: &str, value: Uniform<'_>) -> Result<(), GLError> {
+        let index = self
+            .uniform_entries
+            .iter()
+            .position(|(entry_name, _)| entry_name == name)
+            .ok_or_else(|| GLError(format!("Program has no uniform {}", name)))?;
+        let entry_type = self.uniform_entries[index].1;
+        if value.uniform_type() != entry_type {
             return Err(GLError(format!(
-                "Wrong uniform type. Expected: {:?} Got uniform of type: {:?}",
-                self.uniform_entry_types[index],
+                "Wrong type for uniform {}. Expected: {:?} Got uniform of type: {:?}",
+                name,
+                entry_type,
                 value.uniform_type()
             )));
         }
-        self.set_uniforms[index].1 = match value {
-            Uniform::Texture(texture) => Some(SetUniformValue::Texture(texture.texture_id.clone())),
-            Uniform::Int(x) => Some(SetUniformValue::Int(x)),
-            Uniform::Int2(x, y) => Some(SetUniformValue::Int2(x, y)),
-            Uniform::Int3(x, y, z) => Some(SetUniformValue::Int3(x, y, z)),
-            Uniform::Int4(x, y, z, w) => Some(SetUniformValue::Int4(x, y, z, w)),
-            Uniform::Float(x) => Some(SetUniformValue::Float(x)),
-            Uniform::Float2(x, y) => Some(SetUniformValue::Float2(x, y)),
-            Uniform::Float3(x, y, z) => Some(SetUniformValue::Float3(x, y, z)),
-            Uniform::Float4(x, y, z, w) => Some(SetUniformValue::Float4(x, y, z, w)),
-            Uniform::Mat2(m) => Some(SetUniformValue::Mat2(m)),
-            Uniform::Mat3(m) => Some(SetUniformValue::Mat3(m)),
-            Uniform::Mat4(m) => Some(SetUniformValue::Mat4(m)),
-        };
+        self.set_uniforms[index] = Some(match value {
+            Uniform::Texture(texture) => (
+                UniformValue::Texture(texture.texture.handle),
+                Some(texture.texture.clone()),
+            ),
+            Uniform::Int(x) => (UniformValue::Int(x), None),
+            Uniform::Int2(x, y) => (UniformValue::Int2(x, y), None),
+            Uniform::Int3(x, y, z) => (UniformValue::Int3(x, y, z), None),
+            Uniform::Int4(x, y, z, w) => (UniformValue::Int4(x, y, z, w), None),
+            Uniform::Float(x) => (UniformValue::Float(x), None),
+            Uniform::Float2(x, y) => (UniformValue::Float2(x, y), None),
+            Uniform::Float3(x, y, z) => (UniformValue::Float3(x, y, z), None),
+            Uniform::Float4(x, y, z, w) => (UniformValue::Float4(x, y, z, w), None),
+            Uniform::Mat2(m) => (UniformValue::Mat2(m), None),
+            Uniform::Mat3(m) => (UniformValue::Mat3(m), None),
+            Uniform::Mat4(m) => (UniformValue::Mat4(m), None),
+        });
 
         Ok(())
     }
 
-    pub unsafe fn render_vertices(&self, vertex_buffer: &VertexBuffer) -> Result<(), GLError> {
-        self.context
-            .blend_func(glow::ONE, glow::ONE_MINUS_SRC_ALPHA);
-        self.context.enable(glow::BLEND);
-
-        self.context
-            .bind_vertex_array(Some(*vertex_buffer.vertex_array));
-        self.context
-            .bind_buffer(glow::ARRAY_BUFFER, Some(*vertex_buffer.buffer));
-
-        self.context.use_program(Some(*self.program_id));
+    pub unsafe fn render_vertices(
+        &self,
+        vertex_buffer: &VertexBuffer,
+        state: &DrawState,
+    ) -> Result<(), GLError> {
+        self.draw(vertex_buffer, None, 0, vertex_buffer.len, None, state)
+    }
 
-        let mut texture_index = 0;
-        for (i, (location, uniform_value)) in self.set_uniforms.iter().enumerate() {
-            if uniform_value.is_none() {
-                return Err(GLError(format!("uniform {} is not set", i)));
-            }
-            match uniform_value.as_ref().unwrap() {
-                SetUniformValue::Texture(texture) => {
-                    self.context.active_texture(glow::TEXTURE0 + texture_index);
-                    self.context.bind_texture(glow::TEXTURE_2D, Some(**texture));
-                    self.context
-                        .uniform_1_i32(Some(location.clone()), texture_index as i32);
-                    texture_index += 1;
-                }
-                SetUniformValue::Int(x) => {
-                    self.context.uniform_1_i32(Some(location.clone()), *x);
-                }
-                SetUniformValue::Int2(x, y) => {
-                    self.context.uniform_2_i32(Some(location.clone()), *x, *y);
-                }
-                SetUniformValue::Int3(x, y, z) => {
-                    self.context
-                        .uniform_3_i32(Some(location.clone()), *x, *y, *z);
-                }
-                SetUniformValue::Int4(x, y, z, w) => {
-                    self.context
-                        .uniform_4_i32(Some(location.clone()), *x, *y, *z, *w);
-                }
-                SetUniformValue::Float(x) => {
-                    self.context.uniform_1_f32(Some(location.clone()), *x);
-                }
-                SetUniformValue::Float2(x, y) => {
-                    self.context.uniform_2_f32(Some(location.clone()), *x, *y);
-                }
-                SetUniformValue::Float3(x, y, z) => {
-                    self.context
-                        .uniform_3_f32(Some(location.clone()), *x, *y, *z);
-                }
-                SetUniformValue::Float4(x, y, z, w) => {
-                    self.context
-                        .uniform_4_f32(Some(location.clone()), *x, *y, *z, *w);
-                }
-                SetUniformValue::Mat2(m) => {
-                    self.context.uniform_matrix_2_f32_slice(
-                        Some(location.clone()),
-                        false,
-                        &[m[0][0], m[0][1], m[1][0], m[1][1]],
-                    );
-                }
-                SetUniformValue::Mat3(m) => {
-                    self.context.uniform_matrix_3_f32_slice(
-                        Some(location.clone()),
-                        false,
-                        &[
-                            m[0][0], m[0][1], m[0][2], m[1][0], m[1][1], m[1][2], m[2][0], m[2][1],
-                            m[2][2],
-                        ],
-                    );
-                }
-                SetUniformValue::Mat4(m) => {
-                    self.context.uniform_matrix_4_f32_slice(
-                        Some(location.clone()),
-                        false,
-                        &[
-                            m[0][0], m[0][1], m[0][2], m[0][3], m[1][0], m[1][1], m[1][2], m[1][3],
-                            m[2][0], m[2][1], m[2][2], m[2][3], m[3][0], m[3][1], m[3][2], m[3][3],
-                        ],
-                    );
-                }
-            }
+    /// Draws `count` indices starting at the beginning of `index_buffer`, with every index offset
+    /// by `base_vertex`. This lets one index buffer be reused for any range of the vertex buffer
+    /// even though indices are only 16 bits.
+    pub unsafe fn render_indexed(
+        &self,
+        vertex_buffer: &VertexBuffer,
+        index_buffer: &IndexBuffer,
+        base_vertex: usize,
+        count: usize,
+        state: &DrawState,
+    ) -> Result<(), GLError> {
+        if count > index_buffer.len {
+            return Err(GLError(format!(
+                "Drawing {} indices from an index buffer of length {}",
+                count, index_buffer.len
+            )));
         }
+        self.draw(
+            vertex_buffer,
+            Some(index_buffer.buffer.handle),
+            base_vertex,
+            count,
+            None,
+            state,
+        )
+    }
 
-        for (location, attribute) in self.vertex_format.attributes.iter() {
-            self.context.enable_vertex_attrib_array(*location);
-            self.context.vertex_attrib_pointer_f32(
-                *location,
-                attribute.size as i32,
-                match attribute.ty {
-                    VertexAttributeType::Float => glow::FLOAT,
-                    VertexAttributeType::Int => glow::BYTE,
-                    VertexAttributeType::Uint => glow::UNSIGNED_BYTE,
-                },
-                false,
-                self.vertex_format.stride,
-                attribute.offset as i32,
-            );
+    /// Draws `count` indices of `index_buffer` once for each of `instance_count` instances,
+    /// starting at `first_instance` of `instance_buffer`. Only for programs with instance
+    /// attributes, on contexts that support instancing.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn render_instanced(
+        &self,
+        vertex_buffer: &VertexBuffer,
+        index_buffer: &IndexBuffer,
+        count: usize,
+        instance_buffer: &VertexBuffer,
+        first_instance: usize,
+        instance_count: usize,
+        state: &DrawState,
+    ) -> Result<(), GLError> {
+        if !self.program.context.backend.supports_instancing() {
+            return Err(GLError("instancing is not supported".to_owned()));
+        }
+        if !self.instanced {
+            return Err(GLError("program has no instance attributes".to_owned()));
         }
+        if count > index_buffer.len {
+            return Err(GLError(format!(
+                "Drawing {} indices from an index buffer of length {}",
+                count, index_buffer.len
+            )));
+        }
+        if first_instance + instance_count > instance_buffer.len {
+            return Err(GLError(format!(
+                "Drawing instances {} to {} from an instance buffer of length {}",
+                first_instance,
+                first_instance + instance_count,
+                instance_buffer.len
+            )));
+        }
+        self.draw(
+            vertex_buffer,
+            Some(index_buffer.buffer.handle),
+            0,
+            count,
+            Some(Instances {
+                buffer: instance_buffer.buffer.handle,
+                first: first_instance,
+                count: instance_count,
+            }),
+            state,
+        )
+    }
 
-        self.context
-            .draw_arrays(glow::TRIANGLES, 0, vertex_buffer.len as i32);
+    unsafe fn draw(
+        &self,
+        vertex_buffer: &VertexBuffer,
+        index_buffer: Option<Handle>,
+        base_vertex: usize,
+        count: usize,
+        instances: Option<Instances>,
+        state: &DrawState,
+    ) -> Result<(), GLError> {
+        if self.instanced && instances.is_none() {
+            return Err(GLError(
+                "program with instance attributes drawn without instances".to_owned(),
+            ));
+        }
+        let uniforms = self
+            .set_uniforms
+            .iter()
+            .zip(self.uniform_entries.iter())
+            .map(|(uniform, (name, _))| match uniform {
+                Some((value, _)) => Ok(*value),
+                None => Err(GLError(format!("uniform {} is not set", name))),
+            })
+            .collect::<Result<Vec<_>, GLError>>()?;
+        self.program.context.backend.draw(&Draw {
+            program: self.program.handle,
+            uniforms,
+            vertex_buffer: vertex_buffer.buffer.handle,
+            index_buffer,
+            base_vertex,
+            count,
+            instances,
+            state: *state,
+        });
 
         Ok(())
     }
 }
 
-enum SetUniformValue {
-    Texture(Rc<TextureId>),
-    Int(i32),
-    Int2(i32, i32),
-    Int3(i32, i32, i32),
-    Int4(i32, i32, i32, i32),
-    Float(f32),
-    Float2(f32, f32),
-    Float3(f32, f32, f32),
-    Float4(f32, f32, f32, f32),
-    Mat2([[f32; 2]; 2]),
-    Mat3([[f32; 3]; 3]),
-    Mat4([[f32; 4]; 4]),
-}
-
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum UniformType {
     Texture,
@@ -544,7 +871,7 @@ pub struct UniformEntry<'a> {
     pub ty: UniformType,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum VertexAttributeType {
     Int,
     Uint,
@@ -559,16 +886,377 @@ pub struct VertexAttribute<'a> {
     pub offset: u32,
 }
 
-type VertexAttributeLocation = u32;
-
 pub struct VertexFormat<'a> {
     pub stride: usize,
     pub attributes: &'a [VertexAttribute<'a>],
 }
 
+impl VertexFormat<'static> {
+    pub fn of<V: VertexLayout>() -> Self {
+        VertexFormat {
+            stride: std::mem::size_of::<V>(),
+            attributes: V::ATTRIBUTES,
+        }
+    }
+}
+
+/// A vertex whose fields are its attributes, implemented with `vertex_layout!`.
+pub trait VertexLayout: AsBytes {
+    const ATTRIBUTES: &'static [VertexAttribute<'static>];
+}
+
+/// Field types a vertex attribute can be read from. Bytes are read as 0 to 1, or -1 to 1 when
+/// signed.
+pub trait AttributeData {
+    const TYPE: VertexAttributeType;
+    const SIZE: u32;
+}
+
+macro_rules! attribute_data {
+    ($($data:ty => $ty:ident, $size:expr;)*) => {
+        $(impl AttributeData for $data {
+            const TYPE: VertexAttributeType = VertexAttributeType::$ty;
+            const SIZE: u32 = $size;
+        })*
+    };
+}
+
+attribute_data! {
+    f32 => Float, 1;
+    [f32; 2] => Float, 2;
+    [f32; 3] => Float, 3;
+    [f32; 4] => Float, 4;
+    [i8; 4] => Int, 4;
+    [u8; 4] => Uint, 4;
+}
+
+/// Declares a vertex struct and implements `VertexLayout` for it, each field followed by the
+/// name of the attribute it is read as. The struct is `#[repr(C)]` and derives `AsBytes`,
+/// which rules out padding, so every field starts where the previous one ends.
+///
+/// ```ignore
+/// vertex_layout! {
+///     #[derive(Clone, Copy, Debug)]
+///     pub struct Vertex {
+///         pub position: [f32; 2] => "a_pos",
+///         pub color: [f32; 4] => "a_color",
+///     }
+/// }
+/// ```
+macro_rules! vertex_layout {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $field_vis:vis $field:ident: $ty:ty => $attribute:literal
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[repr(C)]
+        #[derive(zerocopy::AsBytes)]
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $crate::gl::VertexLayout for $name {
+            const ATTRIBUTES: &'static [$crate::gl::VertexAttribute<'static>] =
+                $crate::gl::vertex_layout!(@attributes [] [0] $($ty => $attribute,)*);
+        }
+    };
+    (@attributes [$($done:expr,)*] [$offset:expr]) => {
+        &[$($done,)*]
+    };
+    (@attributes [$($done:expr,)*] [$offset:expr] $ty:ty => $attribute:literal, $($rest:tt)*) => {
+        $crate::gl::vertex_layout!(
+            @attributes
+            [
+                $($done,)*
+                $crate::gl::VertexAttribute {
+                    name: $attribute,
+                    ty: <$ty as $crate::gl::AttributeData>::TYPE,
+                    size: <$ty as $crate::gl::AttributeData>::SIZE,
+                    offset: ($offset) as u32,
+                },
+            ]
+            [$offset + std::mem::size_of::<$ty>()]
+            $($rest)*
+        )
+    };
+}
+pub(crate) use vertex_layout;
+
 pub struct ProgramDescriptor<'a> {
     pub vertex_shader: &'a Shader,
     pub fragment_shader: &'a Shader,
     pub uniforms: &'a [UniformEntry<'a>],
     pub vertex_format: VertexFormat<'a>,
+    /// Attributes read once per instance, from the instance buffer given to
+    /// `Program::render_instanced`. Programs with instance attributes can only draw instanced.
+    pub instance_format: Option<VertexFormat<'a>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BufferUsage, Call, CallLog, Context, DrawState, Filter, Handle, Program, ProgramDescriptor,
+        Recorder, ShaderType, TextureDescriptor, TextureFormat, Uniform, UniformEntry, UniformType,
+        VertexFormat, Wrap,
+    };
+
+    fn writes(usage: BufferUsage, lens: &[usize]) -> Vec<Call> {
+        let recorder = Recorder::new();
+        let calls = recorder.calls();
+        let mut context = Context::new(Box::new(recorder));
+        unsafe {
+            let mut buffer = context.create_index_buffer(usage).unwrap();
+            calls.replace(Vec::new());
+            for &len in lens {
+                buffer.write(&vec![0; len]);
+            }
+        }
+        calls.replace(Vec::new())
+    }
+
+    fn allocate(len: usize, usage: BufferUsage) -> Call {
+        Call::AllocateIndices {
+            buffer: Handle(0),
+            len,
+            usage,
+        }
+    }
+
+    fn write(len: usize) -> Call {
+        Call::WriteIndices {
+            buffer: Handle(0),
+            first: 0,
+            len,
+        }
+    }
+
+    #[test]
+    fn static_buffers_are_allocated_to_fit() {
+        use BufferUsage::Static;
+        assert_eq!(
+            writes(Static, &[6, 5, 9]),
+            vec![
+                allocate(6, Static),
+                write(6),
+                write(5),
+                allocate(9, Static),
+                write(9)
+            ]
+        );
+    }
+
+    #[test]
+    fn dynamic_buffers_grow_to_powers_of_two() {
+        use BufferUsage::Dynamic;
+        assert_eq!(
+            writes(Dynamic, &[6, 8, 0, 9]),
+            vec![
+                allocate(8, Dynamic),
+                write(6),
+                write(8),
+                allocate(16, Dynamic),
+                write(9)
+            ]
+        );
+    }
+
+    #[test]
+    fn stream_buffers_are_orphaned_on_every_write() {
+        use BufferUsage::Stream;
+        assert_eq!(
+            writes(Stream, &[6, 5, 0, 9]),
+            vec![
+                allocate(8, Stream),
+                write(6),
+                allocate(8, Stream),
+                write(5),
+                allocate(16, Stream),
+                write(9)
+            ]
+        );
+    }
+
+    unsafe fn program(context: &mut Context, uniforms: &[UniformEntry]) -> Result<Program, String> {
+        let vertex_shader = context.create_shader(ShaderType::Vertex, "").unwrap();
+        let fragment_shader = context.create_shader(ShaderType::Fragment, "").unwrap();
+        context
+            .create_program(&ProgramDescriptor {
+                vertex_shader: &vertex_shader,
+                fragment_shader: &fragment_shader,
+                uniforms,
+                vertex_format: VertexFormat {
+                    stride: 0,
+                    attributes: &[],
+                },
+                instance_format: None,
+            })
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn uniforms_are_set_by_name() {
+        let mut context = Context::new(Box::new(Recorder::new()));
+        let uniform = |name| UniformEntry {
+            name,
+            ty: UniformType::Float,
+        };
+        unsafe {
+            assert_eq!(
+                program(&mut context, &[uniform("u_time"), uniform("u_time")]).err(),
+                Some("OpenGL error: uniform u_time is declared twice".to_owned())
+            );
+
+            let mut program =
+                program(&mut context, &[uniform("u_time"), uniform("u_speed")]).unwrap();
+            let mut buffer = context.create_vertex_buffer(BufferUsage::Static).unwrap();
+            buffer.write(&[0u8; 4]);
+            program.set_uniform("u_time", Uniform::Float(1.)).unwrap();
+            assert_eq!(
+                program
+                    .render_vertices(&buffer, &DrawState::default())
+                    .unwrap_err()
+                    .to_string(),
+                "OpenGL error: uniform u_speed is not set"
+            );
+            assert_eq!(
+                program
+                    .set_uniform("u_speed", Uniform::Int(1))
+                    .unwrap_err()
+                    .to_string(),
+                "OpenGL error: Wrong type for uniform u_speed. Expected: Float Got uniform of type: Int"
+            );
+            assert!(program.set_uniform("u_sped", Uniform::Float(1.)).is_err());
+            program.set_uniform("u_speed", Uniform::Float(2.)).unwrap();
+            program
+                .render_vertices(&buffer, &DrawState::default())
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn textures_are_created_from_descriptors_and_read_back() {
+        let recorder = Recorder::new();
+        let calls = recorder.calls();
+        let mut context = Context::new(Box::new(recorder));
+        let desc = TextureDescriptor::new(TextureFormat::RGBAByte, 3, 2);
+        assert_eq!(
+            (desc.min_filter, desc.mag_filter, desc.mipmaps),
+            (Filter::Nearest, Filter::Nearest, None)
+        );
+        assert_eq!(
+            (desc.wrap_s, desc.wrap_t),
+            (Wrap::ClampToEdge, Wrap::ClampToEdge)
+        );
+        unsafe {
+            let mut texture = context.create_texture(&desc).unwrap();
+            texture.write(1, 0, 2, 2, &[255; 16]);
+            let pixels = context.read_texture(&texture).unwrap();
+            assert_eq!(pixels.len(), 3 * 2 * 4);
+        }
+        assert_eq!(
+            calls.borrow()[..],
+            [
+                Call::CreateTexture {
+                    texture: Handle(0),
+                    desc
+                },
+                Call::WriteTexture {
+                    texture: Handle(0),
+                    rect: (1, 0, 2, 2)
+                },
+                Call::ReadTexture(Handle(0)),
+            ]
+        );
+    }
+
+    fn deleted(calls: &CallLog) -> Vec<Handle> {
+        calls
+            .borrow()
+            .iter()
+            .filter_map(|call| match call {
+                Call::Delete(handle) => Some(*handle),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dropped_resources_are_deleted_at_maintain() {
+        let recorder = Recorder::new();
+        let calls = recorder.calls();
+        let mut context = Context::new(Box::new(recorder));
+        unsafe {
+            let buffer = context.create_vertex_buffer(BufferUsage::Static).unwrap();
+            drop(buffer);
+            assert_eq!(deleted(&calls), []);
+            context.maintain();
+        }
+        assert_eq!(deleted(&calls), [Handle(0)]);
+    }
+
+    #[test]
+    fn destroyed_resources_are_deleted_right_away() {
+        let recorder = Recorder::new();
+        let calls = recorder.calls();
+        let mut context = Context::new(Box::new(recorder));
+        unsafe {
+            let dropped = context.create_index_buffer(BufferUsage::Static).unwrap();
+            let destroyed = context.create_index_buffer(BufferUsage::Static).unwrap();
+            drop(dropped);
+            context.destroy(destroyed);
+        }
+        // along with whatever was dropped before
+        assert_eq!(deleted(&calls), [Handle(0), Handle(1)]);
+    }
+
+    #[test]
+    fn resources_outliving_the_context_are_still_deleted() {
+        let recorder = Recorder::new();
+        let calls = recorder.calls();
+        let mut context = Context::new(Box::new(recorder));
+        let texture = unsafe {
+            context
+                .create_texture(&TextureDescriptor::new(TextureFormat::RGBAByte, 1, 1))
+                .unwrap()
+        };
+        drop(context);
+        assert_eq!(deleted(&calls), []);
+        drop(texture);
+        assert_eq!(deleted(&calls), [Handle(0)]);
+    }
+
+    #[test]
+    fn textures_set_on_programs_are_kept() {
+        let recorder = Recorder::new();
+        let calls = recorder.calls();
+        let mut context = Context::new(Box::new(recorder));
+        unsafe {
+            let texture_entry = UniformEntry {
+                name: "u_texture",
+                ty: UniformType::Texture,
+            };
+            let mut program = program(&mut context, &[texture_entry]).unwrap();
+            let texture = context
+                .create_texture(&TextureDescriptor::new(TextureFormat::RGBAByte, 1, 1))
+                .unwrap();
+            let handle = texture.texture.handle;
+            program
+                .set_uniform("u_texture", Uniform::Texture(&texture))
+                .unwrap();
+
+            context.destroy(texture);
+            assert!(!deleted(&calls).contains(&handle));
+            drop(program);
+            context.maintain();
+            assert!(deleted(&calls).contains(&handle));
+        }
+    }
 }
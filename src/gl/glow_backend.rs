@@ -0,0 +1,1149 @@
+use std::{cell::RefCell, mem};
+
+use glow::HasContext;
+use zerocopy::AsBytes;
+
+use super::{
+    Backend, BlendMode, BufferUsage, Draw, DrawState, Filter, Handle, Rect, ShaderType,
+    TextureDescriptor, TextureFormat, Topology, UniformEntry, UniformType, UniformValue,
+    VertexAttribute, VertexAttributeType, VertexFormat, Wrap,
+};
+
+type VertexArrayId = <glow::Context as glow::HasContext>::VertexArray;
+type BufferId = <glow::Context as glow::HasContext>::Buffer;
+type UniformLocationId = <glow::Context as glow::HasContext>::UniformLocation;
+type ProgramId = <glow::Context as glow::HasContext>::Program;
+type ShaderId = <glow::Context as glow::HasContext>::Shader;
+type TextureId = <glow::Context as glow::HasContext>::Texture;
+type FramebufferId = <glow::Context as glow::HasContext>::Framebuffer;
+
+/// Draws with OpenGL ES 2 or WebGL 1 through glow.
+pub struct GlowBackend {
+    context: glow::Context,
+    // indexed by handle, `None` once deleted
+    objects: RefCell<Vec<Option<Object>>>,
+    free_handles: RefCell<Vec<Handle>>,
+    state: RefCell<StateCache>,
+    // what `bind_render_target` last bound, `None` for the window
+    render_target: RefCell<Option<FramebufferId>>,
+    instancing: bool,
+    version: Version,
+}
+
+enum Object {
+    Shader(ShaderId),
+    Program {
+        program: ProgramId,
+        uniforms: Vec<UniformLocationId>,
+        vertex_format: VertexFormatInner,
+        instance_format: Option<VertexFormatInner>,
+    },
+    VertexBuffer {
+        vertex_array: VertexArrayId,
+        buffer: BufferId,
+    },
+    IndexBuffer(BufferId),
+    Texture {
+        texture: TextureId,
+        format: TextureFormat,
+        mipmaps: bool,
+    },
+    RenderTarget(FramebufferId),
+}
+
+struct VertexFormatInner {
+    stride: i32,
+    attributes: Vec<(u32, VertexAttributeInner)>,
+}
+
+struct VertexAttributeInner {
+    ty: VertexAttributeType,
+    size: u32,
+    offset: u32,
+}
+
+// What GL state is set to, `None` when unknown
+#[derive(Default)]
+struct StateCache {
+    blend_mode: Option<BlendMode>,
+    color_mask: Option<[bool; 4]>,
+    // the rect the scissor test clips to, `Some(None)` when it's disabled
+    scissor: Option<Option<Rect>>,
+    // what is drawn into, the viewport of the window or all of a render target
+    area: Rect,
+    // the window is always clipped to its viewport so the bars around it stay black
+    clip_to_area: bool,
+}
+
+// State to send to GL before a draw, `None` for what is already set
+#[derive(Debug, Default, PartialEq)]
+struct StateChanges {
+    blend_mode: Option<BlendMode>,
+    color_mask: Option<[bool; 4]>,
+    scissor: Option<Option<Rect>>,
+}
+
+impl StateCache {
+    // what of `state` differs from what GL is set to, assuming it is then sent
+    fn changes(&mut self, state: &DrawState) -> StateChanges {
+        let scissor = self.scissor_rect(state.scissor);
+        StateChanges {
+            blend_mode: changed(&mut self.blend_mode, state.blend_mode),
+            color_mask: changed(&mut self.color_mask, state.color_mask),
+            scissor: changed(&mut self.scissor, scissor),
+        }
+    }
+
+    // clears don't blend, but are masked and clipped like draws
+    fn clear_changes(&mut self) -> StateChanges {
+        let scissor = self.scissor_rect(None);
+        StateChanges {
+            blend_mode: None,
+            color_mask: changed(&mut self.color_mask, [true; 4]),
+            scissor: changed(&mut self.scissor, scissor),
+        }
+    }
+
+    // what the scissor test clips `scissor` within the area to, `None` to disable it
+    fn scissor_rect(&self, scissor: Option<Rect>) -> Option<Rect> {
+        let (x, y, width, height) = self.area;
+        match scissor {
+            Some((sx, sy, sw, sh)) => {
+                // clamped to the area, GL would happily clip outside of it
+                let left = (x + sx).max(x);
+                let bottom = (y + sy).max(y);
+                let right = (x + sx + sw as i32).min(x + width as i32).max(left);
+                let top = (y + sy + sh as i32).min(y + height as i32).max(bottom);
+                Some((left, bottom, (right - left) as u32, (top - bottom) as u32))
+            }
+            None if self.clip_to_area => Some(self.area),
+            None => None,
+        }
+    }
+}
+
+// `value` if the cache holds something else, then caches it
+fn changed<T: Copy + PartialEq>(cached: &mut Option<T>, value: T) -> Option<T> {
+    if *cached == Some(value) {
+        return None;
+    }
+    *cached = Some(value);
+    Some(value)
+}
+
+impl GlowBackend {
+    /// `instancing` is whether the context can draw instanced, which glow only knows for
+    /// WebGL 1 where it enables `ANGLE_instanced_arrays` if it's there.
+    pub fn new(context: glow::Context, instancing: bool) -> Self {
+        let version = Version::of(&context);
+        unsafe {
+            // every draw blends, `DrawState` only picks how
+            context.enable(glow::BLEND);
+            // pixel rows are tightly packed whatever the format, GL pads them to 4 bytes by default
+            context.pixel_store_i32(glow::UNPACK_ALIGNMENT, 1);
+            context.pixel_store_i32(glow::PACK_ALIGNMENT, 1);
+        }
+        Self {
+            context,
+            objects: RefCell::new(Vec::new()),
+            free_handles: RefCell::new(Vec::new()),
+            state: RefCell::new(StateCache::default()),
+            render_target: RefCell::new(None),
+            instancing,
+            version,
+        }
+    }
+
+    fn insert(&self, object: Object) -> Handle {
+        let mut objects = self.objects.borrow_mut();
+        match self.free_handles.borrow_mut().pop() {
+            Some(handle) => {
+                objects[handle.0 as usize] = Some(object);
+                handle
+            }
+            None => {
+                objects.push(Some(object));
+                Handle(objects.len() as u32 - 1)
+            }
+        }
+    }
+
+    unsafe fn bind_vertex_buffer(&self, handle: Handle) {
+        let (vertex_array, buffer) = match self.objects.borrow()[handle.0 as usize] {
+            Some(Object::VertexBuffer {
+                vertex_array,
+                buffer,
+            }) => (vertex_array, buffer),
+            _ => panic!("{:?} is not a vertex buffer", handle),
+        };
+        self.context.bind_vertex_array(Some(vertex_array));
+        self.context.bind_buffer(glow::ARRAY_BUFFER, Some(buffer));
+    }
+
+    unsafe fn bind_index_buffer(&self, handle: Handle) {
+        let buffer = match self.objects.borrow()[handle.0 as usize] {
+            Some(Object::IndexBuffer(buffer)) => buffer,
+            _ => panic!("{:?} is not an index buffer", handle),
+        };
+        self.context
+            .bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(buffer));
+    }
+
+    fn shader(&self, handle: Handle) -> ShaderId {
+        match self.objects.borrow()[handle.0 as usize] {
+            Some(Object::Shader(shader)) => shader,
+            _ => panic!("{:?} is not a shader", handle),
+        }
+    }
+
+    fn texture(&self, handle: Handle) -> TextureId {
+        match self.objects.borrow()[handle.0 as usize] {
+            Some(Object::Texture { texture, .. }) => texture,
+            _ => panic!("{:?} is not a texture", handle),
+        }
+    }
+
+    // compares what the linked program uses with what it will be given, since GL silently
+    // ignores uniforms and attributes that aren't set or aren't used
+    unsafe fn check_interface(
+        &self,
+        program: ProgramId,
+        uniforms: &[UniformEntry],
+        vertex_format: &VertexFormat,
+        instance_format: Option<&VertexFormat>,
+    ) -> Result<(), String> {
+        let active_uniforms: Vec<_> = (0..self.context.get_active_uniforms(program))
+            .filter_map(|i| self.context.get_active_uniform(program, i))
+            .collect();
+        for entry in uniforms {
+            let active = active_uniforms
+                .iter()
+                .find(|active| array_name(&active.name) == entry.name)
+                .ok_or_else(|| format!("uniform {} is not used by the shaders", entry.name))?;
+            if !uniform_types(entry.ty).contains(&active.utype) {
+                return Err(format!(
+                    "uniform {} is declared as {:?} but the shaders use {}",
+                    entry.name,
+                    entry.ty,
+                    gl_type_name(active.utype)
+                ));
+            }
+        }
+        for active in active_uniforms.iter() {
+            let name = array_name(&active.name);
+            if !uniforms.iter().any(|entry| entry.name == name) {
+                return Err(format!(
+                    "uniform {} is used by the shaders but not declared",
+                    name
+                ));
+            }
+        }
+
+        let attributes: Vec<&VertexAttribute> = vertex_format
+            .attributes
+            .iter()
+            .chain(
+                instance_format
+                    .into_iter()
+                    .flat_map(|format| format.attributes),
+            )
+            .collect();
+        let active_attributes: Vec<_> = (0..self.context.get_active_attributes(program))
+            .filter_map(|i| self.context.get_active_attribute(program, i))
+            .filter(|active| !active.name.starts_with("gl_"))
+            .collect();
+        for attribute in attributes.iter() {
+            let active = active_attributes
+                .iter()
+                .find(|active| active.name == attribute.name)
+                .ok_or_else(|| {
+                    format!("attribute {} is not used by the shaders", attribute.name)
+                })?;
+            if !attribute_types(attribute.size).contains(&active.atype) {
+                return Err(format!(
+                    "attribute {} has {} components but the shaders use {}",
+                    attribute.name,
+                    attribute.size,
+                    gl_type_name(active.atype)
+                ));
+            }
+        }
+        for active in active_attributes.iter() {
+            if !attributes
+                .iter()
+                .any(|attribute| attribute.name == active.name)
+            {
+                return Err(format!(
+                    "attribute {} is used by the shaders but not in the vertex format",
+                    active.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn locate_attributes(
+        &self,
+        program: ProgramId,
+        format: &VertexFormat,
+    ) -> Result<VertexFormatInner, String> {
+        let attributes = format
+            .attributes
+            .iter()
+            .map(|attr_desc| {
+                let location = self
+                    .context
+                    .get_attrib_location(program, attr_desc.name)
+                    .ok_or_else(|| {
+                        format!("could not get location of attribute {}", attr_desc.name)
+                    })?;
+                let attribute = VertexAttributeInner {
+                    ty: attr_desc.ty,
+                    size: attr_desc.size,
+                    offset: attr_desc.offset,
+                };
+                Ok((location, attribute))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(VertexFormatInner {
+            stride: format.stride as i32,
+            attributes,
+        })
+    }
+
+    // points the format's attributes at the bound array buffer, starting at element `first` and
+    // advancing every `divisor` instances, or every vertex for 0
+    unsafe fn bind_attributes(&self, format: &VertexFormatInner, first: usize, divisor: u32) {
+        for (location, attribute) in format.attributes.iter() {
+            self.context.enable_vertex_attrib_array(*location);
+            self.context.vertex_attrib_pointer_f32(
+                *location,
+                attribute.size as i32,
+                match attribute.ty {
+                    VertexAttributeType::Float => glow::FLOAT,
+                    VertexAttributeType::Int => glow::BYTE,
+                    VertexAttributeType::Uint => glow::UNSIGNED_BYTE,
+                },
+                // bytes are read as 0 to 1, or -1 to 1 when signed
+                !matches!(attribute.ty, VertexAttributeType::Float),
+                format.stride,
+                (first as i32 * format.stride) + attribute.offset as i32,
+            );
+            // divisors stay with the vertex array, which another program may have instanced with
+            if self.instancing {
+                self.context.vertex_attrib_divisor(*location, divisor);
+            }
+        }
+    }
+
+    // attaches `texture` to a new framebuffer, which is left bound
+    unsafe fn attach(&self, texture: TextureId) -> Result<FramebufferId, String> {
+        let framebuffer = self.context.create_framebuffer()?;
+        self.context
+            .bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+        self.context.framebuffer_texture_2d(
+            glow::FRAMEBUFFER,
+            glow::COLOR_ATTACHMENT0,
+            glow::TEXTURE_2D,
+            Some(texture),
+            0,
+        );
+        Ok(framebuffer)
+    }
+
+    // sends the parts of `state` that changed since the last draw
+    unsafe fn apply(&self, state: &DrawState) {
+        let changes = self.state.borrow_mut().changes(state);
+        self.send(changes);
+    }
+
+    unsafe fn send(&self, changes: StateChanges) {
+        match changes.blend_mode {
+            Some(BlendMode::Premultiplied) => self
+                .context
+                .blend_func(glow::ONE, glow::ONE_MINUS_SRC_ALPHA),
+            Some(BlendMode::Additive) => self.context.blend_func(glow::ONE, glow::ONE),
+            Some(BlendMode::Multiply) => self.context.blend_func(glow::DST_COLOR, glow::ZERO),
+            None => {}
+        }
+        if let Some([r, g, b, a]) = changes.color_mask {
+            self.context.color_mask(r, g, b, a);
+        }
+        match changes.scissor {
+            Some(Some((x, y, width, height))) => {
+                self.context.enable(glow::SCISSOR_TEST);
+                self.context.scissor(x, y, width as i32, height as i32);
+            }
+            Some(None) => self.context.disable(glow::SCISSOR_TEST),
+            None => {}
+        }
+    }
+
+    unsafe fn set_uniform(
+        &self,
+        location: &UniformLocationId,
+        value: &UniformValue,
+        unit: &mut u32,
+    ) {
+        // uniform locations are only `Copy` on native
+        #[allow(clippy::clone_on_copy)]
+        let location = Some(location.clone());
+        match *value {
+            UniformValue::Texture(texture) => {
+                self.context.active_texture(glow::TEXTURE0 + *unit);
+                self.context
+                    .bind_texture(glow::TEXTURE_2D, Some(self.texture(texture)));
+                self.context.uniform_1_i32(location, *unit as i32);
+                *unit += 1;
+            }
+            UniformValue::Int(x) => self.context.uniform_1_i32(location, x),
+            UniformValue::Int2(x, y) => self.context.uniform_2_i32(location, x, y),
+            UniformValue::Int3(x, y, z) => self.context.uniform_3_i32(location, x, y, z),
+            UniformValue::Int4(x, y, z, w) => self.context.uniform_4_i32(location, x, y, z, w),
+            UniformValue::Float(x) => self.context.uniform_1_f32(location, x),
+            UniformValue::Float2(x, y) => self.context.uniform_2_f32(location, x, y),
+            UniformValue::Float3(x, y, z) => self.context.uniform_3_f32(location, x, y, z),
+            UniformValue::Float4(x, y, z, w) => self.context.uniform_4_f32(location, x, y, z, w),
+            UniformValue::Mat2(m) => self.context.uniform_matrix_2_f32_slice(
+                location,
+                false,
+                &[m[0][0], m[0][1], m[1][0], m[1][1]],
+            ),
+            UniformValue::Mat3(m) => self.context.uniform_matrix_3_f32_slice(
+                location,
+                false,
+                &[
+                    m[0][0], m[0][1], m[0][2], m[1][0], m[1][1], m[1][2], m[2][0], m[2][1], m[2][2],
+                ],
+            ),
+            UniformValue::Mat4(m) => self.context.uniform_matrix_4_f32_slice(
+                location,
+                false,
+                &[
+                    m[0][0], m[0][1], m[0][2], m[0][3], m[1][0], m[1][1], m[1][2], m[1][3],
+                    m[2][0], m[2][1], m[2][2], m[2][3], m[3][0], m[3][1], m[3][2], m[3][3],
+                ],
+            ),
+        }
+    }
+}
+
+impl Backend for GlowBackend {
+    fn supports_instancing(&self) -> bool {
+        self.instancing
+    }
+
+    unsafe fn create_shader(
+        &self,
+        shader_type: ShaderType,
+        source: &str,
+    ) -> Result<Handle, String> {
+        let shader = self.context.create_shader(shader_type as u32)?;
+        self.context.shader_source(shader, source);
+        self.context.compile_shader(shader);
+        if !self.context.get_shader_compile_status(shader) {
+            let log = self.context.get_shader_info_log(shader);
+            self.context.delete_shader(shader);
+            return Err(log);
+        }
+        Ok(self.insert(Object::Shader(shader)))
+    }
+
+    unsafe fn create_program(
+        &self,
+        vertex_shader: Handle,
+        fragment_shader: Handle,
+        uniforms: &[UniformEntry],
+        vertex_format: &VertexFormat,
+        instance_format: Option<&VertexFormat>,
+    ) -> Result<Handle, String> {
+        if instance_format.is_some() && !self.instancing {
+            return Err("instancing is not supported".to_owned());
+        }
+        let program = self.context.create_program()?;
+        // attached shaders are only deleted along with the program, it doesn't need to keep them
+        self.context
+            .attach_shader(program, self.shader(vertex_shader));
+        self.context
+            .attach_shader(program, self.shader(fragment_shader));
+        self.context.link_program(program);
+        let locations = if self.context.get_program_link_status(program) {
+            Ok(())
+        } else {
+            Err(self.context.get_program_info_log(program))
+        }
+        .and_then(|()| self.check_interface(program, uniforms, vertex_format, instance_format))
+        .and_then(|()| {
+            let uniforms = uniforms
+                .iter()
+                .map(|entry| {
+                    self.context
+                        .get_uniform_location(program, entry.name)
+                        .ok_or_else(|| format!("could not get location for uniform {}", entry.name))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            let vertex_format = self.locate_attributes(program, vertex_format)?;
+            let instance_format = instance_format
+                .map(|format| self.locate_attributes(program, format))
+                .transpose()?;
+            Ok((uniforms, vertex_format, instance_format))
+        });
+        let (uniforms, vertex_format, instance_format) = match locations {
+            Ok(locations) => locations,
+            Err(e) => {
+                self.context.delete_program(program);
+                return Err(e);
+            }
+        };
+
+        Ok(self.insert(Object::Program {
+            program,
+            uniforms,
+            vertex_format,
+            instance_format,
+        }))
+    }
+
+    unsafe fn create_vertex_buffer(&self) -> Result<Handle, String> {
+        let vertex_array = self.context.create_vertex_array()?;
+        let buffer = match self.context.create_buffer() {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                self.context.delete_vertex_array(vertex_array);
+                return Err(e);
+            }
+        };
+        Ok(self.insert(Object::VertexBuffer {
+            vertex_array,
+            buffer,
+        }))
+    }
+
+    unsafe fn create_index_buffer(&self) -> Result<Handle, String> {
+        let buffer = self.context.create_buffer()?;
+        Ok(self.insert(Object::IndexBuffer(buffer)))
+    }
+
+    unsafe fn create_texture(&self, desc: &TextureDescriptor) -> Result<Handle, String> {
+        let texture = self.context.create_texture()?;
+        self.context.bind_texture(glow::TEXTURE_2D, Some(texture));
+        let min_filter = match (desc.min_filter, desc.mipmaps) {
+            (Filter::Nearest, None) => glow::NEAREST,
+            (Filter::Linear, None) => glow::LINEAR,
+            (Filter::Nearest, Some(Filter::Nearest)) => glow::NEAREST_MIPMAP_NEAREST,
+            (Filter::Linear, Some(Filter::Nearest)) => glow::LINEAR_MIPMAP_NEAREST,
+            (Filter::Nearest, Some(Filter::Linear)) => glow::NEAREST_MIPMAP_LINEAR,
+            (Filter::Linear, Some(Filter::Linear)) => glow::LINEAR_MIPMAP_LINEAR,
+        };
+        self.context.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MIN_FILTER,
+            min_filter as i32,
+        );
+        self.context.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MAG_FILTER,
+            gl_filter(desc.mag_filter) as i32,
+        );
+        self.context.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_WRAP_S,
+            gl_wrap(desc.wrap_s) as i32,
+        );
+        self.context.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_WRAP_T,
+            gl_wrap(desc.wrap_t) as i32,
+        );
+
+        let (internal_format, format, ty) = match gl_formats(desc.format, self.version) {
+            Some(formats) => formats,
+            None => {
+                self.context.delete_texture(texture);
+                return Err(format!(
+                    "{:?} textures are not supported by {:?}",
+                    desc.format, self.version
+                ));
+            }
+        };
+        self.context.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            internal_format as i32,
+            desc.width as i32,
+            desc.height as i32,
+            0,
+            format,
+            ty,
+            None,
+        );
+
+        Ok(self.insert(Object::Texture {
+            texture,
+            format: desc.format,
+            mipmaps: desc.mipmaps.is_some(),
+        }))
+    }
+
+    unsafe fn create_render_target(&self, texture: Handle) -> Result<Handle, String> {
+        let framebuffer = self.attach(self.texture(texture))?;
+        let status = self.context.check_framebuffer_status(glow::FRAMEBUFFER);
+        self.context
+            .bind_framebuffer(glow::FRAMEBUFFER, *self.render_target.borrow());
+        if status != glow::FRAMEBUFFER_COMPLETE {
+            self.context.delete_framebuffer(framebuffer);
+            return Err(format!("framebuffer is incomplete, status {:#x}", status));
+        }
+        Ok(self.insert(Object::RenderTarget(framebuffer)))
+    }
+
+    unsafe fn allocate_vertices(&self, buffer: Handle, size: usize, usage: BufferUsage) {
+        self.bind_vertex_buffer(buffer);
+        self.context
+            .buffer_data_size(glow::ARRAY_BUFFER, size as i32, gl_usage(usage));
+    }
+
+    unsafe fn write_vertices(&self, buffer: Handle, offset: usize, data: &[u8]) {
+        self.bind_vertex_buffer(buffer);
+        self.context
+            .buffer_sub_data_u8_slice(glow::ARRAY_BUFFER, offset as i32, data);
+    }
+
+    unsafe fn allocate_indices(&self, buffer: Handle, len: usize, usage: BufferUsage) {
+        self.bind_index_buffer(buffer);
+        self.context.buffer_data_size(
+            glow::ELEMENT_ARRAY_BUFFER,
+            (len * mem::size_of::<u16>()) as i32,
+            gl_usage(usage),
+        );
+    }
+
+    unsafe fn write_indices(&self, buffer: Handle, first: usize, indices: &[u16]) {
+        self.bind_index_buffer(buffer);
+        self.context.buffer_sub_data_u8_slice(
+            glow::ELEMENT_ARRAY_BUFFER,
+            (first * mem::size_of::<u16>()) as i32,
+            indices.as_bytes(),
+        );
+    }
+
+    unsafe fn write_texture(&self, texture: Handle, rect: (u32, u32, u32, u32), data: &[u8]) {
+        let (texture, format, mipmaps) = match self.objects.borrow()[texture.0 as usize] {
+            Some(Object::Texture {
+                texture,
+                format,
+                mipmaps,
+            }) => (texture, format, mipmaps),
+            _ => panic!("{:?} is not a texture", texture),
+        };
+        // only textures with supported formats are created
+        let (_, format, ty) = gl_formats(format, self.version).unwrap();
+        let (x, y, width, height) = rect;
+        self.context.bind_texture(glow::TEXTURE_2D, Some(texture));
+        self.context.tex_sub_image_2d_u8_slice(
+            glow::TEXTURE_2D,
+            0,
+            x as i32,
+            y as i32,
+            width as i32,
+            height as i32,
+            format,
+            ty,
+            Some(data),
+        );
+        if mipmaps {
+            self.context.generate_mipmap(glow::TEXTURE_2D);
+        }
+    }
+
+    unsafe fn read_texture(
+        &self,
+        texture: Handle,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<u8>, String> {
+        let (gl_texture, format) = match self.objects.borrow()[texture.0 as usize] {
+            Some(Object::Texture {
+                texture, format, ..
+            }) => (texture, format),
+            _ => panic!("{:?} is not a texture", texture),
+        };
+        if is_integer(format) {
+            return Err("integer textures can't be read back as RGBA8".to_owned());
+        }
+        // GLES can't read textures directly, only what they are attached to
+        let framebuffer = self.attach(gl_texture)?;
+        let status = self.context.check_framebuffer_status(glow::FRAMEBUFFER);
+        let pixels = if status != glow::FRAMEBUFFER_COMPLETE {
+            Err(format!(
+                "texture can't be read back, framebuffer status {:#x}",
+                status
+            ))
+        } else if is_float(format) {
+            // GLES only reads float buffers as floats
+            let mut floats = vec![0; (width * height * 4 * 4) as usize];
+            self.context.read_pixels(
+                0,
+                0,
+                width as i32,
+                height as i32,
+                glow::RGBA,
+                glow::FLOAT,
+                &mut floats,
+            );
+            Ok(floats
+                .chunks_exact(4)
+                .map(|bytes| {
+                    let x = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                    (x.clamp(0., 1.) * 255.).round() as u8
+                })
+                .collect())
+        } else {
+            Ok(self.read_pixels((0, 0, width, height)))
+        };
+        self.context
+            .bind_framebuffer(glow::FRAMEBUFFER, *self.render_target.borrow());
+        self.context.delete_framebuffer(framebuffer);
+        pixels
+    }
+
+    unsafe fn bind_render_target(&self, target: Option<Handle>) {
+        let framebuffer = target.map(|target| match self.objects.borrow()[target.0 as usize] {
+            Some(Object::RenderTarget(framebuffer)) => framebuffer,
+            _ => panic!("{:?} is not a render target", target),
+        });
+        *self.render_target.borrow_mut() = framebuffer;
+        self.context
+            .bind_framebuffer(glow::FRAMEBUFFER, framebuffer);
+    }
+
+    unsafe fn set_viewport(&self, area: Rect, clip_to_area: bool) {
+        let (x, y, width, height) = area;
+        self.context.viewport(x, y, width as i32, height as i32);
+        let mut cache = self.state.borrow_mut();
+        cache.area = area;
+        cache.clip_to_area = clip_to_area;
+    }
+
+    unsafe fn clear(&self, color: [f32; 4]) {
+        let changes = self.state.borrow_mut().clear_changes();
+        self.send(changes);
+        self.context
+            .clear_color(color[0], color[1], color[2], color[3]);
+        self.context.clear(glow::COLOR_BUFFER_BIT);
+    }
+
+    unsafe fn read_pixels(&self, rect: Rect) -> Vec<u8> {
+        let (x, y, width, height) = rect;
+        let mut pixels = vec![0; (width * height * 4) as usize];
+        self.context.read_pixels(
+            x,
+            y,
+            width as i32,
+            height as i32,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            &mut pixels,
+        );
+        pixels
+    }
+
+    unsafe fn draw(&self, draw: &Draw) {
+        let objects = self.objects.borrow();
+        let (program, uniforms, vertex_format, instance_format) =
+            match &objects[draw.program.0 as usize] {
+                Some(Object::Program {
+                    program,
+                    uniforms,
+                    vertex_format,
+                    instance_format,
+                }) => (*program, uniforms, vertex_format, instance_format),
+                _ => panic!("{:?} is not a program", draw.program),
+            };
+        let (vertex_array, buffer) = match objects[draw.vertex_buffer.0 as usize] {
+            Some(Object::VertexBuffer {
+                vertex_array,
+                buffer,
+            }) => (vertex_array, buffer),
+            _ => panic!("{:?} is not a vertex buffer", draw.vertex_buffer),
+        };
+        self.context.bind_vertex_array(Some(vertex_array));
+        self.context.bind_buffer(glow::ARRAY_BUFFER, Some(buffer));
+        self.context.use_program(Some(program));
+
+        let mut texture_unit = 0;
+        for (location, value) in uniforms.iter().zip(draw.uniforms.iter()) {
+            self.set_uniform(location, value, &mut texture_unit);
+        }
+
+        self.bind_attributes(vertex_format, draw.base_vertex, 0);
+        let instance_count = match (draw.instances, instance_format) {
+            (Some(instances), Some(instance_format)) => {
+                let buffer = match objects[instances.buffer.0 as usize] {
+                    Some(Object::VertexBuffer { buffer, .. }) => buffer,
+                    _ => panic!("{:?} is not a vertex buffer", instances.buffer),
+                };
+                self.context.bind_buffer(glow::ARRAY_BUFFER, Some(buffer));
+                self.bind_attributes(instance_format, instances.first, 1);
+                Some(instances.count as i32)
+            }
+            (None, None) => None,
+            _ => panic!("instances don't match the program's instance attributes"),
+        };
+
+        let state = &draw.state;
+        self.apply(state);
+        match draw.index_buffer {
+            Some(index_buffer) => {
+                let index_buffer = match objects[index_buffer.0 as usize] {
+                    Some(Object::IndexBuffer(buffer)) => buffer,
+                    _ => panic!("{:?} is not an index buffer", index_buffer),
+                };
+                self.context
+                    .bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(index_buffer));
+                match instance_count {
+                    Some(instance_count) => self.context.draw_elements_instanced(
+                        gl_mode(state.topology),
+                        draw.count as i32,
+                        glow::UNSIGNED_SHORT,
+                        0,
+                        instance_count,
+                    ),
+                    None => self.context.draw_elements(
+                        gl_mode(state.topology),
+                        draw.count as i32,
+                        glow::UNSIGNED_SHORT,
+                        0,
+                    ),
+                }
+            }
+            None => match instance_count {
+                Some(instance_count) => self.context.draw_arrays_instanced(
+                    gl_mode(state.topology),
+                    0,
+                    draw.count as i32,
+                    instance_count,
+                ),
+                None => self
+                    .context
+                    .draw_arrays(gl_mode(state.topology), 0, draw.count as i32),
+            },
+        }
+    }
+
+    unsafe fn delete(&self, handle: Handle) {
+        let object = self.objects.borrow_mut()[handle.0 as usize].take();
+        match object {
+            Some(Object::Shader(shader)) => self.context.delete_shader(shader),
+            Some(Object::Program { program, .. }) => self.context.delete_program(program),
+            Some(Object::VertexBuffer {
+                vertex_array,
+                buffer,
+            }) => {
+                self.context.delete_vertex_array(vertex_array);
+                self.context.delete_buffer(buffer);
+            }
+            Some(Object::IndexBuffer(buffer)) => self.context.delete_buffer(buffer),
+            Some(Object::Texture { texture, .. }) => self.context.delete_texture(texture),
+            Some(Object::RenderTarget(framebuffer)) => self.context.delete_framebuffer(framebuffer),
+            None => panic!("{:?} was already deleted", handle),
+        }
+        self.free_handles.borrow_mut().push(handle);
+    }
+}
+
+/// Which API a context implements, from its version string. WebGL 1 is GLES 2 and WebGL 2 is
+/// GLES 3.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Version {
+    Gles2,
+    Gles3,
+    Desktop { major: u32, minor: u32 },
+}
+
+impl Version {
+    fn of(context: &glow::Context) -> Version {
+        Version::parse(&unsafe { context.get_parameter_string(glow::VERSION) })
+    }
+
+    fn parse(version: &str) -> Version {
+        let (es, version) = if let Some(version) = version.strip_prefix("OpenGL ES ") {
+            (Some(0), version)
+        } else if let Some(version) = version.strip_prefix("WebGL ") {
+            (Some(1), version)
+        } else {
+            (None, version)
+        };
+        let mut numbers = version
+            .split(|c: char| !c.is_ascii_digit())
+            .map(|number| number.parse::<u32>().unwrap_or(0));
+        let major = numbers.next().unwrap_or(0);
+        let minor = numbers.next().unwrap_or(0);
+        match es {
+            Some(offset) if major + offset >= 3 => Version::Gles3,
+            Some(_) => Version::Gles2,
+            None => Version::Desktop { major, minor },
+        }
+    }
+}
+
+/// Whether the context's GL version has instancing built in: GLES 3, WebGL 2 and desktop GL
+/// 3.3 do.
+pub fn has_instancing(context: &glow::Context) -> bool {
+    match Version::of(context) {
+        Version::Gles2 => false,
+        Version::Gles3 => true,
+        Version::Desktop { major, minor } => (major, minor) >= (3, 3),
+    }
+}
+
+fn gl_mode(topology: Topology) -> u32 {
+    match topology {
+        Topology::Points => glow::POINTS,
+        Topology::Lines => glow::LINES,
+        Topology::LineStrip => glow::LINE_STRIP,
+        Topology::Triangles => glow::TRIANGLES,
+        Topology::TriangleStrip => glow::TRIANGLE_STRIP,
+    }
+}
+
+// internal format, then format and type of the data uploaded, `None` if `version` can't create
+// textures of `format`
+fn gl_formats(format: TextureFormat, version: Version) -> Option<(u32, u32, u32)> {
+    let formats = match (format, version) {
+        // GLES 2 only has unsized internal formats matching the data, red and red green
+        // textures and float data need extensions we don't check for
+        (TextureFormat::RGBByte, Version::Gles2) => (glow::RGB, glow::RGB, glow::UNSIGNED_BYTE),
+        (TextureFormat::RGBAByte, Version::Gles2) => (glow::RGBA, glow::RGBA, glow::UNSIGNED_BYTE),
+        (_, Version::Gles2) => return None,
+        // GLES has no BGR orders at all
+        (
+            TextureFormat::BGRFloat
+            | TextureFormat::BGRAFloat
+            | TextureFormat::BGRInt
+            | TextureFormat::BGRAInt,
+            Version::Gles3,
+        ) => return None,
+        (TextureFormat::RByte, _) => (glow::R8, glow::RED, glow::UNSIGNED_BYTE),
+        (TextureFormat::RGByte, _) => (glow::RG8, glow::RG, glow::UNSIGNED_BYTE),
+        (TextureFormat::RGBByte, _) => (glow::RGB8, glow::RGB, glow::UNSIGNED_BYTE),
+        (TextureFormat::RGBAByte, _) => (glow::RGBA8, glow::RGBA, glow::UNSIGNED_BYTE),
+        (TextureFormat::RFloat, _) => (glow::R32F, glow::RED, glow::FLOAT),
+        (TextureFormat::RGFloat, _) => (glow::RG32F, glow::RG, glow::FLOAT),
+        (TextureFormat::RGBFloat, _) => (glow::RGB32F, glow::RGB, glow::FLOAT),
+        (TextureFormat::BGRFloat, _) => (glow::RGB32F, glow::BGR, glow::FLOAT),
+        (TextureFormat::RGBAFloat, _) => (glow::RGBA32F, glow::RGBA, glow::FLOAT),
+        (TextureFormat::BGRAFloat, _) => (glow::RGBA32F, glow::BGRA, glow::FLOAT),
+        (TextureFormat::RInt, _) => (glow::R8UI, glow::RED_INTEGER, glow::UNSIGNED_BYTE),
+        (TextureFormat::RGInt, _) => (glow::RG8UI, glow::RG_INTEGER, glow::UNSIGNED_BYTE),
+        (TextureFormat::RGBInt, _) => (glow::RGB8UI, glow::RGB_INTEGER, glow::UNSIGNED_BYTE),
+        (TextureFormat::BGRInt, _) => (glow::RGB8UI, glow::BGR_INTEGER, glow::UNSIGNED_BYTE),
+        (TextureFormat::RGBAInt, _) => (glow::RGBA8UI, glow::RGBA_INTEGER, glow::UNSIGNED_BYTE),
+        (TextureFormat::BGRAInt, _) => (glow::RGBA8UI, glow::BGRA_INTEGER, glow::UNSIGNED_BYTE),
+    };
+    Some(formats)
+}
+
+fn is_float(format: TextureFormat) -> bool {
+    matches!(
+        format,
+        TextureFormat::RFloat
+            | TextureFormat::RGFloat
+            | TextureFormat::RGBFloat
+            | TextureFormat::BGRFloat
+            | TextureFormat::RGBAFloat
+            | TextureFormat::BGRAFloat
+    )
+}
+
+fn is_integer(format: TextureFormat) -> bool {
+    matches!(
+        format,
+        TextureFormat::RInt
+            | TextureFormat::RGInt
+            | TextureFormat::RGBInt
+            | TextureFormat::BGRInt
+            | TextureFormat::RGBAInt
+            | TextureFormat::BGRAInt
+    )
+}
+
+fn gl_usage(usage: BufferUsage) -> u32 {
+    match usage {
+        BufferUsage::Static => glow::STATIC_DRAW,
+        BufferUsage::Dynamic => glow::DYNAMIC_DRAW,
+        BufferUsage::Stream => glow::STREAM_DRAW,
+    }
+}
+
+// arrays are reflected as their first element
+fn array_name(name: &str) -> &str {
+    name.trim_end_matches("[0]")
+}
+
+// the GL types a uniform of type `ty` can be set on
+fn uniform_types(ty: UniformType) -> &'static [u32] {
+    match ty {
+        UniformType::Texture => &[
+            glow::SAMPLER_2D,
+            glow::INT_SAMPLER_2D,
+            glow::UNSIGNED_INT_SAMPLER_2D,
+        ],
+        UniformType::Int => &[glow::INT, glow::BOOL],
+        UniformType::Int2 => &[glow::INT_VEC2, glow::BOOL_VEC2],
+        UniformType::Int3 => &[glow::INT_VEC3, glow::BOOL_VEC3],
+        UniformType::Int4 => &[glow::INT_VEC4, glow::BOOL_VEC4],
+        UniformType::Float => &[glow::FLOAT],
+        UniformType::Float2 => &[glow::FLOAT_VEC2],
+        UniformType::Float3 => &[glow::FLOAT_VEC3],
+        UniformType::Float4 => &[glow::FLOAT_VEC4],
+        UniformType::Mat2 => &[glow::FLOAT_MAT2],
+        UniformType::Mat3 => &[glow::FLOAT_MAT3],
+        UniformType::Mat4 => &[glow::FLOAT_MAT4],
+    }
+}
+
+// the GL types an attribute of `size` components can feed, attributes are always converted to
+// floats and missing components default to 0, 0, 0, 1
+fn attribute_types(size: u32) -> &'static [u32] {
+    match size {
+        1 => &[
+            glow::FLOAT,
+            glow::FLOAT_VEC2,
+            glow::FLOAT_VEC3,
+            glow::FLOAT_VEC4,
+        ],
+        2 => &[glow::FLOAT_VEC2, glow::FLOAT_VEC3, glow::FLOAT_VEC4],
+        3 => &[glow::FLOAT_VEC3, glow::FLOAT_VEC4],
+        4 => &[glow::FLOAT_VEC4],
+        _ => &[],
+    }
+}
+
+fn gl_type_name(ty: u32) -> String {
+    match ty {
+        glow::FLOAT => "float",
+        glow::FLOAT_VEC2 => "vec2",
+        glow::FLOAT_VEC3 => "vec3",
+        glow::FLOAT_VEC4 => "vec4",
+        glow::INT => "int",
+        glow::INT_VEC2 => "ivec2",
+        glow::INT_VEC3 => "ivec3",
+        glow::INT_VEC4 => "ivec4",
+        glow::BOOL => "bool",
+        glow::BOOL_VEC2 => "bvec2",
+        glow::BOOL_VEC3 => "bvec3",
+        glow::BOOL_VEC4 => "bvec4",
+        glow::FLOAT_MAT2 => "mat2",
+        glow::FLOAT_MAT3 => "mat3",
+        glow::FLOAT_MAT4 => "mat4",
+        glow::SAMPLER_2D => "sampler2D",
+        glow::INT_SAMPLER_2D => "isampler2D",
+        glow::UNSIGNED_INT_SAMPLER_2D => "usampler2D",
+        _ => return format!("GL type {:#x}", ty),
+    }
+    .to_owned()
+}
+
+fn gl_filter(filter: Filter) -> u32 {
+    match filter {
+        Filter::Nearest => glow::NEAREST,
+        Filter::Linear => glow::LINEAR,
+    }
+}
+
+fn gl_wrap(wrap: Wrap) -> u32 {
+    match wrap {
+        Wrap::ClampToEdge => glow::CLAMP_TO_EDGE,
+        Wrap::Repeat => glow::REPEAT,
+        Wrap::MirroredRepeat => glow::MIRRORED_REPEAT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{gl_formats, StateCache, StateChanges, Version};
+    use crate::gl::{BlendMode, DrawState, TextureFormat};
+
+    #[test]
+    fn texture_formats_follow_the_version() {
+        assert_eq!(Version::parse("OpenGL ES 2.0 Mesa"), Version::Gles2);
+        assert_eq!(
+            Version::parse("WebGL 1.0 (OpenGL ES 2.0 Chromium)"),
+            Version::Gles2
+        );
+        assert_eq!(Version::parse("OpenGL ES 3.2 Mesa"), Version::Gles3);
+        assert_eq!(Version::parse("WebGL 2.0"), Version::Gles3);
+        assert_eq!(
+            Version::parse("4.6 (Core Profile) Mesa"),
+            Version::Desktop { major: 4, minor: 6 }
+        );
+
+        let desktop = Version::Desktop { major: 3, minor: 3 };
+        for &format in &[TextureFormat::RGBByte, TextureFormat::RGBAByte] {
+            assert!(gl_formats(format, Version::Gles2).is_some());
+        }
+        for &format in &[
+            TextureFormat::RByte,
+            TextureFormat::RGFloat,
+            TextureFormat::RGBAInt,
+        ] {
+            assert_eq!(gl_formats(format, Version::Gles2), None);
+            assert!(gl_formats(format, Version::Gles3).is_some());
+        }
+        for &format in &[TextureFormat::BGRFloat, TextureFormat::BGRAInt] {
+            assert_eq!(gl_formats(format, Version::Gles3), None);
+            assert!(gl_formats(format, desktop).is_some());
+        }
+    }
+
+    #[test]
+    fn only_changed_state_is_sent() {
+        let mut cache = StateCache {
+            area: (10, 20, 400, 300),
+            clip_to_area: true,
+            ..StateCache::default()
+        };
+        let state = DrawState {
+            scissor: Some((-5, 0, 100, 50)),
+            ..DrawState::default()
+        };
+        assert_eq!(
+            cache.changes(&state),
+            StateChanges {
+                blend_mode: Some(BlendMode::Premultiplied),
+                color_mask: Some([true; 4]),
+                scissor: Some(Some((10, 20, 95, 50))),
+            }
+        );
+        assert_eq!(cache.changes(&state), StateChanges::default());
+
+        let additive = DrawState {
+            blend_mode: BlendMode::Additive,
+            ..state
+        };
+        assert_eq!(
+            cache.changes(&additive),
+            StateChanges {
+                blend_mode: Some(BlendMode::Additive),
+                ..StateChanges::default()
+            }
+        );
+
+        // clears clip to the window's viewport, and then draws need their scissor again
+        assert_eq!(
+            cache.clear_changes(),
+            StateChanges {
+                scissor: Some(Some((10, 20, 400, 300))),
+                ..StateChanges::default()
+            }
+        );
+        assert_eq!(
+            cache.changes(&additive),
+            StateChanges {
+                scissor: Some(Some((10, 20, 95, 50))),
+                ..StateChanges::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use super::{
+    Backend, BufferUsage, Draw, Handle, Rect, ShaderType, TextureDescriptor, UniformEntry,
+    VertexFormat,
+};
+
+/// What a `Recorder` was asked to do, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    CreateShader {
+        shader: Handle,
+        shader_type: ShaderType,
+    },
+    CreateProgram {
+        program: Handle,
+        uniforms: Vec<String>,
+        attributes: Vec<String>,
+        instance_attributes: Vec<String>,
+    },
+    CreateVertexBuffer(Handle),
+    CreateIndexBuffer(Handle),
+    CreateTexture {
+        texture: Handle,
+        desc: TextureDescriptor,
+    },
+    CreateRenderTarget {
+        target: Handle,
+        texture: Handle,
+    },
+    AllocateVertices {
+        buffer: Handle,
+        size: usize,
+        usage: BufferUsage,
+    },
+    WriteVertices {
+        buffer: Handle,
+        offset: usize,
+        bytes: usize,
+    },
+    AllocateIndices {
+        buffer: Handle,
+        len: usize,
+        usage: BufferUsage,
+    },
+    WriteIndices {
+        buffer: Handle,
+        first: usize,
+        len: usize,
+    },
+    WriteTexture {
+        texture: Handle,
+        rect: (u32, u32, u32, u32),
+    },
+    ReadTexture(Handle),
+    BindRenderTarget(Option<Handle>),
+    SetViewport {
+        area: Rect,
+        clip_to_area: bool,
+    },
+    Clear([f32; 4]),
+    ReadPixels(Rect),
+    Draw(Draw),
+    Delete(Handle),
+}
+
+/// Calls recorded by a `Recorder`, shared with whoever checks them.
+pub type CallLog = Rc<RefCell<Vec<Call>>>;
+
+/// A backend that draws nothing and records every call instead, so what is drawn can be checked
+/// without a GPU. Everything succeeds but compiling shaders with an `#error` directive, and reads
+/// return transparent black.
+#[derive(Default)]
+pub struct Recorder {
+    calls: CallLog,
+    next_handle: Cell<u32>,
+    instancing: bool,
+}
+
+impl Recorder {
+    /// A recorder for a context without instancing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_instancing() -> Self {
+        Self {
+            instancing: true,
+            ..Self::default()
+        }
+    }
+
+    /// The calls made so far, and from then on.
+    pub fn calls(&self) -> CallLog {
+        self.calls.clone()
+    }
+
+    fn record(&self, call: Call) {
+        self.calls.borrow_mut().push(call);
+    }
+
+    // handles are never reused, so each names one object over the whole recording
+    fn create(&self, call: impl FnOnce(Handle) -> Call) -> Result<Handle, String> {
+        let handle = Handle(self.next_handle.get());
+        self.next_handle.set(handle.0 + 1);
+        self.record(call(handle));
+        Ok(handle)
+    }
+}
+
+impl Backend for Recorder {
+    fn supports_instancing(&self) -> bool {
+        self.instancing
+    }
+
+    unsafe fn create_shader(
+        &self,
+        shader_type: ShaderType,
+        source: &str,
+    ) -> Result<Handle, String> {
+        if source.contains("#error") {
+            return Err(format!("{:?} shader has an #error directive", shader_type));
+        }
+        self.create(|shader| Call::CreateShader {
+            shader,
+            shader_type,
+        })
+    }
+
+    unsafe fn create_program(
+        &self,
+        _vertex_shader: Handle,
+        _fragment_shader: Handle,
+        uniforms: &[UniformEntry],
+        vertex_format: &VertexFormat,
+        instance_format: Option<&VertexFormat>,
+    ) -> Result<Handle, String> {
+        let names = |format: &VertexFormat| -> Vec<String> {
+            format
+                .attributes
+                .iter()
+                .map(|attribute| attribute.name.to_owned())
+                .collect()
+        };
+        self.create(|program| Call::CreateProgram {
+            program,
+            uniforms: uniforms.iter().map(|entry| entry.name.to_owned()).collect(),
+            attributes: names(vertex_format),
+            instance_attributes: instance_format.map(names).unwrap_or_default(),
+        })
+    }
+
+    unsafe fn create_vertex_buffer(&self) -> Result<Handle, String> {
+        self.create(Call::CreateVertexBuffer)
+    }
+
+    unsafe fn create_index_buffer(&self) -> Result<Handle, String> {
+        self.create(Call::CreateIndexBuffer)
+    }
+
+    unsafe fn create_texture(&self, desc: &TextureDescriptor) -> Result<Handle, String> {
+        self.create(|texture| Call::CreateTexture {
+            texture,
+            desc: *desc,
+        })
+    }
+
+    unsafe fn create_render_target(&self, texture: Handle) -> Result<Handle, String> {
+        self.create(|target| Call::CreateRenderTarget { target, texture })
+    }
+
+    unsafe fn allocate_vertices(&self, buffer: Handle, size: usize, usage: BufferUsage) {
+        self.record(Call::AllocateVertices {
+            buffer,
+            size,
+            usage,
+        });
+    }
+
+    unsafe fn write_vertices(&self, buffer: Handle, offset: usize, data: &[u8]) {
+        self.record(Call::WriteVertices {
+            buffer,
+            offset,
+            bytes: data.len(),
+        });
+    }
+
+    unsafe fn allocate_indices(&self, buffer: Handle, len: usize, usage: BufferUsage) {
+        self.record(Call::AllocateIndices { buffer, len, usage });
+    }
+
+    unsafe fn write_indices(&self, buffer: Handle, first: usize, indices: &[u16]) {
+        self.record(Call::WriteIndices {
+            buffer,
+            first,
+            len: indices.len(),
+        });
+    }
+
+    unsafe fn write_texture(&self, texture: Handle, rect: (u32, u32, u32, u32), _data: &[u8]) {
+        self.record(Call::WriteTexture { texture, rect });
+    }
+
+    unsafe fn read_texture(
+        &self,
+        texture: Handle,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<u8>, String> {
+        self.record(Call::ReadTexture(texture));
+        Ok(vec![0; (width * height * 4) as usize])
+    }
+
+    unsafe fn bind_render_target(&self, target: Option<Handle>) {
+        self.record(Call::BindRenderTarget(target));
+    }
+
+    unsafe fn set_viewport(&self, area: Rect, clip_to_area: bool) {
+        self.record(Call::SetViewport { area, clip_to_area });
+    }
+
+    unsafe fn clear(&self, color: [f32; 4]) {
+        self.record(Call::Clear(color));
+    }
+
+    unsafe fn read_pixels(&self, rect: Rect) -> Vec<u8> {
+        self.record(Call::ReadPixels(rect));
+        let (_, _, width, height) = rect;
+        vec![0; (width * height * 4) as usize]
+    }
+
+    unsafe fn draw(&self, draw: &Draw) {
+        self.record(Call::Draw(draw.clone()));
+    }
+
+    unsafe fn delete(&self, handle: Handle) {
+        self.record(Call::Delete(handle));
+    }
+}
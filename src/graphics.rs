@@ -1,21 +1,357 @@
-use anyhow::Error;
+pub mod animation;
+pub mod text;
+
+use anyhow::{format_err, Error};
 use euclid::{
     default::{Point2D, Rect, Size2D, Transform2D},
-    point2, size2,
+    point2, size2, vec2,
 };
-use zerocopy::AsBytes;
 
 use crate::{
     gl,
-    texture_atlas::{TextureAtlas, TextureRect},
+    sprite_atlas::SPRITE_ATLAS_PAGES,
+    texture_atlas::{AtlasRect, TextureRect},
 };
 
-#[repr(C)]
-#[derive(Clone, Copy, Debug, AsBytes)]
-pub struct Vertex {
-    pub position: [f32; 2],
-    pub uv: [f32; 2],
-    pub color: [f32; 4],
+gl::vertex_layout! {
+    #[derive(Clone, Copy, Debug)]
+    pub struct Vertex {
+        pub position: [f32; 2] => "a_pos",
+        pub uv: [f32; 2] => "a_uv",
+        pub color: [f32; 4] => "a_color",
+    }
+}
+
+gl::vertex_layout! {
+    /// A quad drawn by instancing a unit quad, corners from (0, 0) to (1, 1), onto a
+    /// parallelogram. Without instancing it is expanded into 4 `Vertex`es on the CPU instead.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct SpriteInstance {
+        /// Where the bottom left corner goes.
+        pub origin: [f32; 2] => "i_origin",
+        /// The bottom edge from `origin`, then the left edge.
+        pub axes: [f32; 4] => "i_axes",
+        /// Texture coordinates at the bottom left corner, then the top right corner.
+        pub uv: [f32; 4] => "i_uv",
+        pub color: [f32; 4] => "i_color",
+    }
+}
+
+gl::vertex_layout! {
+    #[derive(Clone, Copy, Debug)]
+    struct Corner {
+        corner: [f32; 2] => "a_corner",
+    }
+}
+
+impl SpriteInstance {
+    /// An axis aligned quad from `min` to `max`, with `uv_min` sampled at `min`.
+    pub fn rect(
+        min: Point2D<f32>,
+        max: Point2D<f32>,
+        uv_min: [f32; 2],
+        uv_max: [f32; 2],
+        color: [f32; 4],
+    ) -> Self {
+        Self {
+            origin: min.to_array(),
+            axes: [max.x - min.x, 0., 0., max.y - min.y],
+            uv: [uv_min[0], uv_min[1], uv_max[0], uv_max[1]],
+            color,
+        }
+    }
+
+    /// The corners as vertices, in the order `QUAD_INDICES` expects.
+    pub fn vertices(&self) -> [Vertex; 4] {
+        let vertex = |x: f32, y: f32| Vertex {
+            position: [
+                self.origin[0] + x * self.axes[0] + y * self.axes[2],
+                self.origin[1] + x * self.axes[1] + y * self.axes[3],
+            ],
+            uv: [
+                self.uv[0] + x * (self.uv[2] - self.uv[0]),
+                self.uv[1] + y * (self.uv[3] - self.uv[1]),
+            ],
+            color: self.color,
+        };
+        [
+            vertex(0., 0.),
+            vertex(1., 0.),
+            vertex(0., 1.),
+            vertex(1., 1.),
+        ]
+    }
+}
+
+/// Corners of the unit quad each `SpriteInstance` is drawn as.
+const UNIT_QUAD: [Corner; 4] = [
+    Corner { corner: [0., 0.] },
+    Corner { corner: [1., 0.] },
+    Corner { corner: [0., 1.] },
+    Corner { corner: [1., 1.] },
+];
+
+/// Vertex order of a quad: bottom left, bottom right, top left, top right.
+pub const QUAD_INDICES: [u16; 6] = [0, 1, 2, 1, 3, 2];
+
+/// Most quads one draw call can address with 16 bit indices.
+pub const MAX_BATCH_QUADS: usize = (u16::MAX as usize + 1) / 4;
+
+/// Creates the index buffer shared by every `SpriteBatch`, holding `QUAD_INDICES` repeated for
+/// `MAX_BATCH_QUADS` quads.
+pub unsafe fn create_quad_index_buffer(
+    context: &mut gl::Context,
+) -> Result<gl::IndexBuffer, Error> {
+    let indices: Vec<u16> = (0..MAX_BATCH_QUADS as u16)
+        .flat_map(|quad| QUAD_INDICES.iter().map(move |i| quad * 4 + i))
+        .collect();
+    let mut index_buffer = context.create_index_buffer(gl::BufferUsage::Static)?;
+    index_buffer.write(&indices);
+    Ok(index_buffer)
+}
+
+/// The file in `src/shaders` and compiled in source of the vertex shader for sprite programs,
+/// the instanced one when `context` supports instancing.
+pub fn sprite_vertex_shader(context: &gl::Context) -> (&'static str, &'static str) {
+    if context.supports_instancing() {
+        (
+            "sprite_instanced.vert",
+            include_str!("shaders/sprite_instanced.vert"),
+        )
+    } else {
+        ("shader.vert", include_str!("shaders/shader.vert"))
+    }
+}
+
+/// Creates a program for drawing `SpriteBatch`es, from the vertex shader `sprite_vertex_shader`
+/// picks. Its uniforms are `u_transform` and `u_texture`, followed by `uniforms`.
+pub unsafe fn create_sprite_program(
+    context: &mut gl::Context,
+    vertex_source: &str,
+    fragment_source: &str,
+    uniforms: &[gl::UniformEntry],
+) -> Result<gl::Program, Error> {
+    let vertex_shader = context.create_shader(gl::ShaderType::Vertex, vertex_source)?;
+    let fragment_shader = context.create_shader(gl::ShaderType::Fragment, fragment_source)?;
+    let uniforms: Vec<gl::UniformEntry> = [
+        gl::UniformEntry {
+            name: "u_transform",
+            ty: gl::UniformType::Mat3,
+        },
+        gl::UniformEntry {
+            name: "u_texture",
+            ty: gl::UniformType::Texture,
+        },
+    ]
+    .iter()
+    .chain(uniforms)
+    .cloned()
+    .collect();
+    let (vertex_format, instance_format) = if context.supports_instancing() {
+        (
+            gl::VertexFormat::of::<Corner>(),
+            Some(gl::VertexFormat::of::<SpriteInstance>()),
+        )
+    } else {
+        (gl::VertexFormat::of::<Vertex>(), None)
+    };
+    Ok(context.create_program(&gl::ProgramDescriptor {
+        vertex_shader: &vertex_shader,
+        fragment_shader: &fragment_shader,
+        uniforms: &uniforms,
+        vertex_format,
+        instance_format,
+    })?)
+}
+
+/// Where a `SpriteBatch` is uploaded to be drawn: one `SpriteInstance` per quad when the context
+/// supports instancing, otherwise the 4 vertices of each quad.
+pub struct SpriteBuffer {
+    buffer: gl::VertexBuffer,
+    // the unit quad instances are drawn as, `None` without instancing
+    unit_quad: Option<gl::VertexBuffer>,
+}
+
+impl SpriteBuffer {
+    pub unsafe fn new(context: &mut gl::Context, usage: gl::BufferUsage) -> Result<Self, Error> {
+        let unit_quad = if context.supports_instancing() {
+            let mut unit_quad = context.create_vertex_buffer(gl::BufferUsage::Static)?;
+            unit_quad.write(&UNIT_QUAD);
+            Some(unit_quad)
+        } else {
+            None
+        };
+        Ok(Self {
+            buffer: context.create_vertex_buffer(usage)?,
+            unit_quad,
+        })
+    }
+}
+
+/// Draw order of a quad for `SpriteBatch::sort`. Lower layers are drawn first, and within a
+/// layer quads further up the screen are drawn first so nearer ones overlap them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SortKey {
+    pub layer: i32,
+    pub y: f32,
+}
+
+impl SortKey {
+    pub fn new(layer: i32, y: f32) -> SortKey {
+        SortKey { layer, y }
+    }
+
+    fn draw_order(&self, other: &SortKey) -> std::cmp::Ordering {
+        self.layer.cmp(&other.layer).then_with(|| {
+            other
+                .y
+                .partial_cmp(&self.y)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+    }
+}
+
+/// Collects quads as `SpriteInstance`s, grouped into runs of quads that use the same texture.
+#[derive(Default)]
+pub struct SpriteBatch {
+    instances: Vec<SpriteInstance>,
+    batches: Vec<Batch>,
+    sort_key: SortKey,
+    sort_keys: Vec<SortKey>,
+}
+
+struct Batch {
+    texture: usize,
+    first_quad: usize,
+    quad_count: usize,
+}
+
+impl SpriteBatch {
+    pub fn new() -> SpriteBatch {
+        SpriteBatch::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.instances.clear();
+        self.batches.clear();
+        self.sort_key = SortKey::default();
+        self.sort_keys.clear();
+    }
+
+    /// Sort key of the quads pushed from now on.
+    pub fn set_sort_key(&mut self, key: SortKey) {
+        self.sort_key = key;
+    }
+
+    /// Reorders the quads by their sort keys. Quads with equal keys keep their push order.
+    pub fn sort(&mut self) {
+        let textures: Vec<usize> = self
+            .batches
+            .iter()
+            .flat_map(|batch| (0..batch.quad_count).map(move |_| batch.texture))
+            .collect();
+        let mut order: Vec<usize> = (0..textures.len()).collect();
+        let sort_keys = std::mem::take(&mut self.sort_keys);
+        order.sort_by(|a, b| sort_keys[*a].draw_order(&sort_keys[*b]));
+
+        let instances = std::mem::take(&mut self.instances);
+        let sort_key = self.sort_key;
+        self.batches.clear();
+        for quad in order {
+            self.sort_key = sort_keys[quad];
+            self.push_instance(textures[quad], instances[quad]);
+        }
+        self.sort_key = sort_key;
+    }
+
+    /// `texture` indexes the texture list given to `draw`. Quads are drawn in push order
+    /// unless sorted.
+    pub fn push_instance(&mut self, texture: usize, instance: SpriteInstance) {
+        match self.batches.last_mut() {
+            Some(batch) if batch.texture == texture => batch.quad_count += 1,
+            _ => self.batches.push(Batch {
+                texture,
+                first_quad: self.instances.len(),
+                quad_count: 1,
+            }),
+        }
+        self.instances.push(instance);
+        self.sort_keys.push(self.sort_key);
+    }
+
+    /// Iterates over the texture index and quads of each batch.
+    #[cfg(test)]
+    pub fn batches(&self) -> impl Iterator<Item = (usize, &[SpriteInstance])> {
+        self.batches.iter().map(move |batch| {
+            (
+                batch.texture,
+                &self.instances[batch.first_quad..batch.first_quad + batch.quad_count],
+            )
+        })
+    }
+
+    pub unsafe fn upload(&self, buffer: &mut SpriteBuffer) {
+        if buffer.unit_quad.is_some() {
+            buffer.buffer.write(&self.instances);
+        } else {
+            let vertices: Vec<Vertex> = self
+                .instances
+                .iter()
+                .flat_map(|instance| instance.vertices().to_vec())
+                .collect();
+            buffer.buffer.write(&vertices);
+        }
+    }
+
+    /// Draws the quads last uploaded to `buffer`, binding each batch's texture to the program's
+    /// `u_texture`. The program must come from `create_sprite_program` on the same context. The
+    /// state's topology is ignored, quads are always triangles.
+    pub unsafe fn draw(
+        &self,
+        program: &mut gl::Program,
+        textures: &[&gl::Texture],
+        buffer: &SpriteBuffer,
+        index_buffer: &gl::IndexBuffer,
+        state: &gl::DrawState,
+    ) -> Result<(), Error> {
+        let state = gl::DrawState {
+            topology: gl::Topology::Triangles,
+            ..*state
+        };
+        for batch in self.batches.iter() {
+            let texture = textures
+                .get(batch.texture)
+                .ok_or_else(|| format_err!("no texture at index {}", batch.texture))?;
+            program.set_uniform("u_texture", gl::Uniform::Texture(texture))?;
+
+            if let Some(unit_quad) = &buffer.unit_quad {
+                program.render_instanced(
+                    unit_quad,
+                    index_buffer,
+                    QUAD_INDICES.len(),
+                    &buffer.buffer,
+                    batch.first_quad,
+                    batch.quad_count,
+                    &state,
+                )?;
+                continue;
+            }
+            let mut first_quad = batch.first_quad;
+            let end_quad = batch.first_quad + batch.quad_count;
+            while first_quad < end_quad {
+                let quad_count = (end_quad - first_quad).min(MAX_BATCH_QUADS);
+                program.render_indexed(
+                    &buffer.buffer,
+                    index_buffer,
+                    first_quad * 4,
+                    quad_count * 6,
+                    &state,
+                )?;
+                first_quad += quad_count;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -25,10 +361,13 @@ pub struct Sprite {
     origin: Point2D<f32>,
     transform: Transform2D<f32>,
     color: [f32; 4],
+    texture: usize,
 }
 
 impl Sprite {
-    pub fn new(image: TextureRect, frame_count: u32, origin: Point2D<f32>) -> Self {
+    /// Slices `image` into `frame_count` frames of equal width.
+    pub fn new(image: AtlasRect, frame_count: u32, origin: Point2D<f32>) -> Self {
+        let AtlasRect { page, rect: image } = image;
         let width = image[2] - image[0];
         let frame_width = width / frame_count;
         let frames = (0..frame_count)
@@ -41,12 +380,18 @@ impl Sprite {
                 ]
             })
             .collect();
+        Self::from_frames(page, frames, origin)
+    }
+
+    /// A sprite with arbitrary frame rects, all on atlas page `page`.
+    pub fn from_frames(page: usize, frames: Vec<TextureRect>, origin: Point2D<f32>) -> Self {
         Self {
+            frame_count: frames.len() as u32,
             frames,
-            frame_count,
             origin,
             transform: Transform2D::create_translation(-origin.x, -origin.y),
             color: [1., 1., 1., 1.],
+            texture: page,
         }
     }
 
@@ -62,25 +407,34 @@ impl Sprite {
     pub fn transform(&self) -> &Transform2D<f32> {
         &self.transform
     }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count
+    }
 }
 
-pub unsafe fn load_image(
-    image_bytes: &[u8],
-    texture_atlas: &mut TextureAtlas,
-    texture: &mut gl::Texture,
-) -> Result<TextureRect, Error> {
-    let image = image::load_from_memory(image_bytes).unwrap().to_rgba();
-    let texture_coords = texture_atlas
-        .add_texture((image.width(), image.height()))
-        .unwrap();
-    texture.write(
-        texture_coords[0],
-        texture_coords[1],
-        texture_coords[2] - texture_coords[0],
-        texture_coords[3] - texture_coords[1],
-        &image.into_raw(),
-    );
-    Ok(texture_coords)
+/// Uploads each page of the prepacked sprite atlas to a texture, indexed by page.
+pub unsafe fn load_sprite_atlas(context: &mut gl::Context) -> Result<Vec<gl::Texture>, Error> {
+    SPRITE_ATLAS_PAGES
+        .iter()
+        .map(|png| {
+            let image = image::load_from_memory(png)?.to_rgba();
+            if image.dimensions() != (TEXTURE_ATLAS_SIZE.width, TEXTURE_ATLAS_SIZE.height) {
+                return Err(format_err!(
+                    "sprite atlas page is {:?}, expected {:?}",
+                    image.dimensions(),
+                    TEXTURE_ATLAS_SIZE
+                ));
+            }
+            let mut texture = context.create_texture(&gl::TextureDescriptor::new(
+                gl::TextureFormat::RGBAByte,
+                TEXTURE_ATLAS_SIZE.width,
+                TEXTURE_ATLAS_SIZE.height,
+            ))?;
+            texture.write(0, 0, image.width(), image.height(), &image.into_raw());
+            Ok(texture)
+        })
+        .collect()
 }
 
 fn render_line(
@@ -88,7 +442,7 @@ fn render_line(
     end: &mut Sprite,
     start_point: Point2D<f32>,
     end_point: Point2D<f32>,
-    out: &mut Vec<Vertex>,
+    out: &mut SpriteBatch,
 ) {
     let angle = (end_point - start_point).angle_from_x_axis();
 
@@ -105,7 +459,18 @@ fn render_line(
     render_sprite(end, 0, end_point, out);
 }
 
-pub fn render_sprite(sprite: &Sprite, frame: usize, position: Point2D<f32>, out: &mut Vec<Vertex>) {
+pub fn render_sprite(sprite: &Sprite, frame: usize, position: Point2D<f32>, out: &mut SpriteBatch) {
+    render_sprite_colored(sprite, frame, position, sprite.color, out);
+}
+
+/// Like `render_sprite`, with `color` in place of the sprite's own color.
+pub fn render_sprite_colored(
+    sprite: &Sprite,
+    frame: usize,
+    position: Point2D<f32>,
+    color: [f32; 4],
+    out: &mut SpriteBatch,
+) {
     let size = size2(
         (sprite.frames[frame][2] - sprite.frames[frame][0]) as f32,
         (sprite.frames[frame][3] - sprite.frames[frame][1]) as f32,
@@ -124,92 +489,33 @@ pub fn render_sprite(sprite: &Sprite, frame: usize, position: Point2D<f32>, out:
     );
     let uv_rect = Rect::new(uv_pos, uv_size);
 
-    let transform = |p: Point2D<f32>| -> [f32; 2] {
-        (position + sprite.transform().transform_point(p).to_vector()).to_array()
-    };
-    out.extend_from_slice(&[
-        Vertex {
-            position: transform(vertex_rect.min()),
-            uv: [uv_rect.min_x(), uv_rect.max_y()],
-            color: sprite.color,
-        },
-        Vertex {
-            position: transform(point2(vertex_rect.max_x(), vertex_rect.min_y())),
-            uv: [uv_rect.max_x(), uv_rect.max_y()],
-            color: sprite.color,
-        },
-        Vertex {
-            position: transform(point2(vertex_rect.min_x(), vertex_rect.max_y())),
-            uv: [uv_rect.min_x(), uv_rect.min_y()],
-            color: sprite.color,
+    let transform = sprite.transform();
+    let origin = position + transform.transform_point(vertex_rect.min()).to_vector();
+    let x_axis = transform.transform_vector(vec2(vertex_rect.width(), 0.));
+    let y_axis = transform.transform_vector(vec2(0., vertex_rect.height()));
+    out.push_instance(
+        sprite.texture,
+        SpriteInstance {
+            origin: origin.to_array(),
+            axes: [x_axis.x, x_axis.y, y_axis.x, y_axis.y],
+            uv: [
+                uv_rect.min_x(),
+                uv_rect.max_y(),
+                uv_rect.max_x(),
+                uv_rect.min_y(),
+            ],
+            color,
         },
-        Vertex {
-            position: transform(point2(vertex_rect.max_x(), vertex_rect.min_y())),
-            uv: [uv_rect.max_x(), uv_rect.max_y()],
-            color: sprite.color,
-        },
-        Vertex {
-            position: transform(vertex_rect.max()),
-            uv: [uv_rect.max_x(), uv_rect.min_y()],
-            color: sprite.color,
-        },
-        Vertex {
-            position: transform(point2(vertex_rect.min_x(), vertex_rect.max_y())),
-            uv: [uv_rect.min_x(), uv_rect.min_y()],
-            color: sprite.color,
-        },
-    ]);
-}
-
-pub fn render_quad(position: Point2D<f32>, tex_coords: TextureRect, out: &mut Vec<Vertex>) {
-    let size = size2(
-        (tex_coords[2] - tex_coords[0]) as f32,
-        (tex_coords[3] - tex_coords[1]) as f32,
     );
-    let vertex_rect = Rect::new(position, size);
+}
 
-    let uv_pos = point2(
-        tex_coords[0] as f32 / TEXTURE_ATLAS_SIZE.width as f32,
-        tex_coords[1] as f32 / TEXTURE_ATLAS_SIZE.height as f32,
+/// Pushes a quad showing the whole of a render target texture, given to `SpriteBatch::draw` as
+/// texture 0. Unlike images, render targets store their bottom row first.
+pub fn render_target_quad(min: Point2D<f32>, max: Point2D<f32>, out: &mut SpriteBatch) {
+    out.push_instance(
+        0,
+        SpriteInstance::rect(min, max, [0., 0.], [1., 1.], [1., 1., 1., 1.]),
     );
-    let uv_size = size2(
-        (tex_coords[2] - tex_coords[0]) as f32 / TEXTURE_ATLAS_SIZE.width as f32,
-        (tex_coords[3] - tex_coords[1]) as f32 / TEXTURE_ATLAS_SIZE.height as f32,
-    );
-    let uv_rect = Rect::new(uv_pos, uv_size);
-
-    out.extend_from_slice(&[
-        Vertex {
-            position: vertex_rect.min().to_array(),
-            uv: [uv_rect.min_x(), uv_rect.max_y()],
-            color: [1., 1., 1., 1.],
-        },
-        Vertex {
-            position: [vertex_rect.max_x(), vertex_rect.min_y()],
-            uv: [uv_rect.max_x(), uv_rect.max_y()],
-            color: [1., 1., 1., 1.],
-        },
-        Vertex {
-            position: [vertex_rect.min_x(), vertex_rect.max_y()],
-            uv: [uv_rect.min_x(), uv_rect.min_y()],
-            color: [1., 1., 1., 1.],
-        },
-        Vertex {
-            position: [vertex_rect.max_x(), vertex_rect.min_y()],
-            uv: [uv_rect.max_x(), uv_rect.max_y()],
-            color: [1., 1., 1., 1.],
-        },
-        Vertex {
-            position: vertex_rect.max().to_array(),
-            uv: [uv_rect.max_x(), uv_rect.min_y()],
-            color: [1., 1., 1., 1.],
-        },
-        Vertex {
-            position: [vertex_rect.min_x(), vertex_rect.max_y()],
-            uv: [uv_rect.min_x(), uv_rect.min_y()],
-            color: [1., 1., 1., 1.],
-        },
-    ]);
 }
 
 pub const TEXTURE_ATLAS_SIZE: Size2D<u32> = Size2D {
@@ -217,3 +523,203 @@ pub const TEXTURE_ATLAS_SIZE: Size2D<u32> = Size2D {
     height: 1024,
     _unit: std::marker::PhantomData::<euclid::UnknownUnit>,
 };
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use super::{
+        create_quad_index_buffer, create_sprite_program, sprite_vertex_shader, SortKey,
+        SpriteBatch, SpriteBuffer, SpriteInstance, Vertex, MAX_BATCH_QUADS,
+    };
+    use crate::gl::{self, Call, Recorder, UniformValue, VertexAttributeType, VertexFormat};
+
+    fn quad(id: f32) -> SpriteInstance {
+        SpriteInstance::rect(
+            point2(id, 0.),
+            point2(id + 1., 1.),
+            [0., 0.],
+            [1., 1.],
+            [1., 1., 1., 1.],
+        )
+    }
+
+    #[test]
+    fn sort_orders_by_layer_then_y_and_rebatches() {
+        let mut batch = SpriteBatch::new();
+        let pushes = [
+            (0, SortKey::new(1, 2.), 0.),
+            (1, SortKey::new(0, 1.), 1.),
+            (0, SortKey::new(1, 5.), 2.),
+            (1, SortKey::new(1, 2.), 3.),
+            (0, SortKey::new(0, 3.), 4.),
+        ];
+        for (texture, key, id) in pushes.iter() {
+            batch.set_sort_key(*key);
+            batch.push_instance(*texture, quad(*id));
+        }
+        batch.sort();
+
+        let drawn: Vec<(usize, Vec<f32>)> = batch
+            .batches()
+            .map(|(texture, quads)| {
+                let ids = quads.iter().map(|quad| quad.origin[0]).collect();
+                (texture, ids)
+            })
+            .collect();
+        assert_eq!(
+            drawn,
+            vec![
+                (0, vec![4.]),
+                (1, vec![1.]),
+                (0, vec![2., 0.]),
+                (1, vec![3.])
+            ]
+        );
+    }
+
+    #[test]
+    fn batches_draw_their_quads_from_each_page_in_turn() {
+        let recorder = Recorder::new();
+        let calls = recorder.calls();
+        let mut context = gl::Context::new(Box::new(recorder));
+        let page = |context: &mut gl::Context| {
+            let desc = gl::TextureDescriptor::new(gl::TextureFormat::RGBAByte, 4, 4);
+            unsafe { context.create_texture(&desc) }.unwrap()
+        };
+        let pages = [page(&mut context), page(&mut context)];
+        let (index_buffer, mut buffer, mut program) = unsafe {
+            let vertex_source = sprite_vertex_shader(&context).1;
+            let fragment_source = include_str!("shaders/shader.frag");
+            (
+                create_quad_index_buffer(&mut context).unwrap(),
+                SpriteBuffer::new(&mut context, gl::BufferUsage::Static).unwrap(),
+                create_sprite_program(&mut context, vertex_source, fragment_source, &[]).unwrap(),
+            )
+        };
+
+        // the second page's run is one quad more than the index buffer covers
+        let mut batch = SpriteBatch::new();
+        let runs = [(0, 3), (1, MAX_BATCH_QUADS + 1), (0, 2)];
+        for (page, quads) in runs.iter() {
+            for _ in 0..*quads {
+                batch.push_instance(*page, quad(0.));
+            }
+        }
+        let mut textures = Vec::new();
+        let mut index_buffers = Vec::new();
+        for call in calls.replace(Vec::new()) {
+            match call {
+                Call::CreateTexture { texture, .. } => textures.push(texture),
+                Call::CreateIndexBuffer(buffer) => index_buffers.push(buffer),
+                _ => {}
+            }
+        }
+        let identity = [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]];
+        program
+            .set_uniform("u_transform", gl::Uniform::Mat3(identity))
+            .unwrap();
+        unsafe {
+            batch.upload(&mut buffer);
+            let state = gl::DrawState::default();
+            batch
+                .draw(
+                    &mut program,
+                    &[&pages[0], &pages[1]],
+                    &buffer,
+                    &index_buffer,
+                    &state,
+                )
+                .unwrap();
+        }
+
+        let drawn: Vec<_> = calls
+            .borrow()
+            .iter()
+            .filter_map(|call| match call {
+                Call::Draw(draw) => {
+                    let texture = draw.uniforms.iter().find_map(|uniform| match uniform {
+                        UniformValue::Texture(texture) => Some(*texture),
+                        _ => None,
+                    });
+                    assert_eq!(draw.index_buffer, Some(index_buffers[0]));
+                    Some((texture.unwrap(), draw.base_vertex, draw.count))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            drawn,
+            vec![
+                (textures[0], 0, 3 * 6),
+                (textures[1], 3 * 4, MAX_BATCH_QUADS * 6),
+                (textures[1], (3 + MAX_BATCH_QUADS) * 4, 6),
+                (textures[0], (4 + MAX_BATCH_QUADS) * 4, 2 * 6),
+            ]
+        );
+    }
+
+    gl::vertex_layout! {
+        struct FlaggedVertex {
+            position: [f32; 2] => "a_pos",
+            color: [u8; 4] => "a_color",
+            flags: [i8; 4] => "a_flags",
+            depth: f32 => "a_depth",
+        }
+    }
+
+    #[test]
+    fn vertex_layouts_follow_the_fields() {
+        let layout = |format: VertexFormat<'static>| -> (usize, Vec<(&str, u32, u32)>) {
+            let attributes = format
+                .attributes
+                .iter()
+                .map(|attribute| (attribute.name, attribute.size, attribute.offset))
+                .collect();
+            (format.stride, attributes)
+        };
+        assert_eq!(
+            layout(VertexFormat::of::<Vertex>()),
+            (
+                32,
+                vec![("a_pos", 2, 0), ("a_uv", 2, 8), ("a_color", 4, 16)]
+            )
+        );
+        assert_eq!(
+            layout(VertexFormat::of::<SpriteInstance>()),
+            (
+                56,
+                vec![
+                    ("i_origin", 2, 0),
+                    ("i_axes", 4, 8),
+                    ("i_uv", 4, 24),
+                    ("i_color", 4, 40)
+                ]
+            )
+        );
+
+        let format = VertexFormat::of::<FlaggedVertex>();
+        let attributes: Vec<_> = format
+            .attributes
+            .iter()
+            .map(|attribute| {
+                (
+                    attribute.name,
+                    attribute.ty,
+                    attribute.size,
+                    attribute.offset,
+                )
+            })
+            .collect();
+        assert_eq!(format.stride, 20);
+        assert_eq!(
+            attributes,
+            [
+                ("a_pos", VertexAttributeType::Float, 2, 0),
+                ("a_color", VertexAttributeType::Uint, 4, 8),
+                ("a_flags", VertexAttributeType::Int, 4, 12),
+                ("a_depth", VertexAttributeType::Float, 1, 16)
+            ]
+        );
+    }
+}
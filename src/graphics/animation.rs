@@ -0,0 +1,353 @@
+use std::rc::Rc;
+
+use anyhow::{format_err, Error};
+use euclid::default::Point2D;
+use serde::Deserialize;
+
+use super::Sprite;
+use crate::{
+    determinism::{HashState, StateHasher},
+    texture_atlas::{AtlasRect, TextureRect},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopMode {
+    Loop,
+    /// Stops on the last frame.
+    Once,
+    /// Plays forwards then backwards, without repeating the end frames.
+    PingPong,
+}
+
+/// A named run of frames, an Aseprite tag.
+#[derive(Clone, Debug)]
+pub struct Clip {
+    pub name: String,
+    /// Frame indices in play order.
+    pub frames: Vec<usize>,
+    pub loop_mode: LoopMode,
+    // frames of one full cycle, ping pong clips come back without repeating the end frames
+    cycle: Vec<usize>,
+    // seconds of one full cycle
+    cycle_length: f32,
+}
+
+impl Clip {
+    fn new(name: String, frames: Vec<usize>, loop_mode: LoopMode, durations: &[f32]) -> Self {
+        let mut cycle = frames.clone();
+        if loop_mode == LoopMode::PingPong && frames.len() > 2 {
+            cycle.extend(frames[1..frames.len() - 1].iter().rev());
+        }
+        let cycle_length = cycle.iter().map(|&frame| durations[frame]).sum();
+        Self {
+            name,
+            frames,
+            loop_mode,
+            cycle,
+            cycle_length,
+        }
+    }
+}
+
+/// Frames of one sprite sheet with their durations in seconds, and the clips playing them.
+#[derive(Clone, Debug)]
+pub struct Animation {
+    page: usize,
+    frames: Vec<TextureRect>,
+    durations: Vec<f32>,
+    clips: Vec<Clip>,
+}
+
+// The parts of Aseprite's JSON sprite sheet export we use. Sheets must be exported with the
+// "Array" frames option, the default "Hash" loses the frame order.
+#[derive(Deserialize)]
+struct AsepriteSheet {
+    frames: Vec<AsepriteFrame>,
+    meta: AsepriteMeta,
+}
+
+#[derive(Deserialize)]
+struct AsepriteFrame {
+    frame: AsepriteRect,
+    duration: u32,
+}
+
+#[derive(Deserialize)]
+struct AsepriteRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Deserialize)]
+struct AsepriteMeta {
+    #[serde(rename = "frameTags", default)]
+    frame_tags: Vec<AsepriteTag>,
+}
+
+#[derive(Deserialize)]
+struct AsepriteTag {
+    name: String,
+    from: usize,
+    to: usize,
+    direction: String,
+    // newer Aseprite versions write a play count, absent means forever
+    repeat: Option<String>,
+}
+
+impl Animation {
+    /// Loads an Aseprite sheet export whose image was packed into the atlas at `sheet`. Without
+    /// tags, every frame is played in a looping clip named after the empty string.
+    pub fn from_aseprite_json(json: &str, sheet: AtlasRect) -> Result<Self, Error> {
+        let export: AsepriteSheet = serde_json::from_str(json)?;
+        if export.frames.is_empty() {
+            return Err(format_err!("sprite sheet has no frames"));
+        }
+
+        let mut frames = Vec::new();
+        let mut durations = Vec::new();
+        for frame in export.frames.iter() {
+            let AsepriteRect { x, y, w, h } = frame.frame;
+            let rect = [
+                sheet.rect[0] + x,
+                sheet.rect[1] + y,
+                sheet.rect[0] + x + w,
+                sheet.rect[1] + y + h,
+            ];
+            if rect[2] > sheet.rect[2] || rect[3] > sheet.rect[3] {
+                return Err(format_err!("frame {:?} is outside the sprite sheet", rect));
+            }
+            frames.push(rect);
+            durations.push(frame.duration as f32 / 1000.);
+        }
+
+        let mut clips = Vec::new();
+        for tag in export.meta.frame_tags {
+            if tag.from > tag.to || tag.to >= frames.len() {
+                return Err(format_err!("tag {} has invalid frames", tag.name));
+            }
+            let mut clip_frames: Vec<usize> = (tag.from..=tag.to).collect();
+            let mut loop_mode = LoopMode::Loop;
+            match tag.direction.as_str() {
+                "forward" => {}
+                "reverse" => clip_frames.reverse(),
+                "pingpong" => loop_mode = LoopMode::PingPong,
+                direction => {
+                    return Err(format_err!(
+                        "tag {} has unknown direction {}",
+                        tag.name,
+                        direction
+                    ))
+                }
+            }
+            if tag.repeat.as_deref() == Some("1") && loop_mode == LoopMode::Loop {
+                loop_mode = LoopMode::Once;
+            }
+            clips.push(Clip::new(tag.name, clip_frames, loop_mode, &durations));
+        }
+        if clips.is_empty() {
+            clips.push(Clip::new(
+                String::new(),
+                (0..frames.len()).collect(),
+                LoopMode::Loop,
+                &durations,
+            ));
+        }
+
+        Ok(Self {
+            page: sheet.page,
+            frames,
+            durations,
+            clips,
+        })
+    }
+
+    pub fn clip(&self, name: &str) -> Result<usize, Error> {
+        self.clips
+            .iter()
+            .position(|clip| clip.name == name)
+            .ok_or_else(|| format_err!("no animation clip named {}", name))
+    }
+
+    /// A sprite with one frame per animation frame, drawn with the frame from `Animator::frame`.
+    pub fn sprite(&self, origin: Point2D<f32>) -> Sprite {
+        Sprite::from_frames(self.page, self.frames.clone(), origin)
+    }
+}
+
+/// Plays the clips of an `Animation`, advanced by the entity owning it.
+#[derive(Clone)]
+pub struct Animator {
+    animation: Rc<Animation>,
+    clip: usize,
+    // into the current cycle
+    time: f32,
+    // since the clip started, over every cycle
+    elapsed: f32,
+}
+
+impl Animator {
+    pub fn new(animation: Rc<Animation>, clip: &str) -> Result<Self, Error> {
+        let clip = animation.clip(clip)?;
+        Ok(Self {
+            animation,
+            clip,
+            time: 0.,
+            elapsed: 0.,
+        })
+    }
+
+    /// Switches to another clip from its start. Playing the current clip again does nothing.
+    pub fn play(&mut self, clip: &str) -> Result<(), Error> {
+        let clip = self.animation.clip(clip)?;
+        if clip != self.clip {
+            self.clip = clip;
+            self.restart();
+        }
+        Ok(())
+    }
+
+    pub fn restart(&mut self) {
+        self.time = 0.;
+        self.elapsed = 0.;
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time += dt;
+        self.elapsed += dt;
+        let length = self.clip().cycle_length;
+        match self.clip().loop_mode {
+            LoopMode::Once => self.time = self.time.min(length),
+            LoopMode::Loop | LoopMode::PingPong => {
+                if length > 0. {
+                    self.time %= length;
+                }
+            }
+        }
+    }
+
+    /// Seconds since the clip started playing, over all of its cycles. Lets motion that isn't
+    /// drawn as frames, like bobbing, keep its own period while the clip plays.
+    pub fn elapsed(&self) -> f32 {
+        self.elapsed
+    }
+
+    /// The animation frame to draw, an index into the sprite from `Animation::sprite`.
+    pub fn frame(&self) -> usize {
+        let clip = self.clip();
+        let mut time = self.time;
+        for &frame in clip.cycle.iter() {
+            time -= self.animation.durations[frame];
+            if time < 0. {
+                return frame;
+            }
+        }
+        *clip.frames.last().expect("clips have frames")
+    }
+
+    fn clip(&self) -> &Clip {
+        &self.animation.clips[self.clip]
+    }
+}
+
+impl HashState for Animator {
+    fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_usize(self.clip);
+        hasher.write_f32(self.time);
+        hasher.write_f32(self.elapsed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{Animation, Animator};
+    use crate::texture_atlas::AtlasRect;
+
+    const SHEET: &str = r#"{
+        "frames": [
+            { "filename": "a 0", "frame": { "x": 0, "y": 0, "w": 4, "h": 4 }, "duration": 100 },
+            { "filename": "a 1", "frame": { "x": 4, "y": 0, "w": 4, "h": 4 }, "duration": 200 },
+            { "filename": "a 2", "frame": { "x": 8, "y": 0, "w": 4, "h": 4 }, "duration": 100 }
+        ],
+        "meta": {
+            "frameTags": [
+                { "name": "loop", "from": 0, "to": 2, "direction": "forward" },
+                { "name": "back", "from": 0, "to": 2, "direction": "reverse", "repeat": "1" },
+                { "name": "bounce", "from": 0, "to": 2, "direction": "pingpong" }
+            ]
+        }
+    }"#;
+
+    fn animation() -> Rc<Animation> {
+        let sheet = AtlasRect {
+            page: 1,
+            rect: [10, 20, 22, 24],
+        };
+        Rc::new(Animation::from_aseprite_json(SHEET, sheet).unwrap())
+    }
+
+    // samples halfway through each 0.1 second step, away from frame boundaries
+    fn frames(animator: &mut Animator, steps: usize) -> Vec<usize> {
+        animator.update(0.05);
+        (0..steps)
+            .map(|_| {
+                let frame = animator.frame();
+                animator.update(0.1);
+                frame
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frames_follow_durations_and_loop_modes() {
+        let animation = animation();
+        assert_eq!(animation.frames[1], [14, 20, 18, 24]);
+
+        let mut animator = Animator::new(animation.clone(), "loop").unwrap();
+        assert_eq!(frames(&mut animator, 6), [0, 1, 1, 2, 0, 1]);
+
+        animator.play("back").unwrap();
+        assert_eq!(frames(&mut animator, 6), [2, 1, 1, 0, 0, 0]);
+
+        animator.play("bounce").unwrap();
+        assert_eq!(frames(&mut animator, 7), [0, 1, 1, 2, 1, 1, 0]);
+
+        assert!(animator.play("missing").is_err());
+    }
+
+    #[test]
+    fn cycles_are_worked_out_once_per_clip() {
+        let animation = animation();
+        let cycles: Vec<(&[usize], f32)> = animation
+            .clips
+            .iter()
+            .map(|clip| (&clip.cycle[..], clip.cycle_length))
+            .collect();
+        // ping pong cycles come back without repeating the end frames
+        assert_eq!(
+            cycles,
+            [
+                (&[0, 1, 2][..], 0.4),
+                (&[2, 1, 0][..], 0.4),
+                (&[0, 1, 2, 1][..], 0.6)
+            ]
+        );
+    }
+
+    #[test]
+    fn elapsed_time_counts_every_cycle_until_another_clip_plays() {
+        let mut animator = Animator::new(animation(), "loop").unwrap();
+        animator.update(0.3);
+        animator.update(0.3);
+        assert!((animator.elapsed() - 0.6).abs() < 1e-6);
+        assert!((animator.time - 0.2).abs() < 1e-6);
+
+        animator.play("loop").unwrap();
+        assert!((animator.elapsed() - 0.6).abs() < 1e-6);
+        animator.play("bounce").unwrap();
+        assert_eq!(animator.elapsed(), 0.);
+    }
+}
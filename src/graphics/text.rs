@@ -0,0 +1,240 @@
+use euclid::{
+    default::{Point2D, Size2D},
+    point2, size2,
+};
+
+use crate::{
+    graphics::{SpriteBatch, SpriteInstance, TEXTURE_ATLAS_SIZE},
+    texture_atlas::AtlasRect,
+};
+
+/// A monospaced bitmap font: a strip of equally sized glyphs for consecutive characters starting
+/// at `first_char`. Each glyph cell includes the spacing to the next glyph and line.
+pub struct Font {
+    image: AtlasRect,
+    glyph_size: Size2D<u32>,
+    first_char: char,
+    glyph_count: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TextStyle {
+    pub color: [f32; 4],
+    pub align: Align,
+    /// Lines are wrapped at spaces to fit, in the units of the text position.
+    pub max_width: Option<f32>,
+    pub scale: f32,
+    /// Color of a drop shadow offset one scaled pixel down and right.
+    pub shadow: Option<[f32; 4]>,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            color: [1., 1., 1., 1.],
+            align: Align::Left,
+            max_width: None,
+            scale: 1.,
+            shadow: None,
+        }
+    }
+}
+
+impl Font {
+    pub fn new(image: AtlasRect, glyph_size: Size2D<u32>, first_char: char) -> Self {
+        Self {
+            image,
+            glyph_size,
+            first_char,
+            glyph_count: (image.rect[2] - image.rect[0]) / glyph_size.width,
+        }
+    }
+
+    // characters missing from the font fall back to uppercase, then to '?'
+    fn glyph(&self, c: char) -> Option<u32> {
+        let index = |c: char| {
+            (c as u32)
+                .checked_sub(self.first_char as u32)
+                .filter(|i| *i < self.glyph_count)
+        };
+        index(c)
+            .or_else(|| index(c.to_ascii_uppercase()))
+            .or_else(|| index('?'))
+    }
+
+    fn advance(&self, style: &TextStyle) -> f32 {
+        self.glyph_size.width as f32 * style.scale
+    }
+
+    pub fn line_height(&self, style: &TextStyle) -> f32 {
+        self.glyph_size.height as f32 * style.scale
+    }
+
+    /// Splits `text` into lines at newlines and wherever it would overflow `style.max_width`.
+    pub fn layout_lines(&self, text: &str, style: &TextStyle) -> Vec<String> {
+        let max_chars = style
+            .max_width
+            .map(|width| ((width / self.advance(style)).floor() as usize).max(1));
+
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            let max_chars = match max_chars {
+                Some(max_chars) => max_chars,
+                None => {
+                    lines.push(paragraph.to_owned());
+                    continue;
+                }
+            };
+
+            let mut line = String::new();
+            for word in paragraph.split(' ') {
+                let line_len = line.chars().count();
+                let word_len = word.chars().count();
+                if line_len > 0 && line_len + 1 + word_len > max_chars {
+                    lines.push(std::mem::take(&mut line));
+                }
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(word);
+                // words longer than a whole line are broken anywhere
+                while line.chars().count() > max_chars {
+                    let split = line
+                        .char_indices()
+                        .nth(max_chars)
+                        .map(|(i, _)| i)
+                        .unwrap_or_else(|| line.len());
+                    let rest = line.split_off(split);
+                    lines.push(std::mem::replace(&mut line, rest));
+                }
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    pub fn measure(&self, text: &str, style: &TextStyle) -> Size2D<f32> {
+        let lines = self.layout_lines(text, style);
+        let longest = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        size2(
+            longest as f32 * self.advance(style),
+            lines.len() as f32 * self.line_height(style),
+        )
+    }
+}
+
+/// Lays out `text` with its first line's top at `position.y`. `position.x` is the left edge,
+/// center or right edge of each line, depending on `style.align`.
+pub fn render_text(
+    font: &Font,
+    text: &str,
+    position: Point2D<f32>,
+    style: &TextStyle,
+    out: &mut SpriteBatch,
+) {
+    let lines = font.layout_lines(text, style);
+    if let Some(shadow) = style.shadow {
+        let offset = point2(position.x + style.scale, position.y - style.scale);
+        render_lines(font, &lines, offset, style, shadow, out);
+    }
+    render_lines(font, &lines, position, style, style.color, out);
+}
+
+fn render_lines(
+    font: &Font,
+    lines: &[String],
+    position: Point2D<f32>,
+    style: &TextStyle,
+    color: [f32; 4],
+    out: &mut SpriteBatch,
+) {
+    let advance = font.advance(style);
+    let line_height = font.line_height(style);
+    for (i, line) in lines.iter().enumerate() {
+        let width = line.chars().count() as f32 * advance;
+        let left = match style.align {
+            Align::Left => position.x,
+            Align::Center => position.x - (width / 2.).floor(),
+            Align::Right => position.x - width,
+        };
+        let top = position.y - i as f32 * line_height;
+
+        for (j, c) in line.chars().enumerate() {
+            let glyph = match font.glyph(c) {
+                Some(glyph) if c != ' ' => glyph,
+                _ => continue,
+            };
+            let x0 = font.image.rect[0] + glyph * font.glyph_size.width;
+            let y0 = font.image.rect[1];
+            let uv_min = [
+                x0 as f32 / TEXTURE_ATLAS_SIZE.width as f32,
+                y0 as f32 / TEXTURE_ATLAS_SIZE.height as f32,
+            ];
+            let uv_max = [
+                (x0 + font.glyph_size.width) as f32 / TEXTURE_ATLAS_SIZE.width as f32,
+                (y0 + font.glyph_size.height) as f32 / TEXTURE_ATLAS_SIZE.height as f32,
+            ];
+
+            let min: Point2D<f32> = point2(left + j as f32 * advance, top - line_height);
+            let max: Point2D<f32> = point2(min.x + advance, top);
+            out.push_instance(
+                font.image.page,
+                SpriteInstance::rect(
+                    min,
+                    max,
+                    [uv_min[0], uv_max[1]],
+                    [uv_max[0], uv_min[1]],
+                    color,
+                ),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::size2;
+
+    use super::{Font, TextStyle};
+    use crate::texture_atlas::AtlasRect;
+
+    fn font() -> Font {
+        let image = AtlasRect {
+            page: 0,
+            rect: [0, 0, 256, 6],
+        };
+        Font::new(image, size2(4, 6), ' ')
+    }
+
+    #[test]
+    fn wraps_at_spaces() {
+        let style = TextStyle {
+            max_width: Some(40.),
+            ..TextStyle::default()
+        };
+        assert_eq!(
+            font().layout_lines("move with wasd to start\nthe loop", &style),
+            vec!["move with", "wasd to", "start", "the loop"]
+        );
+    }
+
+    #[test]
+    fn breaks_words_longer_than_a_line() {
+        let style = TextStyle {
+            max_width: Some(16.),
+            ..TextStyle::default()
+        };
+        assert_eq!(
+            font().layout_lines("a teleporter", &style),
+            vec!["a", "tele", "port", "er"]
+        );
+        assert_eq!(font().measure("a teleporter", &style), size2(16., 24.));
+    }
+}
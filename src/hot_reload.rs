@@ -0,0 +1,118 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often the watched directories are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches source directories for changed files by polling their modification times, so assets
+/// and shaders can be reloaded while the game runs. Only debug builds on native watch anything,
+/// release builds and the web only have what was compiled in.
+pub struct Watcher {
+    dirs: Vec<PathBuf>,
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant,
+}
+
+impl Watcher {
+    /// Watches the files directly in `dirs`, relative to the crate root. `None` unless this is a
+    /// native debug build.
+    pub fn new(dirs: &[&str]) -> Option<Self> {
+        if !cfg!(debug_assertions) || cfg!(target_arch = "wasm32") {
+            return None;
+        }
+        Some(Self::watch(
+            dirs.iter().map(|dir| source_path(dir)).collect(),
+        ))
+    }
+
+    fn watch(dirs: Vec<PathBuf>) -> Self {
+        let mut watcher = Self {
+            dirs,
+            modified: HashMap::new(),
+            last_poll: Instant::now(),
+        };
+        watcher.poll();
+        watcher
+    }
+
+    /// Files modified or added since the last call, checked at most every `POLL_INTERVAL`.
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+        self.poll()
+    }
+
+    fn poll(&mut self) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        for dir in self.dirs.iter() {
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    log::warn!("Could not watch {}: {}", dir.display(), e);
+                    continue;
+                }
+            };
+            for entry in entries.flatten() {
+                let modified = match entry.metadata().and_then(|m| m.modified()) {
+                    Ok(modified) => modified,
+                    Err(_) => continue,
+                };
+                let path = entry.path();
+                if self.modified.insert(path.clone(), modified) != Some(modified) {
+                    changed.push(path);
+                }
+            }
+        }
+        changed.sort();
+        changed
+    }
+}
+
+/// Where a file of the crate's sources is, for reading the current version of something that was
+/// compiled in.
+pub fn source_path(relative: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(relative)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{Watcher, POLL_INTERVAL};
+
+    #[test]
+    fn changed_files_are_found_once() {
+        let dir = env::temp_dir().join(format!("ld47-watcher-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("old.png");
+        fs::write(&old, "old").unwrap();
+
+        let mut watcher = Watcher::watch(vec![dir.clone()]);
+        let new = dir.join("new.png");
+        fs::write(&new, "new").unwrap();
+        // not polled again yet
+        assert!(watcher.changed().is_empty());
+        watcher.last_poll -= POLL_INTERVAL;
+        assert_eq!(watcher.changed(), vec![new.clone()]);
+
+        // modification times may be coarse, so move it well past the last
+        let file = fs::File::options().write(true).open(&old).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        watcher.last_poll -= POLL_INTERVAL;
+        assert_eq!(watcher.changed(), vec![old]);
+        watcher.last_poll -= POLL_INTERVAL;
+        assert!(watcher.changed().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 use euclid::default::{Point2D, Vector2D};
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
@@ -40,6 +42,79 @@ pub enum Key {
     Up,
     Right,
     Down,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+}
+
+/// Parses the `Debug` name of a key, e.g. `"Space"`.
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Key, String> {
+        match s {
+            "A" => Ok(Key::A),
+            "B" => Ok(Key::B),
+            "C" => Ok(Key::C),
+            "D" => Ok(Key::D),
+            "E" => Ok(Key::E),
+            "F" => Ok(Key::F),
+            "G" => Ok(Key::G),
+            "H" => Ok(Key::H),
+            "I" => Ok(Key::I),
+            "J" => Ok(Key::J),
+            "K" => Ok(Key::K),
+            "L" => Ok(Key::L),
+            "M" => Ok(Key::M),
+            "N" => Ok(Key::N),
+            "O" => Ok(Key::O),
+            "P" => Ok(Key::P),
+            "Q" => Ok(Key::Q),
+            "R" => Ok(Key::R),
+            "S" => Ok(Key::S),
+            "T" => Ok(Key::T),
+            "U" => Ok(Key::U),
+            "V" => Ok(Key::V),
+            "W" => Ok(Key::W),
+            "X" => Ok(Key::X),
+            "Y" => Ok(Key::Y),
+            "Z" => Ok(Key::Z),
+            "Space" => Ok(Key::Space),
+            "Backspace" => Ok(Key::Backspace),
+            "Return" => Ok(Key::Return),
+            "Escape" => Ok(Key::Escape),
+            "Slash" => Ok(Key::Slash),
+            "Home" => Ok(Key::Home),
+            "Delete" => Ok(Key::Delete),
+            "End" => Ok(Key::End),
+            "Left" => Ok(Key::Left),
+            "Up" => Ok(Key::Up),
+            "Right" => Ok(Key::Right),
+            "Down" => Ok(Key::Down),
+            "F1" => Ok(Key::F1),
+            "F2" => Ok(Key::F2),
+            "F3" => Ok(Key::F3),
+            "F4" => Ok(Key::F4),
+            "F5" => Ok(Key::F5),
+            "F6" => Ok(Key::F6),
+            "F7" => Ok(Key::F7),
+            "F8" => Ok(Key::F8),
+            "F9" => Ok(Key::F9),
+            "F10" => Ok(Key::F10),
+            "F11" => Ok(Key::F11),
+            "F12" => Ok(Key::F12),
+            _ => Err(format!("unknown key {}", s)),
+        }
+    }
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
@@ -56,6 +131,7 @@ pub enum InputEvent {
     KeyUp(Key),
     MouseDown(MouseButton),
     MouseUp(MouseButton),
+    /// Cursor position in screen pixels, origin at the top left of the game screen.
     MouseMove(Point2D<f32>),
     MouseWheel(Vector2D<f32>),
 }
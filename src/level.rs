@@ -6,12 +6,13 @@ use euclid::{
 };
 
 use crate::{
-    gl,
-    graphics::{Vertex, TEXTURE_ATLAS_SIZE},
-    texture_atlas::TextureRect,
+    graphics::{SpriteBatch, SpriteInstance, TEXTURE_ATLAS_SIZE},
+    sprite_atlas::sprite_rect,
+    texture_atlas::AtlasRect,
 };
 
 pub struct Level {
+    pub name: String,
     pub tiles: Vec<Vec<Tile>>,
     pub player_start: Point2D<f32>,
     pub buttons: HashMap<Point2D<i32>, ButtonTile>,
@@ -19,6 +20,30 @@ pub struct Level {
     pub teleporters: HashMap<Point2D<i32>, TeleporterTile>,
     pub bulbs: HashSet<Point2D<i32>>,
     pub the_machine: Point2D<i32>,
+    /// Light color where no light reaches, `None` draws the level without lighting.
+    pub ambient_light: Option<[f32; 3]>,
+    /// Drawn in order within each depth.
+    pub layers: Vec<TileLayer>,
+}
+
+/// When a tile layer is drawn relative to the entities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayerDepth {
+    /// Floors and walls, below everything else.
+    Ground,
+    /// Above the ground and below entities.
+    Decoration,
+    /// Above ghosts and every other entity.
+    Overhang,
+}
+
+/// Tiles cut from one tileset. Layers are only drawn, gameplay only looks at `Level::tiles`.
+pub struct TileLayer {
+    pub depth: LayerDepth,
+    pub tileset: AtlasRect,
+    /// Rows from the bottom like `Level::tiles`. Each tile is the column and row of its cell in
+    /// the tileset, counted in `TILE_SIZE` cells from the top left.
+    pub tiles: Vec<Vec<Option<[u32; 2]>>>,
 }
 
 impl Level {
@@ -241,7 +266,8 @@ pub fn create_level() -> Level {
         }
     }
 
-    Level {
+    let mut level = Level {
+        name: "The Machine".to_owned(),
         tiles,
         player_start,
         buttons,
@@ -249,6 +275,190 @@ pub fn create_level() -> Level {
         teleporters,
         bulbs,
         the_machine: the_machine.expect("No TheMachine found"),
+        ambient_light: Some([0.55, 0.5, 0.65]),
+        layers: Vec::new(),
+    };
+
+    let floor = sprite_rect("ground").unwrap();
+    let walls = sprite_rect("walls").unwrap();
+    let decoration = sprite_rect("decoration").unwrap();
+    level.layers = vec![
+        TileLayer {
+            depth: LayerDepth::Ground,
+            tileset: floor,
+            tiles: autotile(&level, |tile, _| match tile {
+                Tile::Floor => Some([0, 0]),
+                Tile::Wall => None,
+            }),
+        },
+        TileLayer {
+            depth: LayerDepth::Ground,
+            tileset: walls,
+            tiles: autotile(&level, |tile, neighbours| match tile {
+                Tile::Floor => None,
+                Tile::Wall => Some(wall_cell(neighbours)),
+            }),
+        },
+        parse_layer(
+            LayerDepth::Decoration,
+            decoration,
+            &DECORATION,
+            &DECORATION_LEGEND,
+        ),
+        parse_layer(
+            LayerDepth::Overhang,
+            decoration,
+            &OVERHANG,
+            &OVERHANG_LEGEND,
+        ),
+    ];
+    level
+}
+
+// Cells of the decoration tileset for each character of the layer maps, '#' and ' ' are empty.
+const DECORATION_LEGEND: [(char, [u32; 2]); 4] =
+    [('c', [0, 0]), ('p', [1, 0]), ('m', [2, 0]), ('g', [3, 0])];
+const OVERHANG_LEGEND: [(char, [u32; 2]); 4] =
+    [('<', [0, 1]), ('>', [1, 1]), ('i', [2, 1]), ('=', [3, 1])];
+
+const DECORATION: [&str; LEVEL_HEIGHT] = [
+    "########################################",
+    "########  g ## #### ######## ###########",
+    "######## g  ## #### ######## ###########",
+    "########    ## #### ##    ## ###########",
+    "##p ##### ######### ##    ## ###########",
+    "## c##### ######### ####  ##p###########",
+    "#   ##                ##  ## ###########",
+    "######          g         ## ###########",
+    "######    m g   p     ##################",
+    "##  ##           c c m##################",
+    "##  ##         cg     ##     ###########",
+    "##             c      #### #############",
+    "###c##      p  m      #### #############",
+    "### ###### ##### #########     ##  g  ##",
+    "### ###### ##### ######### ########## ##",
+    "###   ####m  ###  ########p  #   g ## ##",
+    "##########   ###p ######################",
+    "####### ##   ### #######################",
+    "####### ####################       ## ##",
+    "####### ##       #################### ##",
+    "#################################     ##",
+    "########################################",
+];
+
+const OVERHANG: [&str; LEVEL_HEIGHT] = [
+    "########################################",
+    "########    ## #### ######## ###########",
+    "########    ## #### ######## ###########",
+    "########    ## #### ##  > ## ###########",
+    "##< ##### ######### ##    ## ###########",
+    "##  ##### ######### ####  ## ###########",
+    "#   ##  i ========= i ##  ## ###########",
+    "######                    ## ###########",
+    "######                ##################",
+    "##< ##                ##################",
+    "##  ##                ##     ###########",
+    "##                    #### #############",
+    "### ##                #### #############",
+    "### ###### ##### #########     ##     ##",
+    "### ###### ##### ######### ########## ##",
+    "### i ####   ###  ########   # i   ## ##",
+    "##########   ###  ######################",
+    "####### ##   ### #######################",
+    "####### ####################       ## ##",
+    "####### ## i     #################### ##",
+    "#################################     ##",
+    "########################################",
+];
+
+fn parse_layer(
+    depth: LayerDepth,
+    tileset: AtlasRect,
+    map: &[&str; LEVEL_HEIGHT],
+    legend: &[(char, [u32; 2])],
+) -> TileLayer {
+    let tiles = (0..LEVEL_HEIGHT)
+        .map(|y_tile| {
+            map[LEVEL_HEIGHT - 1 - y_tile]
+                .chars()
+                .map(|c| match c {
+                    '#' | ' ' => None,
+                    c => Some(
+                        legend
+                            .iter()
+                            .find(|(key, _)| *key == c)
+                            .unwrap_or_else(|| panic!("unknown layer tile {}", c))
+                            .1,
+                    ),
+                })
+                .collect()
+        })
+        .collect();
+    TileLayer {
+        depth,
+        tileset,
+        tiles,
+    }
+}
+
+// The 3x3 neighbourhood of a tile, `neighbours[y][x]` is true for walls, with y going up.
+type Neighbours = [[bool; 3]; 3];
+
+fn autotile(
+    level: &Level,
+    cell: impl Fn(Tile, &Neighbours) -> Option<[u32; 2]>,
+) -> Vec<Vec<Option<[u32; 2]>>> {
+    (0..level.height())
+        .map(|y_tile| {
+            (0..level.width(y_tile))
+                .map(|x_tile| {
+                    let mut neighbours = [[false; 3]; 3];
+                    for (y, row) in neighbours.iter_mut().enumerate() {
+                        for (x, wall) in row.iter_mut().enumerate() {
+                            *wall = level
+                                .tile(x_tile as i32 + x as i32 - 1, y_tile as i32 + y as i32 - 1)
+                                == Tile::Wall;
+                        }
+                    }
+                    cell(level.tile(x_tile as i32, y_tile as i32), &neighbours)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Picks the wall tile matching which sides and inner corners face open floor.
+fn wall_cell(n: &Neighbours) -> [u32; 2] {
+    let (tl, t, tr) = (n[2][0], n[2][1], n[2][2]);
+    let (l, r) = (n[1][0], n[1][2]);
+    let (bl, b, br) = (n[0][0], n[0][1], n[0][2]);
+
+    if t && r && !tr {
+        [0, 2]
+    } else if t && l && !tl {
+        [2, 2]
+    } else if b && r && !br {
+        [0, 0]
+    } else if b && l && !bl {
+        [2, 0]
+    } else if !t && !l {
+        [3, 0]
+    } else if !t && !r {
+        [4, 0]
+    } else if !t {
+        [1, 2]
+    } else if !b && !l {
+        [3, 1]
+    } else if !b && !r {
+        [4, 1]
+    } else if !b {
+        [1, 0]
+    } else if !l {
+        [2, 1]
+    } else if !r {
+        [0, 1]
+    } else {
+        [1, 1]
     }
 }
 
@@ -273,175 +483,132 @@ pub struct TeleporterTile {
     pub connection: Option<Point2D<i32>>,
 }
 
-pub fn generate_tile_buffer(
-    level: &Level,
-    floor: TextureRect,
-    walls: TextureRect,
-    context: &mut gl::Context,
-) -> gl::VertexBuffer {
-    let mut vertices = Vec::new();
-
-    for y_tile in 0..level.height() {
-        for x_tile in 0..level.width(y_tile) {
-            let tile = match level.tile(x_tile as i32, y_tile as i32) {
-                Tile::Floor => floor,
-                Tile::Wall => {
-                    let tl = level.tile(x_tile as i32 - 1, y_tile as i32 + 1) == Tile::Wall;
-                    let t = level.tile(x_tile as i32, y_tile as i32 + 1) == Tile::Wall;
-                    let tr = level.tile(x_tile as i32 + 1, y_tile as i32 + 1) == Tile::Wall;
-                    let l = level.tile(x_tile as i32 - 1, y_tile as i32) == Tile::Wall;
-                    let r = level.tile(x_tile as i32 + 1, y_tile as i32) == Tile::Wall;
-                    let bl = level.tile(x_tile as i32 - 1, y_tile as i32 - 1) == Tile::Wall;
-                    let b = level.tile(x_tile as i32, y_tile as i32 - 1) == Tile::Wall;
-                    let br = level.tile(x_tile as i32 + 1, y_tile as i32 - 1) == Tile::Wall;
-
-                    if t && r && !tr {
-                        [
-                            walls[0] + 0 * TILE_SIZE,
-                            walls[1] + 2 * TILE_SIZE,
-                            walls[0] + 1 * TILE_SIZE,
-                            walls[1] + 3 * TILE_SIZE,
-                        ]
-                    } else if t && l && !tl {
-                        [
-                            walls[0] + 2 * TILE_SIZE,
-                            walls[1] + 2 * TILE_SIZE,
-                            walls[0] + 3 * TILE_SIZE,
-                            walls[1] + 3 * TILE_SIZE,
-                        ]
-                    } else if b && r && !br {
-                        [
-                            walls[0] + 0 * TILE_SIZE,
-                            walls[1] + 0 * TILE_SIZE,
-                            walls[0] + 1 * TILE_SIZE,
-                            walls[1] + 1 * TILE_SIZE,
-                        ]
-                    } else if b && l && !bl {
-                        [
-                            walls[0] + 2 * TILE_SIZE,
-                            walls[1] + 0 * TILE_SIZE,
-                            walls[0] + 3 * TILE_SIZE,
-                            walls[1] + 1 * TILE_SIZE,
-                        ]
-                    } else if !t && !l {
-                        [
-                            walls[0] + 3 * TILE_SIZE,
-                            walls[1] + 0 * TILE_SIZE,
-                            walls[0] + 4 * TILE_SIZE,
-                            walls[1] + 1 * TILE_SIZE,
-                        ]
-                    } else if !t && !r {
-                        [
-                            walls[0] + 4 * TILE_SIZE,
-                            walls[1] + 0 * TILE_SIZE,
-                            walls[0] + 5 * TILE_SIZE,
-                            walls[1] + 1 * TILE_SIZE,
-                        ]
-                    } else if !t {
-                        [
-                            walls[0] + 1 * TILE_SIZE,
-                            walls[1] + 2 * TILE_SIZE,
-                            walls[0] + 2 * TILE_SIZE,
-                            walls[1] + 3 * TILE_SIZE,
-                        ]
-                    } else if !b && !l {
-                        [
-                            walls[0] + 3 * TILE_SIZE,
-                            walls[1] + 1 * TILE_SIZE,
-                            walls[0] + 4 * TILE_SIZE,
-                            walls[1] + 2 * TILE_SIZE,
-                        ]
-                    } else if !b && !r {
-                        [
-                            walls[0] + 4 * TILE_SIZE,
-                            walls[1] + 1 * TILE_SIZE,
-                            walls[0] + 5 * TILE_SIZE,
-                            walls[1] + 2 * TILE_SIZE,
-                        ]
-                    } else if !b {
-                        [
-                            walls[0] + 1 * TILE_SIZE,
-                            walls[1] + 0 * TILE_SIZE,
-                            walls[0] + 2 * TILE_SIZE,
-                            walls[1] + 1 * TILE_SIZE,
-                        ]
-                    } else if !l {
-                        [
-                            walls[0] + 2 * TILE_SIZE,
-                            walls[1] + 1 * TILE_SIZE,
-                            walls[0] + 3 * TILE_SIZE,
-                            walls[1] + 2 * TILE_SIZE,
-                        ]
-                    } else if !r {
-                        [
-                            walls[0] + 0 * TILE_SIZE,
-                            walls[1] + 1 * TILE_SIZE,
-                            walls[0] + 1 * TILE_SIZE,
-                            walls[1] + 2 * TILE_SIZE,
-                        ]
-                    } else {
-                        [
-                            walls[0] + 1 * TILE_SIZE,
-                            walls[1] + 1 * TILE_SIZE,
-                            walls[0] + 2 * TILE_SIZE,
-                            walls[1] + 2 * TILE_SIZE,
-                        ]
+/// Quads of the level's tile layers at the given depths in tile space, one tile per unit.
+pub fn generate_tile_batch(level: &Level, depths: &[LayerDepth]) -> SpriteBatch {
+    let mut batch = SpriteBatch::new();
+
+    for layer in level
+        .layers
+        .iter()
+        .filter(|layer| depths.contains(&layer.depth))
+    {
+        let tileset = layer.tileset.rect;
+        for (y_tile, row) in layer.tiles.iter().enumerate() {
+            for (x_tile, cell) in row.iter().enumerate() {
+                let [column, row] = match cell {
+                    Some(cell) => *cell,
+                    None => continue,
+                };
+                let tile = [
+                    tileset[0] + column * TILE_SIZE,
+                    tileset[1] + row * TILE_SIZE,
+                    tileset[0] + (column + 1) * TILE_SIZE,
+                    tileset[1] + (row + 1) * TILE_SIZE,
+                ];
+
+                let tile_rect = Box2D::new(
+                    point2(x_tile as f32, y_tile as f32),
+                    point2((x_tile + 1) as f32, (y_tile + 1) as f32),
+                );
+                let uv_pos = point2(
+                    tile[0] as f32 / TEXTURE_ATLAS_SIZE.width as f32,
+                    tile[1] as f32 / TEXTURE_ATLAS_SIZE.height as f32,
+                );
+                let uv_size = size2(
+                    (tile[2] - tile[0]) as f32 / TEXTURE_ATLAS_SIZE.width as f32,
+                    (tile[3] - tile[1]) as f32 / TEXTURE_ATLAS_SIZE.height as f32,
+                );
+                let uv_rect = Rect::new(uv_pos, uv_size);
+
+                batch.push_instance(
+                    layer.tileset.page,
+                    SpriteInstance::rect(
+                        tile_rect.min,
+                        tile_rect.max,
+                        [uv_rect.min_x(), uv_rect.max_y()],
+                        [uv_rect.max_x(), uv_rect.min_y()],
+                        [1., 1., 1., 1.],
+                    ),
+                );
+            }
+        }
+    }
+
+    batch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        create_level, generate_tile_batch, parse_layer, LayerDepth, Level, Tile, DECORATION,
+        DECORATION_LEGEND,
+    };
+    use crate::texture_atlas::AtlasRect;
+
+    // the tile positions of the level's layers at `depths`
+    fn cells(level: &Level, depths: &[LayerDepth]) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+        for layer in level.layers.iter() {
+            if !depths.contains(&layer.depth) {
+                continue;
+            }
+            for (y, row) in layer.tiles.iter().enumerate() {
+                for (x, cell) in row.iter().enumerate() {
+                    if cell.is_some() {
+                        cells.push((x, y));
                     }
                 }
-            };
+            }
+        }
+        cells.sort();
+        cells
+    }
 
-            let tile_rect = Box2D::new(
-                point2(x_tile as f32, y_tile as f32),
-                point2((x_tile + 1) as f32, (y_tile + 1) as f32),
-            );
-            let uv_pos = point2(
-                tile[0] as f32 / TEXTURE_ATLAS_SIZE.width as f32,
-                tile[1] as f32 / TEXTURE_ATLAS_SIZE.height as f32,
-            );
-            let uv_size = size2(
-                (tile[2] - tile[0]) as f32 / TEXTURE_ATLAS_SIZE.width as f32,
-                (tile[3] - tile[1]) as f32 / TEXTURE_ATLAS_SIZE.height as f32,
+    #[test]
+    #[should_panic(expected = "unknown layer tile x")]
+    fn layers_reject_unknown_characters() {
+        let mut map = DECORATION;
+        map[6] = "#   ##   x            ##  ## ###########";
+        let tileset = AtlasRect {
+            page: 0,
+            rect: [0, 0, 64, 32],
+        };
+        parse_layer(LayerDepth::Decoration, tileset, &map, &DECORATION_LEGEND);
+    }
+
+    #[test]
+    fn decorations_and_overhangs_only_sit_on_floor() {
+        let level = create_level();
+        let above_ground = cells(&level, &[LayerDepth::Decoration, LayerDepth::Overhang]);
+        assert!(!above_ground.is_empty());
+        for (x, y) in above_ground {
+            assert!(
+                level.tiles[y][x] == Tile::Floor,
+                "decoration on the wall at {}, {}",
+                x,
+                y
             );
-            let uv_rect = Rect::new(uv_pos, uv_size);
-
-            vertices.extend_from_slice(&[
-                Vertex {
-                    position: tile_rect.min.to_array(),
-                    uv: [uv_rect.min_x(), uv_rect.max_y()],
-                    color: [1., 1., 1., 1.],
-                },
-                Vertex {
-                    position: [tile_rect.max.x, tile_rect.min.y],
-                    uv: [uv_rect.max_x(), uv_rect.max_y()],
-                    color: [1., 1., 1., 1.],
-                },
-                Vertex {
-                    position: [tile_rect.min.x, tile_rect.max.y],
-                    uv: [uv_rect.min_x(), uv_rect.min_y()],
-                    color: [1., 1., 1., 1.],
-                },
-                Vertex {
-                    position: [tile_rect.max.x, tile_rect.min.y],
-                    uv: [uv_rect.max_x(), uv_rect.max_y()],
-                    color: [1., 1., 1., 1.],
-                },
-                Vertex {
-                    position: tile_rect.max.to_array(),
-                    uv: [uv_rect.max_x(), uv_rect.min_y()],
-                    color: [1., 1., 1., 1.],
-                },
-                Vertex {
-                    position: [tile_rect.min.x, tile_rect.max.y],
-                    uv: [uv_rect.min_x(), uv_rect.min_y()],
-                    color: [1., 1., 1., 1.],
-                },
-            ]);
         }
     }
 
-    unsafe {
-        let mut vertex_buffer = context.create_vertex_buffer().unwrap();
-        vertex_buffer.write(&vertices);
-        vertex_buffer
+    #[test]
+    fn tile_batches_only_hold_the_requested_depths() {
+        let level = create_level();
+        for depths in [
+            &[LayerDepth::Ground][..],
+            &[LayerDepth::Decoration],
+            &[LayerDepth::Overhang],
+            &[LayerDepth::Ground, LayerDepth::Overhang],
+        ]
+        .iter()
+        {
+            let mut quads: Vec<(usize, usize)> = generate_tile_batch(&level, depths)
+                .batches()
+                .flat_map(|(_, quads)| quads.iter())
+                .map(|quad| (quad.origin[0] as usize, quad.origin[1] as usize))
+                .collect();
+            quads.sort();
+            assert_eq!(quads, cells(&level, depths), "{:?}", depths);
+        }
     }
 }
@@ -0,0 +1,128 @@
+use anyhow::Error;
+use euclid::default::{Point2D, Transform2D};
+
+use crate::{
+    gl,
+    graphics::{Vertex, MAX_BATCH_QUADS},
+};
+
+/// A round light, in tile coordinates like the entity it belongs to.
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub position: Point2D<f32>,
+    pub radius: f32,
+    pub color: [f32; 3],
+}
+
+/// Draws lights into a light map the size of the world target. The world is multiplied by the
+/// light map afterwards, so the ambient color is what is left where no light reaches.
+pub struct Lighting {
+    program: gl::Program,
+    vertices: Vec<Vertex>,
+    vertex_buffer: gl::VertexBuffer,
+    light_map: gl::RenderTarget,
+}
+
+impl Lighting {
+    pub unsafe fn new(context: &mut gl::Context, width: u32, height: u32) -> Result<Self, Error> {
+        Ok(Self {
+            program: create_program(
+                context,
+                include_str!("shaders/light.vert"),
+                include_str!("shaders/light.frag"),
+            )?,
+            vertices: Vec::new(),
+            vertex_buffer: context.create_vertex_buffer(gl::BufferUsage::Stream)?,
+            light_map: context.create_render_target(width, height)?,
+        })
+    }
+
+    /// Replaces the light program, keeping the old one if the new sources don't compile.
+    pub unsafe fn reload_shaders(
+        &mut self,
+        context: &mut gl::Context,
+        vertex_source: &str,
+        fragment_source: &str,
+    ) -> Result<(), Error> {
+        self.program = create_program(context, vertex_source, fragment_source)?;
+        Ok(())
+    }
+
+    pub fn light_map(&self) -> &gl::Texture {
+        self.light_map.texture()
+    }
+
+    /// Renders the light map, `transform` being the same world transform the world target was
+    /// drawn with. Leaves the light map bound as the render target.
+    pub unsafe fn draw(
+        &mut self,
+        context: &mut gl::Context,
+        ambient: [f32; 3],
+        lights: &[Light],
+        transform: &Transform2D<f32>,
+        index_buffer: &gl::IndexBuffer,
+    ) -> Result<(), Error> {
+        self.vertices.clear();
+        for light in lights.iter().take(MAX_BATCH_QUADS) {
+            let vertex = |x: f32, y: f32| Vertex {
+                position: [
+                    light.position.x + x * light.radius,
+                    light.position.y + y * light.radius,
+                ],
+                uv: [x, y],
+                color: [light.color[0], light.color[1], light.color[2], 1.],
+            };
+            self.vertices.extend_from_slice(&[
+                vertex(-1., -1.),
+                vertex(1., -1.),
+                vertex(-1., 1.),
+                vertex(1., 1.),
+            ]);
+        }
+        self.vertex_buffer.write(&self.vertices);
+
+        self.program.set_uniform(
+            "u_transform",
+            gl::Uniform::Mat3([
+                [transform.m11, transform.m12, 0.0],
+                [transform.m21, transform.m22, 0.0],
+                [transform.m31, transform.m32, 1.0],
+            ]),
+        )?;
+
+        context.bind_render_target(Some(&self.light_map));
+        context.clear([ambient[0], ambient[1], ambient[2], 1.]);
+        if !self.vertices.is_empty() {
+            self.program.render_indexed(
+                &self.vertex_buffer,
+                index_buffer,
+                0,
+                self.vertices.len() / 4 * 6,
+                &gl::DrawState {
+                    blend_mode: gl::BlendMode::Additive,
+                    ..Default::default()
+                },
+            )?;
+        }
+        Ok(())
+    }
+}
+
+unsafe fn create_program(
+    context: &mut gl::Context,
+    vertex_source: &str,
+    fragment_source: &str,
+) -> Result<gl::Program, Error> {
+    let vertex_shader = context.create_shader(gl::ShaderType::Vertex, vertex_source)?;
+    let fragment_shader = context.create_shader(gl::ShaderType::Fragment, fragment_source)?;
+    Ok(context.create_program(&gl::ProgramDescriptor {
+        vertex_shader: &vertex_shader,
+        fragment_shader: &fragment_shader,
+        uniforms: &[gl::UniformEntry {
+            name: "u_transform",
+            ty: gl::UniformType::Mat3,
+        }],
+        vertex_format: gl::VertexFormat::of::<Vertex>(),
+        instance_format: None,
+    })?)
+}
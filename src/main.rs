@@ -1,17 +1,31 @@
 mod constants;
+mod determinism;
+mod frame_times;
 mod game;
 #[allow(unused)]
 mod gl;
 mod graphics;
+mod hot_reload;
 mod input;
 mod level;
+mod lighting;
+mod minimap;
 mod mixer;
+mod particles;
 mod platform;
+mod post_process;
+#[cfg(test)]
+mod rasterizer;
+mod sprite_atlas;
+#[allow(unused)]
 mod texture_atlas;
+mod viewport;
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use constants::{SCREEN_SIZE, TICK_DT};
+use determinism::{check_trace, DeterminismMode, TraceRecorder};
+use frame_times::FrameTimes;
 use game::Game;
 use input::InputEvent;
 
@@ -25,9 +39,41 @@ fn main() {
             platform::start_audio_playback(move |out: &mut [i16]| mixer_inner.poll(out));
 
             let mut game = Game::new(gl_context, mixer);
+            let mut frame_times = platform::bench_ghosts().map(|count| {
+                game.add_bench_ghosts(count);
+                FrameTimes::new()
+            });
+            // benchmarks switch how sprites are written after each report, so every usage is
+            // measured on the same scene
+            let mut sprite_usage = gl::BufferUsage::Stream;
+
+            let recorder = match platform::determinism_mode() {
+                DeterminismMode::Off => None,
+                DeterminismMode::Record(out) => Some(out),
+                DeterminismMode::Check(trace) => {
+                    let result = check_trace(&trace, |inputs| {
+                        game.update(inputs);
+                        game.state_hash()
+                    });
+                    match result {
+                        Ok(updates) => {
+                            log::info!("State trace matches over {} updates", updates);
+                            std::process::exit(0);
+                        }
+                        Err(divergence) => {
+                            log::error!("{}", divergence);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            };
+            let mut recorder = TraceRecorder::new(recorder);
+
             let mut input_vec = Vec::new();
             let mut last_update: f32 = 0.;
             move |dt: f32, inputs: &[InputEvent], gl_context: &mut gl::Context| {
+                let started = frame_times.as_ref().map(|_| Instant::now());
+
                 // accumulate input over several frames
                 input_vec.extend_from_slice(inputs);
 
@@ -35,12 +81,26 @@ fn main() {
                 last_update = (last_update + dt).min(TICK_DT * 5.);
                 while last_update > TICK_DT {
                     game.update(&input_vec);
+                    recorder.record(&input_vec, game.state_hash());
 
                     last_update -= TICK_DT;
                     input_vec.clear();
                 }
 
                 game.draw(gl_context);
+
+                if let (Some(frame_times), Some(started)) = (&mut frame_times, started) {
+                    let work = started.elapsed();
+                    let label = format_args!("{:?} sprite buffers", sprite_usage);
+                    if frame_times.frame(label, work, dt) {
+                        sprite_usage = match sprite_usage {
+                            gl::BufferUsage::Stream => gl::BufferUsage::Static,
+                            gl::BufferUsage::Static => gl::BufferUsage::Dynamic,
+                            gl::BufferUsage::Dynamic => gl::BufferUsage::Stream,
+                        };
+                        unsafe { game.set_sprite_buffer_usage(gl_context, sprite_usage) }.unwrap();
+                    }
+                }
             }
         },
     )
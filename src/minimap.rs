@@ -0,0 +1,275 @@
+use anyhow::Error;
+use euclid::default::Point2D;
+
+use crate::{
+    gl,
+    graphics::{render_target_quad, SpriteBatch, SpriteBuffer},
+    level::{Level, Tile},
+};
+
+/// Pixels per tile on the minimap.
+const CELL: u32 = 3;
+
+const FLOOR_COLOR: [u8; 4] = [142, 109, 109, 230];
+const WALL_COLOR: [u8; 4] = [40, 30, 30, 200];
+
+// marker shapes, as pixels of a tile's cell
+const FULL: [(i32, i32); 9] = [
+    (0, 0),
+    (1, 0),
+    (2, 0),
+    (0, 1),
+    (1, 1),
+    (2, 1),
+    (0, 2),
+    (1, 2),
+    (2, 2),
+];
+const RING: [(i32, i32); 8] = [
+    (0, 0),
+    (1, 0),
+    (2, 0),
+    (0, 1),
+    (2, 1),
+    (0, 2),
+    (1, 2),
+    (2, 2),
+];
+const PLUS: [(i32, i32); 5] = [(1, 0), (0, 1), (1, 1), (2, 1), (1, 2)];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Marker {
+    Door {
+        open: bool,
+    },
+    Teleporter,
+    Bulb,
+    TheMachine,
+    Ghost,
+    /// The ghost being controlled.
+    Player,
+}
+
+/// An overview of the whole level. The tiles are drawn once, markers are drawn over them on the
+/// CPU each frame between `begin` and `upload`, then the result is uploaded to a small texture.
+pub struct Minimap {
+    visible: bool,
+    width: u32,
+    height: u32,
+    tiles: Vec<u8>,
+    pixels: Vec<u8>,
+    texture: gl::Texture,
+    quad: SpriteBatch,
+    quad_buffer: SpriteBuffer,
+}
+
+impl Minimap {
+    pub unsafe fn new(context: &mut gl::Context, level: &Level) -> Result<Self, Error> {
+        let width_tiles = level.tiles.iter().map(|row| row.len()).max().unwrap_or(0) as u32;
+        let height_tiles = level.tiles.len() as u32;
+        let (width, height) = (width_tiles * CELL, height_tiles * CELL);
+
+        // rows are stored bottom up like the level, which is also how GL expects them
+        let mut tiles = vec![0; (width * height * 4) as usize];
+        for (y, row) in level.tiles.iter().enumerate() {
+            for (x, tile) in row.iter().enumerate() {
+                let color = match tile {
+                    Tile::Floor => FLOOR_COLOR,
+                    Tile::Wall => WALL_COLOR,
+                };
+                for py in 0..CELL {
+                    for px in 0..CELL {
+                        let i =
+                            (((y as u32 * CELL + py) * width + x as u32 * CELL + px) * 4) as usize;
+                        tiles[i..i + 4].copy_from_slice(&color);
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            visible: false,
+            width,
+            height,
+            pixels: tiles.clone(),
+            tiles,
+            texture: context.create_texture(&gl::TextureDescriptor::new(
+                gl::TextureFormat::RGBAByte,
+                width,
+                height,
+            ))?,
+            quad: SpriteBatch::new(),
+            quad_buffer: SpriteBuffer::new(context, gl::BufferUsage::Dynamic)?,
+        })
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    /// Size of the minimap texture in pixels.
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Clears the markers of the last frame.
+    pub fn begin(&mut self) {
+        self.pixels.copy_from_slice(&self.tiles);
+    }
+
+    /// Marks a tile, or for ghosts a position in tile coordinates.
+    pub fn mark(&mut self, position: Point2D<f32>, marker: Marker) {
+        let x = position.x.floor() as i32 * CELL as i32;
+        let y = position.y.floor() as i32 * CELL as i32;
+        match marker {
+            Marker::Door { open: false } => self.fill(x, y, &FULL, [80, 100, 220, 255]),
+            Marker::Door { open: true } => self.fill(x, y, &[(1, 1)], [80, 100, 220, 255]),
+            Marker::Teleporter => self.fill(x, y, &RING, [80, 200, 240, 255]),
+            Marker::Bulb => self.fill(x, y, &PLUS, [255, 220, 110, 255]),
+            Marker::TheMachine => {
+                // the machine is two tiles wide, centered on its tile's left edge
+                self.fill(x - CELL as i32, y, &FULL, [240, 150, 60, 255]);
+                self.fill(x, y, &FULL, [240, 150, 60, 255]);
+            }
+            Marker::Ghost | Marker::Player => {
+                let color = if marker == Marker::Player {
+                    [255, 255, 255, 255]
+                } else {
+                    [190, 190, 210, 255]
+                };
+                let x = (position.x * CELL as f32).floor() as i32;
+                let y = (position.y * CELL as f32).floor() as i32;
+                self.fill(x - 1, y - 1, &[(0, 0), (1, 0), (0, 1), (1, 1)], color);
+            }
+        }
+    }
+
+    fn fill(&mut self, x: i32, y: i32, pixels: &[(i32, i32)], color: [u8; 4]) {
+        for (px, py) in pixels.iter() {
+            let (px, py) = (x + px, y + py);
+            if px < 0 || py < 0 || px >= self.width as i32 || py >= self.height as i32 {
+                continue;
+            }
+            let i = ((py as u32 * self.width + px as u32) * 4) as usize;
+            self.pixels[i..i + 4].copy_from_slice(&color);
+        }
+    }
+
+    pub unsafe fn upload(&mut self) {
+        self.texture
+            .write(0, 0, self.width, self.height, &self.pixels);
+    }
+
+    /// Draws the minimap between `min` and `max` in the space of the program's current transform.
+    pub unsafe fn draw(
+        &mut self,
+        min: Point2D<f32>,
+        max: Point2D<f32>,
+        program: &mut gl::Program,
+        index_buffer: &gl::IndexBuffer,
+    ) -> Result<(), Error> {
+        self.quad.clear();
+        render_target_quad(min, max, &mut self.quad);
+        self.quad.upload(&mut self.quad_buffer);
+        self.quad.draw(
+            program,
+            &[&self.texture],
+            &self.quad_buffer,
+            index_buffer,
+            &gl::DrawState::default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::point2;
+
+    use super::{Marker, Minimap, CELL};
+    use crate::{
+        gl::{self, Recorder},
+        level::create_level,
+    };
+
+    fn minimap() -> Minimap {
+        let mut context = gl::Context::new(Box::new(Recorder::new()));
+        unsafe { Minimap::new(&mut context, &create_level()) }.unwrap()
+    }
+
+    fn pixel(minimap: &Minimap, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * minimap.width + x) * 4) as usize;
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&minimap.pixels[i..i + 4]);
+        pixel
+    }
+
+    fn marked(minimap: &Minimap) -> Vec<(u32, u32)> {
+        let mut marked = Vec::new();
+        for y in 0..minimap.height {
+            for x in 0..minimap.width {
+                let i = ((y * minimap.width + x) * 4) as usize;
+                if minimap.pixels[i..i + 4] != minimap.tiles[i..i + 4] {
+                    marked.push((x, y));
+                }
+            }
+        }
+        marked
+    }
+
+    #[test]
+    fn markers_are_drawn_over_their_tile_until_the_next_frame() {
+        let mut minimap = minimap();
+        minimap.mark(point2(2.5, 3.5), Marker::Bulb);
+        let (x, y) = (2 * CELL, 3 * CELL);
+        assert_eq!(
+            marked(&minimap),
+            vec![
+                (x + 1, y),
+                (x, y + 1),
+                (x + 1, y + 1),
+                (x + 2, y + 1),
+                (x + 1, y + 2)
+            ]
+        );
+        assert_eq!(pixel(&minimap, x + 1, y + 1), [255, 220, 110, 255]);
+
+        // ghosts are marked where they stand within the tile
+        minimap.begin();
+        minimap.mark(point2(2.5, 3.5), Marker::Player);
+        let (x, y) = (2 * CELL + 1, 3 * CELL + 1);
+        assert_eq!(
+            marked(&minimap),
+            vec![(x - 1, y - 1), (x, y - 1), (x - 1, y), (x, y)]
+        );
+        assert_eq!(pixel(&minimap, x, y), [255, 255, 255, 255]);
+        minimap.begin();
+        assert!(marked(&minimap).is_empty());
+    }
+
+    #[test]
+    fn markers_are_clipped_at_the_edges() {
+        let mut minimap = minimap();
+        let (width, height) = minimap.size();
+        minimap.mark(point2(0., 0.), Marker::Ghost);
+        assert_eq!(marked(&minimap), vec![(0, 0)]);
+
+        minimap.begin();
+        let corner = point2(width as f32, height as f32) / CELL as f32;
+        minimap.mark(corner, Marker::Ghost);
+        assert_eq!(marked(&minimap), vec![(width - 1, height - 1)]);
+
+        // the left half of a machine on the first column is off the map
+        minimap.begin();
+        minimap.mark(point2(0., 1.), Marker::TheMachine);
+        assert_eq!(marked(&minimap).len(), 9);
+
+        minimap.begin();
+        minimap.fill(-10, -10, &[(0, 0), (9, 9)], [255; 4]);
+        minimap.fill(width as i32, 0, &[(0, 0)], [255; 4]);
+        assert!(marked(&minimap).is_empty());
+    }
+}
@@ -0,0 +1,257 @@
+use std::rc::Rc;
+
+use euclid::{
+    default::{Point2D, Vector2D},
+    vec2,
+};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::graphics::{render_sprite_colored, Sprite, SpriteBatch};
+
+#[derive(Clone, Copy, Debug)]
+pub enum EmitMode {
+    /// Spawns all particles at once.
+    Burst(u32),
+    /// Spawns `rate` particles per second for `duration` seconds.
+    Continuous { rate: f32, duration: f32 },
+}
+
+/// Describes an effect. Ranges are `(min, max)` and each particle picks a random value in them.
+/// Positions and speeds are in tiles.
+pub struct Emitter {
+    /// Particles step through the sprite's frames over their lifetime.
+    pub sprite: Sprite,
+    pub mode: EmitMode,
+    pub lifetime: (f32, f32),
+    pub speed: (f32, f32),
+    /// Direction in radians, counter clockwise from the x axis, and how far particles may
+    /// deviate from it either way.
+    pub direction: f32,
+    pub spread: f32,
+    /// Particles are spawned up to this far from the emit position.
+    pub radius: f32,
+    pub acceleration: Vector2D<f32>,
+    /// Evenly spaced over the lifetime, colors in between are blended.
+    pub colors: Vec<[f32; 4]>,
+}
+
+struct Particle {
+    emitter: Rc<Emitter>,
+    position: Point2D<f32>,
+    velocity: Vector2D<f32>,
+    age: f32,
+    lifetime: f32,
+}
+
+struct ActiveEmitter {
+    emitter: Rc<Emitter>,
+    position: Point2D<f32>,
+    time_left: f32,
+    // particles owed but not spawned yet
+    accumulator: f32,
+}
+
+/// Simulates and draws particles. Purely cosmetic: it has its own random number generator and
+/// is not part of the game state.
+pub struct Particles {
+    particles: Vec<Particle>,
+    emitters: Vec<ActiveEmitter>,
+    rng: SmallRng,
+}
+
+impl Default for Particles {
+    fn default() -> Self {
+        Self {
+            particles: Vec::new(),
+            emitters: Vec::new(),
+            rng: SmallRng::seed_from_u64(0),
+        }
+    }
+}
+
+impl Particles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn emit(&mut self, emitter: &Rc<Emitter>, position: Point2D<f32>) {
+        match emitter.mode {
+            EmitMode::Burst(count) => {
+                for _ in 0..count {
+                    self.spawn(emitter, position);
+                }
+            }
+            EmitMode::Continuous { duration, .. } => self.emitters.push(ActiveEmitter {
+                emitter: emitter.clone(),
+                position,
+                time_left: duration,
+                accumulator: 0.,
+            }),
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for i in 0..self.emitters.len() {
+            let (emitter, position, count) = {
+                let active = &mut self.emitters[i];
+                let rate = match active.emitter.mode {
+                    EmitMode::Continuous { rate, .. } => rate,
+                    EmitMode::Burst(_) => 0.,
+                };
+                active.accumulator += rate * dt.min(active.time_left);
+                active.time_left -= dt;
+                let count = active.accumulator.floor();
+                active.accumulator -= count;
+                (active.emitter.clone(), active.position, count as u32)
+            };
+            for _ in 0..count {
+                self.spawn(&emitter, position);
+            }
+        }
+        self.emitters.retain(|active| active.time_left > 0.);
+
+        for particle in self.particles.iter_mut() {
+            particle.velocity += particle.emitter.acceleration * dt;
+            particle.position += particle.velocity * dt;
+            particle.age += dt;
+        }
+        self.particles
+            .retain(|particle| particle.age < particle.lifetime);
+    }
+
+    pub fn draw(&self, out: &mut SpriteBatch) {
+        for particle in self.particles.iter() {
+            let t = (particle.age / particle.lifetime).min(1.);
+            let sprite = &particle.emitter.sprite;
+            let frame =
+                ((t * sprite.frame_count() as f32) as usize).min(sprite.frame_count() as usize - 1);
+            let color = color_at(&particle.emitter.colors, t);
+            render_sprite_colored(sprite, frame, particle.position, color, out);
+        }
+    }
+
+    fn spawn(&mut self, emitter: &Rc<Emitter>, position: Point2D<f32>) {
+        let mut range = |(min, max): (f32, f32)| {
+            if max > min {
+                self.rng.gen_range(min, max)
+            } else {
+                min
+            }
+        };
+        let angle = range((
+            emitter.direction - emitter.spread,
+            emitter.direction + emitter.spread,
+        ));
+        let speed = range(emitter.speed);
+        let lifetime = range(emitter.lifetime);
+        let offset_angle = range((0., std::f32::consts::PI * 2.));
+        let offset = range((0., emitter.radius));
+
+        self.particles.push(Particle {
+            emitter: emitter.clone(),
+            position: position + vec2(offset_angle.cos(), offset_angle.sin()) * offset,
+            velocity: vec2(angle.cos(), angle.sin()) * speed,
+            age: 0.,
+            lifetime,
+        });
+    }
+}
+
+fn color_at(colors: &[[f32; 4]], t: f32) -> [f32; 4] {
+    match colors.len() {
+        0 => [1., 1., 1., 1.],
+        1 => colors[0],
+        len => {
+            let x = t * (len - 1) as f32;
+            let i = (x.floor() as usize).min(len - 2);
+            let f = x - i as f32;
+            let (a, b) = (colors[i], colors[i + 1]);
+            [
+                a[0] + (b[0] - a[0]) * f,
+                a[1] + (b[1] - a[1]) * f,
+                a[2] + (b[2] - a[2]) * f,
+                a[3] + (b[3] - a[3]) * f,
+            ]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use euclid::{point2, vec2};
+
+    use super::{color_at, EmitMode, Emitter, Particles};
+    use crate::{graphics::Sprite, texture_atlas::AtlasRect};
+
+    fn emitter(mode: EmitMode, lifetime: f32) -> Rc<Emitter> {
+        Rc::new(Emitter {
+            sprite: Sprite::new(
+                AtlasRect {
+                    page: 0,
+                    rect: [0, 0, 8, 2],
+                },
+                4,
+                point2(0., 0.),
+            ),
+            mode,
+            lifetime: (lifetime, lifetime),
+            speed: (0., 1.),
+            direction: 0.,
+            spread: 1.,
+            radius: 0.5,
+            acceleration: vec2(0., -1.),
+            colors: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn bursts_spawn_every_particle_at_once() {
+        let mut particles = Particles::new();
+        particles.emit(&emitter(EmitMode::Burst(12), 1.), point2(3., 4.));
+        assert_eq!(particles.particles.len(), 12);
+        assert!(particles.emitters.is_empty());
+    }
+
+    #[test]
+    fn continuous_emitters_spawn_rate_times_duration() {
+        let mut particles = Particles::new();
+        let mode = EmitMode::Continuous {
+            rate: 10.,
+            duration: 1.5,
+        };
+        particles.emit(&emitter(mode, 100.), point2(0., 0.));
+        // 2.5 particles are owed each update, the half carries over to the next
+        let mut spawned = Vec::new();
+        for _ in 0..8 {
+            particles.update(0.25);
+            spawned.push(particles.particles.len());
+        }
+        assert_eq!(spawned, vec![2, 5, 7, 10, 12, 15, 15, 15]);
+        assert!(particles.emitters.is_empty());
+    }
+
+    #[test]
+    fn particles_expire_at_their_lifetime() {
+        let mut particles = Particles::new();
+        particles.emit(&emitter(EmitMode::Burst(3), 1.), point2(0., 0.));
+        for _ in 0..3 {
+            particles.update(0.25);
+        }
+        assert_eq!(particles.particles.len(), 3);
+        particles.update(0.25);
+        assert!(particles.particles.is_empty());
+    }
+
+    #[test]
+    fn colors_blend_over_the_lifetime() {
+        let colors = [[0., 0., 0., 0.], [1., 0.5, 0., 1.], [1., 1., 1., 1.]];
+        assert_eq!(color_at(&colors, 0.), colors[0]);
+        assert_eq!(color_at(&colors, 0.25), [0.5, 0.25, 0., 0.5]);
+        assert_eq!(color_at(&colors, 0.5), colors[1]);
+        assert_eq!(color_at(&colors, 1.), colors[2]);
+        assert_eq!(color_at(&colors[..1], 0.5), colors[0]);
+        assert_eq!(color_at(&[], 0.5), [1., 1., 1., 1.]);
+    }
+}
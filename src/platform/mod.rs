@@ -1,9 +1,9 @@
 #[cfg(target_arch = "wasm32")]
 mod web;
 #[cfg(target_arch = "wasm32")]
-pub use web::{run, start_audio_playback};
+pub use web::{bench_ghosts, determinism_mode, run, start_audio_playback};
 
 #[cfg(not(target_arch = "wasm32"))]
 mod native;
 #[cfg(not(target_arch = "wasm32"))]
-pub use native::{run, start_audio_playback};
+pub use native::{bench_ghosts, determinism_mode, run, start_audio_playback};
@@ -0,0 +1,261 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::BufWriter,
+    sync::mpsc::{channel, Sender},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Error;
+use image::{gif::Encoder, imageops, Delay, Frame, RgbaImage};
+
+use crate::{gl, viewport::Viewport};
+
+/// Recordings keep this many seconds, older frames are dropped.
+const GIF_SECONDS: f32 = 5.;
+const GIF_FPS: u32 = 20;
+
+/// Saves screenshots and GIF recordings of the game screen to the working directory. While
+/// recording, the last few seconds are kept so they can be saved after something happened.
+/// Only reading the screen happens on the main thread, frames are scaled, kept and encoded on
+/// others.
+pub struct Capture {
+    screen_size: (u32, u32),
+    recording: Option<Recording>,
+}
+
+struct Recording {
+    sender: Sender<Message>,
+    since_last_frame: f32,
+}
+
+enum Message {
+    Frame(Screen),
+    Save,
+}
+
+// the viewport as read back, rows from the bottom up
+struct Screen {
+    pixels: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+impl Capture {
+    pub fn new(screen_size: (u32, u32)) -> Self {
+        Self {
+            screen_size,
+            recording: None,
+        }
+    }
+
+    /// Saves what was last drawn into `viewport` as a PNG at the game's resolution.
+    pub unsafe fn screenshot(&self, context: &gl::Context, viewport: &Viewport) {
+        let screen = match read_screen(context, viewport) {
+            Some(screen) => screen,
+            None => {
+                log::warn!("Nothing to take a screenshot of while minimized");
+                return;
+            }
+        };
+        let size = self.screen_size;
+        let path = file_name("screenshot", "png");
+        thread::spawn(move || match screen.into_image(size).save(&path) {
+            Ok(()) => log::info!("Saved screenshot {}", path),
+            Err(e) => log::error!("Could not save screenshot {}: {}", path, e),
+        });
+    }
+
+    /// Starts keeping the last few seconds, or stops and drops them.
+    pub fn toggle_recording(&mut self) {
+        if self.recording.take().is_some() {
+            log::info!("Recording stopped");
+            return;
+        }
+        // recorded at half resolution, a few seconds at full size add up to a lot of memory
+        let size = (self.screen_size.0 / 2, self.screen_size.1 / 2);
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            let mut frames = Frames::default();
+            for message in receiver {
+                match message {
+                    Message::Frame(screen) => frames.push(screen.into_image(size)),
+                    Message::Save => frames.save(),
+                }
+            }
+        });
+        self.recording = Some(Recording {
+            sender,
+            since_last_frame: 0.,
+        });
+        log::info!("Recording the last {} seconds", GIF_SECONDS);
+    }
+
+    /// Saves the last few seconds as a GIF, if recording.
+    pub fn save_recording(&self) {
+        match &self.recording {
+            Some(recording) => recording.sender.send(Message::Save).unwrap(),
+            None => log::warn!("Not recording, F9 starts keeping the last few seconds"),
+        }
+    }
+
+    /// Called after every drawn frame, grabs a frame at the GIF's frame rate while recording.
+    pub unsafe fn frame_drawn(&mut self, dt: f32, context: &gl::Context, viewport: &Viewport) {
+        let recording = match &mut self.recording {
+            Some(recording) => recording,
+            None => return,
+        };
+        recording.since_last_frame += dt;
+        let frame_time = 1. / GIF_FPS as f32;
+        if recording.since_last_frame < frame_time {
+            return;
+        }
+        recording.since_last_frame = (recording.since_last_frame - frame_time).min(frame_time);
+
+        if let Some(screen) = read_screen(context, viewport) {
+            recording.sender.send(Message::Frame(screen)).unwrap();
+        }
+    }
+}
+
+// the last few seconds of frames
+#[derive(Default)]
+struct Frames(VecDeque<RgbaImage>);
+
+impl Frames {
+    fn push(&mut self, frame: RgbaImage) {
+        self.0.push_back(frame);
+        while self.0.len() as f32 > GIF_SECONDS * GIF_FPS as f32 {
+            self.0.pop_front();
+        }
+    }
+
+    fn save(&self) {
+        if self.0.is_empty() {
+            log::warn!("Nothing recorded yet");
+            return;
+        }
+        let frames = self.0.clone();
+        let path = file_name("recording", "gif");
+        log::info!("Encoding the last {} frames to {}", frames.len(), path);
+        thread::spawn(move || match save_gif(&path, frames) {
+            Ok(()) => log::info!("Saved recording {}", path),
+            Err(e) => log::error!("Could not save recording {}: {}", path, e),
+        });
+    }
+}
+
+// There is nothing to read while the window is minimized.
+unsafe fn read_screen(context: &gl::Context, viewport: &Viewport) -> Option<Screen> {
+    if viewport.width == 0 || viewport.height == 0 {
+        return None;
+    }
+    Some(Screen {
+        pixels: context.read_pixels(viewport.x, viewport.y, viewport.width, viewport.height),
+        width: viewport.width,
+        height: viewport.height,
+    })
+}
+
+impl Screen {
+    // the viewport is scaled up to fit the window, scale it back to `size` so captures don't
+    // depend on the window
+    fn into_image(self, size: (u32, u32)) -> RgbaImage {
+        let image = RgbaImage::from_raw(self.width, self.height, self.pixels)
+            .expect("read_pixels returns a full image");
+        let mut image = imageops::flip_vertical(&image);
+        // the window has no use for alpha and may leave it anywhere
+        for pixel in image.pixels_mut() {
+            pixel[3] = 255;
+        }
+        if image.dimensions() == size {
+            image
+        } else {
+            imageops::resize(&image, size.0, size.1, imageops::FilterType::Nearest)
+        }
+    }
+}
+
+fn save_gif(path: &str, frames: VecDeque<RgbaImage>) -> Result<(), Error> {
+    let mut encoder = Encoder::new(BufWriter::new(File::create(path)?));
+    encoder.encode_frames(
+        frames
+            .into_iter()
+            .map(|image| Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(1000, GIF_FPS))),
+    )?;
+    Ok(())
+}
+
+fn file_name(prefix: &str, extension: &str) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or(0);
+    format!("{}-{}.{}", prefix, time, extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::size2;
+    use image::RgbaImage;
+
+    use super::{Capture, Frames, Screen, GIF_FPS, GIF_SECONDS};
+    use crate::{
+        gl::{self, Call, CallLog, Recorder},
+        viewport::Viewport,
+    };
+
+    fn reads(window: (u32, u32), recording: bool, frames: usize) -> usize {
+        let recorder = Recorder::new();
+        let calls: CallLog = recorder.calls();
+        let context = gl::Context::new(Box::new(recorder));
+        let viewport = Viewport::letterbox(size2(window.0, window.1), size2(40, 30));
+        let mut capture = Capture::new((40, 30));
+        if recording {
+            capture.toggle_recording();
+        }
+        // twice the GIF frame rate
+        for _ in 0..frames {
+            unsafe { capture.frame_drawn(0.5 / GIF_FPS as f32, &context, &viewport) };
+        }
+        let reads = calls
+            .borrow()
+            .iter()
+            .filter(|call| matches!(call, Call::ReadPixels(_)))
+            .count();
+        reads
+    }
+
+    #[test]
+    fn screens_are_read_at_the_gif_frame_rate_while_recording() {
+        assert_eq!(reads((80, 60), true, 40), 20);
+        assert_eq!(reads((80, 60), false, 40), 0);
+        // minimized
+        assert_eq!(reads((0, 0), true, 40), 0);
+    }
+
+    #[test]
+    fn frames_keep_the_last_few_seconds() {
+        let mut frames = Frames::default();
+        for i in 0..(GIF_SECONDS * GIF_FPS as f32) as u32 * 2 {
+            frames.push(RgbaImage::new(i + 1, 1));
+        }
+        assert_eq!(frames.0.len(), (GIF_SECONDS * GIF_FPS as f32) as usize);
+        assert_eq!(frames.0.back().unwrap().width(), frames.0.len() as u32 * 2);
+    }
+
+    #[test]
+    fn screens_are_flipped_opaque_and_scaled() {
+        // two rows from the bottom up: a translucent red row under a blue one
+        let screen = Screen {
+            pixels: vec![255, 0, 0, 10, 255, 0, 0, 10, 0, 0, 255, 255, 0, 0, 255, 255],
+            width: 2,
+            height: 2,
+        };
+        let image = screen.into_image((1, 2));
+        assert_eq!(image.dimensions(), (1, 2));
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(0, 1).0, [255, 0, 0, 255]);
+    }
+}
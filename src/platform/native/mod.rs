@@ -1,16 +1,20 @@
 mod audio;
+mod capture;
 
 use crate::{
+    determinism::{parse_trace, DeterminismMode},
     gl,
     input::{InputEvent, Key, MouseButton},
+    viewport::Viewport,
 };
 
-use euclid::{point2, vec2};
+use euclid::{point2, size2, vec2};
 use glutin::event::{
     ElementState, KeyboardInput, MouseButton as GlutinMouseButton, MouseScrollDelta, VirtualKeyCode,
 };
 
 pub use audio::start_audio_playback;
+use capture::Capture;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub fn run<
@@ -25,6 +29,7 @@ pub fn run<
         event,
         event::WindowEvent,
         event_loop::{ControlFlow, EventLoop},
+        window::Fullscreen,
     };
     use std::time::Instant;
 
@@ -34,11 +39,12 @@ pub fn run<
     wb = wb
         .with_title(title)
         .with_inner_size(glutin::dpi::LogicalSize::new(size.0, size.1))
-        .with_resizable(false);
+        .with_resizable(true);
     let windowed_context = unsafe {
         glutin::ContextBuilder::new()
             .with_gl(glutin::GlRequest::Specific(glutin::Api::OpenGlEs, (2, 0)))
-            .with_vsync(true)
+            // benchmarks draw as fast as they can
+            .with_vsync(bench_ghosts().is_none())
             .build_windowed(wb, &event_loop)
             .unwrap()
             .make_current()
@@ -50,7 +56,19 @@ pub fn run<
             windowed_context.get_proc_address(addr)
         }));
 
-    let mut update_fn = f(&mut gl_context);
+    // an option so the game and its GL resources can be dropped before the window on exit
+    let mut update_fn = Some(f(&mut gl_context));
+
+    // the game always renders at `size`, scaled up into whatever the window is now
+    let screen_size = size2(size.0, size.1);
+    let window_size = windowed_context.window().inner_size();
+    let mut window_size = size2(window_size.width, window_size.height);
+    let mut viewport = Viewport::letterbox(window_size, screen_size);
+
+    // F12 saves a screenshot, F9 starts and stops recording, F10 saves the last few seconds as
+    // a GIF
+    let mut capture = Capture::new(size);
+    let mut take_screenshot = false;
 
     let mut input_events = Vec::new();
     let mut last_time = Instant::now();
@@ -58,11 +76,32 @@ pub fn run<
         *control_flow = ControlFlow::Poll;
         match event {
             event::Event::MainEventsCleared => windowed_context.window().request_redraw(),
+            event::Event::LoopDestroyed => {
+                // delete everything while the window's GL context still exists
+                update_fn = None;
+                unsafe { gl_context.maintain() };
+            }
             event::Event::WindowEvent {
                 event: WindowEvent::Resized(size),
                 ..
             } => {
                 log::info!("Resize to {:?}", size);
+                windowed_context.resize(size);
+                window_size = size2(size.width, size.height);
+                viewport = Viewport::letterbox(window_size, screen_size);
+            }
+            event::Event::WindowEvent {
+                event:
+                    WindowEvent::ScaleFactorChanged {
+                        scale_factor,
+                        new_inner_size,
+                    },
+                ..
+            } => {
+                log::info!("Scale factor changed to {}", scale_factor);
+                windowed_context.resize(*new_inner_size);
+                window_size = size2(new_inner_size.width, new_inner_size.height);
+                viewport = Viewport::letterbox(window_size, screen_size);
             }
             event::Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
@@ -80,6 +119,25 @@ pub fn run<
                         },
                     ..
                 } => {
+                    if key == VirtualKeyCode::F11 && state == ElementState::Pressed {
+                        let window = windowed_context.window();
+                        if window.fullscreen().is_some() {
+                            window.set_fullscreen(None);
+                        } else {
+                            window.set_fullscreen(Some(Fullscreen::Borderless(
+                                window.current_monitor(),
+                            )));
+                        }
+                    }
+                    if key == VirtualKeyCode::F12 && state == ElementState::Pressed {
+                        take_screenshot = true;
+                    }
+                    if key == VirtualKeyCode::F9 && state == ElementState::Pressed {
+                        capture.toggle_recording();
+                    }
+                    if key == VirtualKeyCode::F10 && state == ElementState::Pressed {
+                        capture.save_recording();
+                    }
                     if let Some(key) = get_key(key) {
                         match state {
                             ElementState::Pressed => {
@@ -111,8 +169,9 @@ pub fn run<
                     }
                 },
                 WindowEvent::CursorMoved { position, .. } => {
-                    let position = position.to_logical(1.0);
-                    input_events.push(InputEvent::MouseMove(point2(position.x, position.y)));
+                    let position =
+                        viewport.window_to_screen(point2(position.x as f32, position.y as f32));
+                    input_events.push(InputEvent::MouseMove(position));
                 }
                 _ => {}
             },
@@ -120,8 +179,27 @@ pub fn run<
                 let now = Instant::now();
                 let dt = (now - last_time).as_micros() as f32 / 1_000_000.;
                 last_time = now;
-                update_fn(dt, &input_events, &mut gl_context);
+                unsafe {
+                    gl_context.set_viewport(0, 0, window_size.width, window_size.height);
+                    gl_context.clear([0., 0., 0., 1.]);
+                    gl_context.set_viewport(
+                        viewport.x,
+                        viewport.y,
+                        viewport.width,
+                        viewport.height,
+                    );
+                }
+                if let Some(update_fn) = &mut update_fn {
+                    update_fn(dt, &input_events, &mut gl_context);
+                }
                 input_events.clear();
+                unsafe {
+                    if take_screenshot {
+                        capture.screenshot(&gl_context, &viewport);
+                        take_screenshot = false;
+                    }
+                    capture.frame_drawn(dt, &gl_context, &viewport);
+                }
                 windowed_context.swap_buffers().unwrap();
                 unsafe { gl_context.maintain() };
             }
@@ -130,6 +208,35 @@ pub fn run<
     });
 }
 
+/// `LD47_RECORD_TRACE=<file>` records a state trace of the session, `LD47_CHECK_TRACE=<file>`
+/// re-simulates a trace recorded by any build and reports where it diverges.
+pub fn determinism_mode() -> DeterminismMode {
+    if let Ok(path) = std::env::var("LD47_CHECK_TRACE") {
+        let trace = std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|text| parse_trace(&text))
+            .unwrap_or_else(|e| panic!("Could not load state trace {}: {}", path, e));
+        DeterminismMode::Check(trace)
+    } else if let Ok(path) = std::env::var("LD47_RECORD_TRACE") {
+        let file = std::fs::File::create(&path)
+            .unwrap_or_else(|e| panic!("Could not create state trace {}: {}", path, e));
+        DeterminismMode::Record(Box::new(std::io::BufWriter::new(file)))
+    } else {
+        DeterminismMode::Off
+    }
+}
+
+/// `LD47_BENCH_GHOSTS=<count>` adds that many ghosts, turns off vsync and logs frame times,
+/// alternating between each way of writing the sprite buffers.
+pub fn bench_ghosts() -> Option<usize> {
+    let count = std::env::var("LD47_BENCH_GHOSTS").ok()?;
+    Some(
+        count
+            .parse()
+            .unwrap_or_else(|e| panic!("Bad ghost count {}: {}", count, e)),
+    )
+}
+
 fn get_key(vk: VirtualKeyCode) -> Option<Key> {
     match vk {
         VirtualKeyCode::A => Some(Key::A),
@@ -170,6 +277,18 @@ fn get_key(vk: VirtualKeyCode) -> Option<Key> {
         VirtualKeyCode::Up => Some(Key::Up),
         VirtualKeyCode::Right => Some(Key::Right),
         VirtualKeyCode::Down => Some(Key::Down),
+        VirtualKeyCode::F1 => Some(Key::F1),
+        VirtualKeyCode::F2 => Some(Key::F2),
+        VirtualKeyCode::F3 => Some(Key::F3),
+        VirtualKeyCode::F4 => Some(Key::F4),
+        VirtualKeyCode::F5 => Some(Key::F5),
+        VirtualKeyCode::F6 => Some(Key::F6),
+        VirtualKeyCode::F7 => Some(Key::F7),
+        VirtualKeyCode::F8 => Some(Key::F8),
+        VirtualKeyCode::F9 => Some(Key::F9),
+        VirtualKeyCode::F10 => Some(Key::F10),
+        VirtualKeyCode::F11 => Some(Key::F11),
+        VirtualKeyCode::F12 => Some(Key::F12),
         _ => None,
     }
 }
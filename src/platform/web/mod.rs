@@ -1,14 +1,16 @@
 mod audio;
 
-use std::rc::Rc;
+use std::{cell::Cell, io::Write, rc::Rc};
 
-use euclid::{point2, vec2};
+use euclid::{default::Size2D, point2, size2, vec2};
 use wasm_bindgen::{closure::Closure, JsCast};
-use web_sys::{HtmlElement, KeyboardEvent, MouseEvent, WheelEvent};
+use web_sys::{Event, HtmlCanvasElement, HtmlElement, KeyboardEvent, MouseEvent, WheelEvent};
 
 use crate::{
+    determinism::DeterminismMode,
     gl,
     input::{InputEvent, Key, MouseButton},
+    viewport::Viewport,
 };
 
 pub use audio::start_audio_playback;
@@ -36,17 +38,16 @@ pub fn run<
         .expect("Cannot create canvas")
         .dyn_into::<web_sys::HtmlCanvasElement>()
         .expect("Cannot get canvas element");
-    document
-        .body()
-        .expect("Cannot get document body")
-        .append_child(&canvas)
+    let body = document.body().expect("Cannot get document body");
+    body.append_child(&canvas)
         .expect("Cannot insert canvas into document body");
+    // the canvas fills the page, the game screen is letterboxed into it
+    body.set_attribute("style", "margin: 0; overflow: hidden; background: black")
+        .expect("cannot set style");
     canvas
-        .set_attribute("width", &format!("{}", size.0))
-        .expect("cannot set width");
-    canvas
-        .set_attribute("height", &format!("{}", size.1))
-        .expect("cannot set height");
+        .set_attribute("style", "display: block; width: 100vw; height: 100vh")
+        .expect("cannot set style");
+    let canvas_size = Rc::new(Cell::new(fit_canvas(&canvas, size)));
 
     let webgl1_context = canvas
         .get_context("webgl")
@@ -55,8 +56,13 @@ pub fn run<
         .dyn_into::<web_sys::WebGlRenderingContext>()
         .expect("3");
 
+    // WebGL 1 only instances through this extension, glow enables it when it's there
+    let instancing = matches!(
+        webgl1_context.get_extension("ANGLE_instanced_arrays"),
+        Ok(Some(_))
+    );
     let glow_context = glow::Context::from_webgl1_context(webgl1_context);
-    let mut gl_context = gl::Context::from_glow_context(glow_context);
+    let mut gl_context = gl::Context::new(Box::new(gl::GlowBackend::new(glow_context, instancing)));
 
     let mut update_fn = f(&mut gl_context);
 
@@ -68,8 +74,14 @@ pub fn run<
 
     let input_stream = HtmlEventStream::new(canvas.clone().dyn_into().unwrap(), {
         let input_events = Rc::clone(&input_events);
+        let canvas_size = Rc::clone(&canvas_size);
+        let canvas = canvas.clone();
         move |window_event| match window_event {
             HtmlEvent::KeyDown(key_event) => {
+                if key_event.code() == "F11" {
+                    key_event.prevent_default();
+                    toggle_fullscreen(&canvas);
+                }
                 if let Some(key) = get_key_from_code(&key_event.code()) {
                     input_events.borrow_mut().push(InputEvent::KeyDown(key));
                 }
@@ -92,10 +104,19 @@ pub fn run<
                     .push(InputEvent::MouseUp(get_mouse_button(mouse_event.button())));
             }
             HtmlEvent::MouseMove(mouse_event) => {
-                input_events.borrow_mut().push(InputEvent::MouseMove(point2(
-                    mouse_event.offset_x() as f32,
-                    mouse_event.offset_y() as f32,
-                )));
+                // offsets are in css pixels
+                let CanvasSize {
+                    viewport,
+                    scale_factor,
+                    ..
+                } = canvas_size.get();
+                let position = viewport.window_to_screen(point2(
+                    (mouse_event.offset_x() as f64 * scale_factor) as f32,
+                    (mouse_event.offset_y() as f64 * scale_factor) as f32,
+                ));
+                input_events
+                    .borrow_mut()
+                    .push(InputEvent::MouseMove(position));
             }
             HtmlEvent::MouseWheel(wheel_event) => {
                 input_events.borrow_mut().push(InputEvent::MouseWheel(vec2(
@@ -103,6 +124,7 @@ pub fn run<
                     -wheel_event.delta_y() as f32,
                 )));
             }
+            HtmlEvent::Resize => canvas_size.set(fit_canvas(&canvas, size)),
         }
     });
 
@@ -112,8 +134,19 @@ pub fn run<
             let _ = &input_stream;
 
             let dt = (time - last_time.unwrap_or(time)) / 1000.;
+            let CanvasSize {
+                size: canvas_size,
+                viewport,
+                ..
+            } = canvas_size.get();
+            unsafe {
+                gl_context.set_viewport(0, 0, canvas_size.width, canvas_size.height);
+                gl_context.clear([0., 0., 0., 1.]);
+                gl_context.set_viewport(viewport.x, viewport.y, viewport.width, viewport.height);
+            }
             update_fn(dt as f32, &input_events.borrow(), &mut gl_context);
             input_events.borrow_mut().clear();
+            unsafe { gl_context.maintain() };
             last_time = Some(time);
 
             web_sys::window()
@@ -129,6 +162,52 @@ pub fn run<
     })
 }
 
+// The canvas backing store in physical pixels, so high DPI screens get a sharp image, and where
+// the game screen goes in it.
+#[derive(Clone, Copy)]
+struct CanvasSize {
+    size: Size2D<u32>,
+    scale_factor: f64,
+    viewport: Viewport,
+}
+
+/// Sizes the canvas backing store to the page, for a game screen of `screen` pixels.
+fn fit_canvas(canvas: &HtmlCanvasElement, screen: (u32, u32)) -> CanvasSize {
+    let window = web_sys::window().expect("no global window");
+    let scale_factor = window.device_pixel_ratio();
+    let css_pixels = |size: Result<wasm_bindgen::JsValue, wasm_bindgen::JsValue>| {
+        size.ok().and_then(|size| size.as_f64()).unwrap_or(0.)
+    };
+    let size = size2(
+        (css_pixels(window.inner_width()) * scale_factor).round() as u32,
+        (css_pixels(window.inner_height()) * scale_factor).round() as u32,
+    );
+    canvas
+        .set_attribute("width", &format!("{}", size.width))
+        .expect("cannot set width");
+    canvas
+        .set_attribute("height", &format!("{}", size.height))
+        .expect("cannot set height");
+    CanvasSize {
+        size,
+        scale_factor,
+        viewport: Viewport::letterbox(size, size2(screen.0, screen.1)),
+    }
+}
+
+/// F11 puts the canvas fullscreen, or back into the page.
+fn toggle_fullscreen(canvas: &HtmlCanvasElement) {
+    let document = match canvas.owner_document() {
+        Some(document) => document,
+        None => return,
+    };
+    if document.fullscreen_element().is_some() {
+        document.exit_fullscreen();
+    } else if let Err(e) = canvas.request_fullscreen() {
+        log::warn!("Could not go fullscreen: {:?}", e);
+    }
+}
+
 pub enum HtmlEvent {
     KeyDown(KeyboardEvent),
     KeyUp(KeyboardEvent),
@@ -136,6 +215,8 @@ pub enum HtmlEvent {
     MouseUp(MouseEvent),
     MouseMove(MouseEvent),
     MouseWheel(WheelEvent),
+    /// The page was resized, or went in or out of fullscreen.
+    Resize,
 }
 
 /// Multiplexes different window-level input events into a single callback, automatically removing
@@ -148,6 +229,7 @@ pub struct HtmlEventStream {
     _on_mouse_up: Closure<dyn FnMut(MouseEvent)>,
     _on_mouse_move: Closure<dyn FnMut(MouseEvent)>,
     _on_mouse_wheel: Closure<dyn FnMut(WheelEvent)>,
+    _on_resize: Closure<dyn FnMut(Event)>,
 }
 
 impl HtmlEventStream {
@@ -204,7 +286,15 @@ impl HtmlEventStream {
             }
         }) as Box<dyn FnMut(MouseEvent)>);
 
+        let on_resize = Closure::wrap(Box::new({
+            let callback = Rc::clone(&callback);
+            move |_: Event| {
+                callback(HtmlEvent::Resize);
+            }
+        }) as Box<dyn FnMut(Event)>);
+
         let window = web_sys::window().unwrap();
+        window.set_onresize(Some(on_resize.as_ref().unchecked_ref()));
         window.set_onkeydown(Some(on_key_down.as_ref().unchecked_ref()));
         window.set_onkeyup(Some(on_key_up.as_ref().unchecked_ref()));
         mouse_element.set_onmousedown(Some(on_mouse_down.as_ref().unchecked_ref()));
@@ -220,6 +310,7 @@ impl HtmlEventStream {
             _on_mouse_up: on_mouse_up,
             _on_mouse_move: on_mouse_move,
             _on_mouse_wheel: on_mouse_wheel,
+            _on_resize: on_resize,
         }
     }
 }
@@ -227,6 +318,7 @@ impl HtmlEventStream {
 impl Drop for HtmlEventStream {
     fn drop(&mut self) {
         let window = web_sys::window().unwrap();
+        window.set_onresize(None);
         window.set_onkeydown(None);
         window.set_onkeyup(None);
         self.mouse_element.set_onmousedown(None);
@@ -235,6 +327,42 @@ impl Drop for HtmlEventStream {
     }
 }
 
+/// Loading the page with `?record_trace` logs a state trace line for every update to the console,
+/// to be saved and checked against a native build with `LD47_CHECK_TRACE`.
+pub fn determinism_mode() -> DeterminismMode {
+    let search = web_sys::window()
+        .and_then(|win| win.location().search().ok())
+        .unwrap_or_default();
+    if search.contains("record_trace") {
+        DeterminismMode::Record(Box::new(ConsoleLineWriter(Vec::new())))
+    } else {
+        DeterminismMode::Off
+    }
+}
+
+/// Frame times are only measured by native builds.
+pub fn bench_ghosts() -> Option<usize> {
+    None
+}
+
+/// Buffers written bytes and logs them one line at a time.
+struct ConsoleLineWriter(Vec<u8>);
+
+impl Write for ConsoleLineWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        while let Some(end) = self.0.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.0.drain(..=end).collect();
+            log::info!("{}", String::from_utf8_lossy(&line[..end]));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 fn get_key_from_code(key: &str) -> Option<Key> {
     match key {
         "KeyA" => Some(Key::A),
@@ -275,6 +403,18 @@ fn get_key_from_code(key: &str) -> Option<Key> {
         "ArrowUp" => Some(Key::Up),
         "ArrowRight" => Some(Key::Right),
         "ArrowDown" => Some(Key::Down),
+        "F1" => Some(Key::F1),
+        "F2" => Some(Key::F2),
+        "F3" => Some(Key::F3),
+        "F4" => Some(Key::F4),
+        "F5" => Some(Key::F5),
+        "F6" => Some(Key::F6),
+        "F7" => Some(Key::F7),
+        "F8" => Some(Key::F8),
+        "F9" => Some(Key::F9),
+        "F10" => Some(Key::F10),
+        "F11" => Some(Key::F11),
+        "F12" => Some(Key::F12),
         _ => None,
     }
 }
@@ -0,0 +1,116 @@
+use anyhow::Error;
+use euclid::point2;
+
+use crate::{
+    gl,
+    graphics::{
+        create_sprite_program, render_target_quad, sprite_vertex_shader, SpriteBatch, SpriteBuffer,
+    },
+};
+
+/// The tape rewind look: scanlines, color fringes, desaturation and a rolling tracking band. The
+/// frame is drawn into `frame_target` and then copied to the screen through the effect.
+pub struct RewindEffect {
+    program: gl::Program,
+    frame_target: gl::RenderTarget,
+    quad: SpriteBatch,
+    quad_buffer: SpriteBuffer,
+}
+
+impl RewindEffect {
+    pub unsafe fn new(context: &mut gl::Context, width: u32, height: u32) -> Result<Self, Error> {
+        let mut effect = Self {
+            program: create_program(
+                context,
+                sprite_vertex_shader(context).1,
+                include_str!("shaders/rewind.frag"),
+            )?,
+            frame_target: context.create_render_target(width, height)?,
+            quad: SpriteBatch::new(),
+            quad_buffer: SpriteBuffer::new(context, gl::BufferUsage::Static)?,
+        };
+        effect.set_constant_uniforms()?;
+        render_target_quad(point2(-1., -1.), point2(1., 1.), &mut effect.quad);
+        effect.quad.upload(&mut effect.quad_buffer);
+        Ok(effect)
+    }
+
+    /// Replaces the effect's program, keeping the old one if the new sources don't compile.
+    pub unsafe fn reload_shaders(
+        &mut self,
+        context: &mut gl::Context,
+        vertex_source: &str,
+        fragment_source: &str,
+    ) -> Result<(), Error> {
+        self.program = create_program(context, vertex_source, fragment_source)?;
+        self.set_constant_uniforms()
+    }
+
+    fn set_constant_uniforms(&mut self) -> Result<(), Error> {
+        let (width, height) = (self.frame_target.width(), self.frame_target.height());
+        self.program.set_uniform(
+            "u_transform",
+            gl::Uniform::Mat3([[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]]),
+        )?;
+        self.program.set_uniform(
+            "u_resolution",
+            gl::Uniform::Float2(width as f32, height as f32),
+        )?;
+        Ok(())
+    }
+
+    /// Where to draw the frame when the effect is on.
+    pub fn frame_target(&self) -> &gl::RenderTarget {
+        &self.frame_target
+    }
+
+    /// Draws the frame target to the screen with the effect at `intensity` from 0 to 1. `time`
+    /// in seconds moves the tracking band.
+    pub unsafe fn draw(
+        &mut self,
+        context: &mut gl::Context,
+        intensity: f32,
+        time: f32,
+        index_buffer: &gl::IndexBuffer,
+    ) -> Result<(), Error> {
+        self.program
+            .set_uniform("u_intensity", gl::Uniform::Float(intensity))?;
+        self.program
+            .set_uniform("u_time", gl::Uniform::Float(time))?;
+
+        context.bind_render_target(None);
+        self.quad.draw(
+            &mut self.program,
+            &[self.frame_target.texture()],
+            &self.quad_buffer,
+            index_buffer,
+            &gl::DrawState::default(),
+        )
+    }
+}
+
+unsafe fn create_program(
+    context: &mut gl::Context,
+    vertex_source: &str,
+    fragment_source: &str,
+) -> Result<gl::Program, Error> {
+    create_sprite_program(
+        context,
+        vertex_source,
+        fragment_source,
+        &[
+            gl::UniformEntry {
+                name: "u_intensity",
+                ty: gl::UniformType::Float,
+            },
+            gl::UniformEntry {
+                name: "u_time",
+                ty: gl::UniformType::Float,
+            },
+            gl::UniformEntry {
+                name: "u_resolution",
+                ty: gl::UniformType::Float2,
+            },
+        ],
+    )
+}
@@ -0,0 +1,648 @@
+use std::{cell::RefCell, collections::HashMap, path::Path};
+
+use euclid::{default::Transform2D, point2};
+use image::RgbaImage;
+
+use crate::{
+    gl::{
+        Backend, BlendMode, BufferUsage, Draw, Handle, Rect, ShaderType, TextureDescriptor,
+        TextureFormat, Topology, UniformEntry, UniformValue, VertexFormat,
+    },
+    graphics::{SpriteBatch, Vertex, QUAD_INDICES},
+};
+
+/// Software stand-in for `gl::Program::render_vertices` running `shader.vert`/`shader.frag`, so
+/// frames can be rendered without a GL context. Textures are sampled nearest with clamped
+/// coordinates and colors are blended like the GL path, into 8 bits per channel. Rows are kept
+/// from the bottom up like GL, so a rasterizer drawn into can be sampled as a texture.
+pub struct Rasterizer {
+    width: u32,
+    height: u32,
+    pixels: Vec<[f32; 4]>,
+}
+
+impl Rasterizer {
+    pub fn new(width: u32, height: u32) -> Rasterizer {
+        Rasterizer {
+            width,
+            height,
+            pixels: vec![[0., 0., 0., 0.]; (width * height) as usize],
+        }
+    }
+
+    /// A texture with the pixels of `image`, its first row at the bottom as when uploaded to GL.
+    pub fn from_image(image: &RgbaImage) -> Rasterizer {
+        let mut texture = Rasterizer::new(image.width(), image.height());
+        texture.write((0, 0, image.width(), image.height()), image);
+        texture
+    }
+
+    pub fn clear(&mut self, color: [f32; 4]) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = color;
+        }
+    }
+
+    /// Draws `vertices` as a triangle list. `transform` is the `u_transform` uniform, mapping
+    /// vertex positions to clip space.
+    pub fn render_vertices(
+        &mut self,
+        vertices: &[Vertex],
+        transform: &Transform2D<f32>,
+        texture: &RgbaImage,
+    ) {
+        let texture = Rasterizer::from_image(texture);
+        for triangle in vertices.chunks_exact(3) {
+            self.render_triangle(
+                triangle,
+                transform,
+                BlendMode::Premultiplied,
+                &|uv, color| sprite_fragment(&texture, uv, color),
+            );
+        }
+    }
+
+    /// Draws the quads of `batch` the way `SpriteBatch::draw` does, `textures` indexed by the
+    /// batch texture index.
+    pub fn render_batch(
+        &mut self,
+        batch: &SpriteBatch,
+        transform: &Transform2D<f32>,
+        textures: &[&RgbaImage],
+    ) {
+        let textures: Vec<Rasterizer> = textures
+            .iter()
+            .map(|texture| Rasterizer::from_image(texture))
+            .collect();
+        for (texture, instances) in batch.batches() {
+            let fragment = |uv, color| sprite_fragment(&textures[texture], uv, color);
+            for quad in instances.iter().map(|instance| instance.vertices()) {
+                for triangle in QUAD_INDICES.chunks_exact(3) {
+                    let triangle = [
+                        quad[triangle[0] as usize],
+                        quad[triangle[1] as usize],
+                        quad[triangle[2] as usize],
+                    ];
+                    self.render_triangle(&triangle, transform, BlendMode::Premultiplied, &fragment);
+                }
+            }
+        }
+    }
+
+    // `fragment` gets the interpolated uv and color of each pixel covered, like a fragment shader
+    fn render_triangle(
+        &mut self,
+        triangle: &[Vertex],
+        transform: &Transform2D<f32>,
+        blend_mode: BlendMode,
+        fragment: &dyn Fn([f32; 2], [f32; 4]) -> [f32; 4],
+    ) {
+        // clip space to window space
+        let window = |v: &Vertex| {
+            let clip = transform.transform_point(point2(v.position[0], v.position[1]));
+            [
+                (clip.x + 1.) * 0.5 * self.width as f32,
+                (clip.y + 1.) * 0.5 * self.height as f32,
+            ]
+        };
+        let mut vertices = [&triangle[0], &triangle[1], &triangle[2]];
+        let mut p = [
+            window(vertices[0]),
+            window(vertices[1]),
+            window(vertices[2]),
+        ];
+
+        // evaluated from the same end either way round, so the triangles sharing an edge get
+        // exactly opposite values and no pixel on it is left out of both to rounding
+        let edge = |a: [f32; 2], b: [f32; 2], x: f32, y: f32| {
+            let (from, to, sign) = if (a[0], a[1]) <= (b[0], b[1]) {
+                (a, b, 1.)
+            } else {
+                (b, a, -1.)
+            };
+            sign * ((to[0] - from[0]) * (y - from[1]) - (to[1] - from[1]) * (x - from[0]))
+        };
+        let mut area = edge(p[0], p[1], p[2][0], p[2][1]);
+        if area == 0. {
+            return;
+        }
+        if area < 0. {
+            vertices.swap(1, 2);
+            p.swap(1, 2);
+            area = -area;
+        }
+
+        // Pixels exactly on an edge belong to only one of the two triangles sharing it, otherwise
+        // the diagonal of every translucent quad would be blended twice. The owner is picked by
+        // edge direction, which is reversed in the neighbouring triangle.
+        let owns_edge = |a: [f32; 2], b: [f32; 2]| {
+            let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+            dy > 0. || (dy == 0. && dx < 0.)
+        };
+        let edges = [(p[1], p[2]), (p[2], p[0]), (p[0], p[1])];
+        let owned = [
+            owns_edge(edges[0].0, edges[0].1),
+            owns_edge(edges[1].0, edges[1].1),
+            owns_edge(edges[2].0, edges[2].1),
+        ];
+
+        let min_x = p.iter().map(|p| p[0]).fold(f32::INFINITY, f32::min);
+        let max_x = p.iter().map(|p| p[0]).fold(f32::NEG_INFINITY, f32::max);
+        let min_y = p.iter().map(|p| p[1]).fold(f32::INFINITY, f32::min);
+        let max_y = p.iter().map(|p| p[1]).fold(f32::NEG_INFINITY, f32::max);
+        let x_range = (min_x.floor().max(0.) as u32)..(max_x.ceil().min(self.width as f32) as u32);
+        let y_range = (min_y.floor().max(0.) as u32)..(max_y.ceil().min(self.height as f32) as u32);
+
+        for y in y_range {
+            for x in x_range.clone() {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let mut weights = [0.; 3];
+                let mut inside = true;
+                for (i, (a, b)) in edges.iter().enumerate() {
+                    let w = edge(*a, *b, px, py);
+                    if w < 0. || (w == 0. && !owned[i]) {
+                        inside = false;
+                        break;
+                    }
+                    weights[i] = w / area;
+                }
+                if !inside {
+                    continue;
+                }
+
+                let interpolate = |f: &dyn Fn(&Vertex) -> f32| {
+                    weights[0] * f(vertices[0])
+                        + weights[1] * f(vertices[1])
+                        + weights[2] * f(vertices[2])
+                };
+                let uv = [interpolate(&|v| v.uv[0]), interpolate(&|v| v.uv[1])];
+                let color = [
+                    interpolate(&|v| v.color[0]),
+                    interpolate(&|v| v.color[1]),
+                    interpolate(&|v| v.color[2]),
+                    interpolate(&|v| v.color[3]),
+                ];
+
+                let src = fragment(uv, color);
+                let dst = &mut self.pixels[(y * self.width + x) as usize];
+                *dst = blend(blend_mode, src, *dst);
+            }
+        }
+    }
+
+    fn sample_nearest(&self, uv: [f32; 2]) -> [f32; 4] {
+        let x = ((uv[0] * self.width as f32).floor() as i64)
+            .max(0)
+            .min(self.width as i64 - 1);
+        let y = ((uv[1] * self.height as f32).floor() as i64)
+            .max(0)
+            .min(self.height as i64 - 1);
+        self.pixels[(y as u32 * self.width + x as u32) as usize]
+    }
+
+    // RGBA8 pixels into an x, y, width and height rect
+    fn write(&mut self, rect: (u32, u32, u32, u32), data: &[u8]) {
+        let (x, y, width, _) = rect;
+        for (i, texel) in data.chunks_exact(4).enumerate() {
+            let (dx, dy) = (i as u32 % width, i as u32 / width);
+            let pixel = &mut self.pixels[((y + dy) * self.width + x + dx) as usize];
+            for (channel, value) in pixel.iter_mut().zip(texel.iter()) {
+                *channel = *value as f32 / 255.;
+            }
+        }
+    }
+
+    // RGBA8 pixels of an x, y, width and height rect, rows from the bottom up
+    fn read(&self, rect: (u32, u32, u32, u32)) -> Vec<u8> {
+        let (x, y, width, height) = rect;
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for row in y..y + height {
+            let start = (row * self.width + x) as usize;
+            for pixel in self.pixels[start..start + width as usize].iter() {
+                data.extend(pixel.iter().map(|value| (value * 255.).round() as u8));
+            }
+        }
+        data
+    }
+
+    /// The pixels as an image, the top row first.
+    pub fn to_image(&self) -> RgbaImage {
+        let pixels = self.read((0, 0, self.width, self.height));
+        let image = RgbaImage::from_raw(self.width, self.height, pixels).unwrap();
+        image::imageops::flip_vertical(&image)
+    }
+}
+
+// shader.frag
+fn sprite_fragment(texture: &Rasterizer, uv: [f32; 2], color: [f32; 4]) -> [f32; 4] {
+    let texel = texture.sample_nearest(uv);
+    let alpha = texel[3] * color[3];
+    [
+        texel[0] * color[0] * alpha,
+        texel[1] * color[1] * alpha,
+        texel[2] * color[2] * alpha,
+        alpha,
+    ]
+}
+
+// light.frag
+fn light_fragment(uv: [f32; 2], color: [f32; 4]) -> [f32; 4] {
+    let falloff = (1. - (uv[0] * uv[0] + uv[1] * uv[1]).sqrt()).clamp(0., 1.);
+    let brightness = color[3] * falloff * falloff;
+    [
+        color[0] * brightness,
+        color[1] * brightness,
+        color[2] * brightness,
+        1.,
+    ]
+}
+
+// the blend functions `GlowBackend` sets for each mode, stored in 8 bits like an RGBA8 target
+fn blend(blend_mode: BlendMode, src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+    let mut out = [0.; 4];
+    for i in 0..4 {
+        let value = match blend_mode {
+            // ONE, ONE_MINUS_SRC_ALPHA
+            BlendMode::Premultiplied => src[i] + dst[i] * (1. - src[3]),
+            // ONE, ONE
+            BlendMode::Additive => src[i] + dst[i],
+            // DST_COLOR, ZERO
+            BlendMode::Multiply => src[i] * dst[i],
+        };
+        out[i] = (value.clamp(0., 1.) * 255.).round() / 255.;
+    }
+    out
+}
+
+// the fragment shaders `SoftwareBackend` can run
+#[derive(Clone, Copy, Debug)]
+enum Fragment {
+    Sprite,
+    Light,
+}
+
+/// A `gl::Backend` that draws with rasterizers, so whole frames of the game can be checked
+/// without a GPU. Programs can use the fragment shaders `shader.frag` and `light.frag`, with a
+/// vertex shader taking `Vertex`s through `u_transform`. Textures are RGBA8 and draws always
+/// cover the whole window or render target, ignoring the viewport and scissor, and write every
+/// channel.
+pub struct SoftwareBackend {
+    objects: RefCell<Objects>,
+}
+
+#[derive(Default)]
+struct Objects {
+    next_handle: u32,
+    // `None` for shaders without a software version, which programs can't draw with
+    shaders: HashMap<Handle, Option<Fragment>>,
+    programs: HashMap<Handle, Option<Fragment>>,
+    vertex_buffers: HashMap<Handle, Vec<u8>>,
+    index_buffers: HashMap<Handle, Vec<u16>>,
+    textures: HashMap<Handle, Rasterizer>,
+    // the texture each render target draws into
+    render_targets: HashMap<Handle, Handle>,
+    bound: Option<Handle>,
+}
+
+impl SoftwareBackend {
+    /// A backend drawing into a window of `width` by `height` pixels.
+    pub fn new(width: u32, height: u32) -> Self {
+        let mut objects = Objects::default();
+        objects
+            .textures
+            .insert(WINDOW, Rasterizer::new(width, height));
+        objects.next_handle = WINDOW.0 + 1;
+        SoftwareBackend {
+            objects: RefCell::new(objects),
+        }
+    }
+
+    fn create<T>(
+        &self,
+        objects: impl FnOnce(&mut Objects) -> &mut HashMap<Handle, T>,
+        value: T,
+    ) -> Handle {
+        let mut all = self.objects.borrow_mut();
+        let handle = Handle(all.next_handle);
+        all.next_handle += 1;
+        objects(&mut all).insert(handle, value);
+        handle
+    }
+}
+
+// the window is kept with the textures, as what the default render target draws into
+const WINDOW: Handle = Handle(0);
+
+impl Objects {
+    // the texture drawing currently goes to
+    fn target(&self) -> Handle {
+        match self.bound {
+            Some(target) => self.render_targets[&target],
+            None => WINDOW,
+        }
+    }
+
+    fn vertex(&self, buffer: Handle, index: usize) -> Vertex {
+        let stride = std::mem::size_of::<Vertex>();
+        let bytes = &self.vertex_buffers[&buffer][index * stride..(index + 1) * stride];
+        let floats: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        Vertex {
+            position: [floats[0], floats[1]],
+            uv: [floats[2], floats[3]],
+            color: [floats[4], floats[5], floats[6], floats[7]],
+        }
+    }
+}
+
+impl Backend for SoftwareBackend {
+    fn supports_instancing(&self) -> bool {
+        false
+    }
+
+    unsafe fn create_shader(
+        &self,
+        shader_type: ShaderType,
+        source: &str,
+    ) -> Result<Handle, String> {
+        let fragment = match shader_type {
+            ShaderType::Vertex => None,
+            ShaderType::Fragment if source == include_str!("shaders/shader.frag") => {
+                Some(Fragment::Sprite)
+            }
+            ShaderType::Fragment if source == include_str!("shaders/light.frag") => {
+                Some(Fragment::Light)
+            }
+            ShaderType::Fragment => None,
+        };
+        Ok(self.create(|objects| &mut objects.shaders, fragment))
+    }
+
+    unsafe fn create_program(
+        &self,
+        _vertex_shader: Handle,
+        fragment_shader: Handle,
+        _uniforms: &[UniformEntry],
+        vertex_format: &VertexFormat,
+        instance_format: Option<&VertexFormat>,
+    ) -> Result<Handle, String> {
+        if vertex_format.stride != std::mem::size_of::<Vertex>() || instance_format.is_some() {
+            return Err("programs can only take Vertex attributes".to_owned());
+        }
+        let fragment = self.objects.borrow().shaders[&fragment_shader];
+        Ok(self.create(|objects| &mut objects.programs, fragment))
+    }
+
+    unsafe fn create_vertex_buffer(&self) -> Result<Handle, String> {
+        Ok(self.create(|objects| &mut objects.vertex_buffers, Vec::new()))
+    }
+
+    unsafe fn create_index_buffer(&self) -> Result<Handle, String> {
+        Ok(self.create(|objects| &mut objects.index_buffers, Vec::new()))
+    }
+
+    unsafe fn create_texture(&self, desc: &TextureDescriptor) -> Result<Handle, String> {
+        if desc.format != TextureFormat::RGBAByte {
+            return Err(format!("{:?} textures are not supported", desc.format));
+        }
+        let texture = Rasterizer::new(desc.width, desc.height);
+        Ok(self.create(|objects| &mut objects.textures, texture))
+    }
+
+    unsafe fn create_render_target(&self, texture: Handle) -> Result<Handle, String> {
+        Ok(self.create(|objects| &mut objects.render_targets, texture))
+    }
+
+    unsafe fn allocate_vertices(&self, buffer: Handle, size: usize, _usage: BufferUsage) {
+        let mut objects = self.objects.borrow_mut();
+        *objects.vertex_buffers.get_mut(&buffer).unwrap() = vec![0; size];
+    }
+
+    unsafe fn write_vertices(&self, buffer: Handle, offset: usize, data: &[u8]) {
+        let mut objects = self.objects.borrow_mut();
+        let buffer = objects.vertex_buffers.get_mut(&buffer).unwrap();
+        buffer[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    unsafe fn allocate_indices(&self, buffer: Handle, len: usize, _usage: BufferUsage) {
+        let mut objects = self.objects.borrow_mut();
+        *objects.index_buffers.get_mut(&buffer).unwrap() = vec![0; len];
+    }
+
+    unsafe fn write_indices(&self, buffer: Handle, first: usize, indices: &[u16]) {
+        let mut objects = self.objects.borrow_mut();
+        let buffer = objects.index_buffers.get_mut(&buffer).unwrap();
+        buffer[first..first + indices.len()].copy_from_slice(indices);
+    }
+
+    unsafe fn write_texture(&self, texture: Handle, rect: (u32, u32, u32, u32), data: &[u8]) {
+        let mut objects = self.objects.borrow_mut();
+        objects
+            .textures
+            .get_mut(&texture)
+            .unwrap()
+            .write(rect, data);
+    }
+
+    unsafe fn read_texture(
+        &self,
+        texture: Handle,
+        width: u32,
+        height: u32,
+    ) -> Result<Vec<u8>, String> {
+        Ok(self.objects.borrow().textures[&texture].read((0, 0, width, height)))
+    }
+
+    unsafe fn bind_render_target(&self, target: Option<Handle>) {
+        self.objects.borrow_mut().bound = target;
+    }
+
+    unsafe fn set_viewport(&self, _area: Rect, _clip_to_area: bool) {}
+
+    unsafe fn clear(&self, color: [f32; 4]) {
+        let mut objects = self.objects.borrow_mut();
+        let target = objects.target();
+        objects.textures.get_mut(&target).unwrap().clear(color);
+    }
+
+    unsafe fn read_pixels(&self, rect: Rect) -> Vec<u8> {
+        let objects = self.objects.borrow();
+        let (x, y, width, height) = rect;
+        objects.textures[&objects.target()].read((x as u32, y as u32, width, height))
+    }
+
+    unsafe fn draw(&self, draw: &Draw) {
+        let mut objects = self.objects.borrow_mut();
+        assert_eq!(draw.state.topology, Topology::Triangles);
+        assert_eq!(draw.state.color_mask, [true; 4]);
+        let fragment = objects.programs[&draw.program]
+            .expect("no software version of the program's fragment shader");
+        let mut transform = Transform2D::identity();
+        let mut texture = None;
+        for uniform in draw.uniforms.iter() {
+            match *uniform {
+                UniformValue::Mat3(m) => {
+                    transform =
+                        Transform2D::row_major(m[0][0], m[0][1], m[1][0], m[1][1], m[2][0], m[2][1])
+                }
+                UniformValue::Texture(handle) => texture = Some(handle),
+                _ => {}
+            }
+        }
+        let vertices: Vec<Vertex> = match draw.index_buffer {
+            Some(indices) => objects.index_buffers[&indices][..draw.count]
+                .iter()
+                .map(|&i| objects.vertex(draw.vertex_buffer, draw.base_vertex + i as usize))
+                .collect(),
+            None => (0..draw.count)
+                .map(|i| objects.vertex(draw.vertex_buffer, draw.base_vertex + i))
+                .collect(),
+        };
+
+        // taken out while drawn into, so the textures it samples can be borrowed
+        let target_handle = objects.target();
+        let mut target = objects.textures.remove(&target_handle).unwrap();
+        for triangle in vertices.chunks_exact(3) {
+            match fragment {
+                Fragment::Sprite => {
+                    let texture = &objects.textures[&texture.expect("sprite without a texture")];
+                    target.render_triangle(
+                        triangle,
+                        &transform,
+                        draw.state.blend_mode,
+                        &|uv, color| sprite_fragment(texture, uv, color),
+                    );
+                }
+                Fragment::Light => target.render_triangle(
+                    triangle,
+                    &transform,
+                    draw.state.blend_mode,
+                    &light_fragment,
+                ),
+            }
+        }
+        objects.textures.insert(target_handle, target);
+    }
+
+    unsafe fn delete(&self, handle: Handle) {
+        let mut objects = self.objects.borrow_mut();
+        objects.shaders.remove(&handle);
+        objects.programs.remove(&handle);
+        objects.vertex_buffers.remove(&handle);
+        objects.index_buffers.remove(&handle);
+        objects.textures.remove(&handle);
+        objects.render_targets.remove(&handle);
+    }
+}
+
+/// Compares `image` against `tests/golden/<name>.png`, allowing each channel to be off by one.
+/// Run with `UPDATE_GOLDEN=1` to write the reference image instead. On mismatch the rendered
+/// image is saved next to the build output for inspection.
+pub fn assert_golden(name: &str, image: &RgbaImage) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image.save(&path).unwrap();
+        return;
+    }
+
+    let reference = image::open(&path)
+        .unwrap_or_else(|e| panic!("could not open golden image {:?}: {}", path, e))
+        .to_rgba();
+    assert_eq!(
+        reference.dimensions(),
+        image.dimensions(),
+        "golden image {} has different dimensions",
+        name
+    );
+    let mismatched = reference
+        .pixels()
+        .zip(image.pixels())
+        .filter(|(a, b)| {
+            a.0.iter()
+                .zip(b.0.iter())
+                .any(|(a, b)| (*a as i32 - *b as i32).abs() > 1)
+        })
+        .count();
+    if mismatched > 0 {
+        let actual = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("target/golden")
+            .join(format!("{}.actual.png", name));
+        std::fs::create_dir_all(actual.parent().unwrap()).unwrap();
+        image.save(&actual).unwrap();
+        panic!(
+            "{} pixels differ from golden image {}, rendered image saved to {:?}",
+            mismatched, name, actual
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::{default::Transform2D, point2, vec2};
+    use image::RgbaImage;
+
+    use super::{assert_golden, Rasterizer};
+    use crate::{
+        graphics::{render_sprite, Sprite, Vertex},
+        level::{create_level, generate_tile_batch, LayerDepth},
+        sprite_atlas::{sprite_rect, SPRITE_ATLAS_PAGES},
+    };
+
+    #[test]
+    fn premultiplied_blending() {
+        let texture = RgbaImage::from_raw(1, 1, vec![255, 255, 255, 255]).unwrap();
+        let quad = |color: [f32; 4]| {
+            let vertex = |x: f32, y: f32| Vertex {
+                position: [x, y],
+                uv: [0.5, 0.5],
+                color,
+            };
+            vec![
+                vertex(-1., -1.),
+                vertex(1., -1.),
+                vertex(-1., 1.),
+                vertex(1., -1.),
+                vertex(1., 1.),
+                vertex(-1., 1.),
+            ]
+        };
+
+        let mut rasterizer = Rasterizer::new(4, 4);
+        rasterizer.clear([0., 0., 1., 1.]);
+        rasterizer.render_vertices(&quad([1., 0., 0., 0.5]), &Transform2D::identity(), &texture);
+
+        // the shared diagonal must not be blended twice
+        for pixel in rasterizer.to_image().pixels() {
+            assert_eq!(pixel.0, [128, 0, 128, 255]);
+        }
+    }
+
+    #[test]
+    fn level_tiles() {
+        let pages: Vec<RgbaImage> = SPRITE_ATLAS_PAGES
+            .iter()
+            .map(|png| image::load_from_memory(png).unwrap().to_rgba())
+            .collect();
+        let pages: Vec<&RgbaImage> = pages.iter().collect();
+        let level = create_level();
+
+        let mut batch = generate_tile_batch(&level, &[LayerDepth::Ground]);
+        let mut ghost = Sprite::new(sprite_rect("player").unwrap(), 6, point2(6., -4.));
+        ghost.set_transform(Transform2D::create_scale(1. / 16., 1. / 16.));
+        render_sprite(&ghost, 0, level.player_start, &mut batch);
+
+        // 40x22 tiles at 16 pixels each
+        let transform =
+            Transform2D::create_scale(2. / 40., 2. / 22.).post_translate(vec2(-1., -1.));
+        let mut rasterizer = Rasterizer::new(40 * 16, 22 * 16);
+        rasterizer.clear([75. / 255., 58. / 255., 58. / 255., 1.]);
+        rasterizer.render_batch(&batch, &transform, &pages);
+
+        assert_golden("level_tiles", &rasterizer.to_image());
+    }
+}
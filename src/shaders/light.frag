@@ -0,0 +1,10 @@
+#version 100
+varying highp vec2 v_uv;
+varying lowp vec4 v_color;
+
+void main()
+{
+    // uv runs from -1 to 1 across the light, brightness falls off towards the edge
+    highp float falloff = clamp(1.0 - length(v_uv), 0.0, 1.0);
+    gl_FragColor = vec4(v_color.rgb * v_color.a * falloff * falloff, 1.0);
+}
\ No newline at end of file
@@ -0,0 +1,35 @@
+#version 100
+varying highp vec2 v_uv;
+varying lowp vec4 v_color;
+
+uniform sampler2D u_texture;
+uniform highp float u_intensity;
+uniform highp float u_time;
+uniform highp vec2 u_resolution;
+
+void main()
+{
+    highp vec2 uv = v_uv;
+
+    // a band of rows torn sideways, rolling over the screen like bad tape tracking
+    highp float band = fract(uv.y * 0.7 + u_time * 0.6);
+    highp float tear = smoothstep(0.85, 0.95, band) * (1.0 - smoothstep(0.95, 1.0, band));
+    uv.x += tear * 0.015 * u_intensity;
+
+    // red and blue drift apart horizontally
+    highp vec2 offset = vec2(3.0 * u_intensity / u_resolution.x, 0.0);
+    highp vec3 color = vec3(
+        texture2D(u_texture, uv + offset).r,
+        texture2D(u_texture, uv).g,
+        texture2D(u_texture, uv - offset).b
+    );
+
+    highp float luma = dot(color, vec3(0.299, 0.587, 0.114));
+    color = mix(color, vec3(luma), 0.7 * u_intensity);
+
+    // darken every other pair of screen rows
+    highp float scanline = mod(floor(gl_FragCoord.y / 2.0), 2.0);
+    color *= 1.0 - 0.3 * u_intensity * scanline;
+
+    gl_FragColor = vec4(color, 1.0) * v_color;
+}
@@ -0,0 +1,20 @@
+#version 100
+uniform highp mat3 u_transform;
+
+attribute highp vec2 a_corner;
+attribute highp vec2 i_origin;
+attribute highp vec4 i_axes;
+attribute highp vec4 i_uv;
+attribute lowp vec4 i_color;
+
+varying vec2 v_uv;
+varying vec4 v_color;
+
+void main()
+{
+    // the unit quad stretched over the sprite's edges
+    highp vec2 pos = i_origin + a_corner.x * i_axes.xy + a_corner.y * i_axes.zw;
+    v_uv = mix(i_uv.xy, i_uv.zw, a_corner);
+    v_color = i_color;
+    gl_Position = vec4((u_transform * vec3(pos, 1.0)).xy, 0.0, 1.0);
+}
\ No newline at end of file
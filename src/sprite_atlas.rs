@@ -0,0 +1,15 @@
+use anyhow::{format_err, Error};
+
+use crate::texture_atlas::AtlasRect;
+
+// every image in assets/ packed by build.rs, defines SPRITE_ATLAS_PAGES and SPRITE_RECTS
+include!(concat!(env!("OUT_DIR"), "/sprite_atlas.rs"));
+
+/// Rect of `assets/<name>.png` in the sprite atlas. Its page is the texture index to draw with.
+pub fn sprite_rect(name: &str) -> Result<AtlasRect, Error> {
+    SPRITE_RECTS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, rect)| *rect)
+        .ok_or_else(|| format_err!("no sprite named {} in the atlas", name))
+}
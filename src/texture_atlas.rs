@@ -2,61 +2,284 @@ use anyhow::{format_err, Error};
 
 pub type TextureRect = [u32; 4];
 
+/// A rect on one page of a `TextureAtlas`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AtlasRect {
+    pub page: usize,
+    pub rect: TextureRect,
+}
+
+/// Packs textures into pages of a fixed size with the MaxRects algorithm, opening a new page
+/// whenever a texture doesn't fit on any existing one. Every texture is kept `padding` pixels
+/// away from the page edges and `padding * 2` pixels away from other textures.
 pub struct TextureAtlas {
     size: (u32, u32),
-    texture_rects: Vec<[u32; 4]>,
+    padding: u32,
+    pages: Vec<Page>,
+}
+
+// Rects on a page are stored padded, so neighbouring textures only need to not overlap.
+struct Page {
+    // maximal free rects, these overlap each other
+    free_rects: Vec<TextureRect>,
+    used_rects: Vec<TextureRect>,
 }
 
 impl TextureAtlas {
-    pub fn new(size: (u32, u32)) -> TextureAtlas {
+    pub fn new(size: (u32, u32), padding: u32) -> TextureAtlas {
         TextureAtlas {
-            size: size,
-            texture_rects: Vec::new(),
+            size,
+            padding,
+            pages: Vec::new(),
         }
     }
-    pub fn add_texture(&mut self, size: (u32, u32)) -> Result<[u32; 4], Error> {
-        let pad = |rect: [u32; 4]| [rect[0] - 1, rect[1] - 1, rect[2] + 1, rect[3] + 1];
-        let unpad = |rect: [u32; 4]| [rect[0] + 1, rect[1] + 1, rect[2] - 1, rect[3] - 1];
-        let tex_coords = {
-            let mut y = 1;
-            let mut x = 1;
-            let mut coords = None;
-            'outer: while y < self.size.1 - size.1 {
-                let mut next_y = self.size.1;
-                while x < self.size.0 - size.0 {
-                    let t1 = pad([x, y, x + size.0, y + size.1]);
-                    let overlap = self.texture_rects.iter().filter(|t2| {
-                        !(t1[0] >= t2[2] || t2[2] <= t2[0] || t1[1] >= t2[3] || t1[3] <= t2[1])
-                    });
-                    let mut any_intersect = false;
-                    // on the x axis, skip past any overlapping textures
-                    // on the y axis, jump up to the lowest top edge in the row
-                    for rect in overlap {
-                        if rect[3] < next_y {
-                            next_y = rect[3] + 1;
-                        }
-                        if rect[2] > x {
-                            x = rect[2] + 1;
-                        }
-                        any_intersect = true;
-                    }
-                    if !any_intersect {
-                        coords = Some(unpad(t1));
-                        break 'outer;
-                    }
-                }
-                x = 1;
-                y = next_y;
-            }
-            coords
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn add_texture(&mut self, size: (u32, u32)) -> Result<AtlasRect, Error> {
+        let padded = (size.0 + self.padding * 2, size.1 + self.padding * 2);
+        if padded.0 > self.size.0 || padded.1 > self.size.1 {
+            return Err(format_err!(
+                "Texture of size {:?} does not fit in an atlas page of size {:?}",
+                size,
+                self.size
+            ));
+        }
+
+        let placement = self
+            .pages
+            .iter()
+            .enumerate()
+            .filter_map(|(i, page)| {
+                page.find_position(padded)
+                    .map(|(rect, score)| (i, rect, score))
+            })
+            .min_by_key(|(_, _, score)| *score);
+        let (page, rect) = match placement {
+            Some((page, rect, _)) => (page, rect),
+            None => {
+                let page = Page::new(self.size);
+                let (rect, _) = page
+                    .find_position(padded)
+                    .expect("texture fits on an empty page");
+                self.pages.push(page);
+                (self.pages.len() - 1, rect)
+            }
         };
+        self.pages[page].place(rect);
+
+        Ok(AtlasRect {
+            page,
+            rect: [
+                rect[0] + self.padding,
+                rect[1] + self.padding,
+                rect[2] - self.padding,
+                rect[3] - self.padding,
+            ],
+        })
+    }
+
+    /// Frees the space of a texture returned by `add_texture` for later textures.
+    pub fn remove_texture(&mut self, texture: AtlasRect) -> Result<(), Error> {
+        let rect = texture.rect;
+        let padded = [
+            rect[0] - self.padding,
+            rect[1] - self.padding,
+            rect[2] + self.padding,
+            rect[3] + self.padding,
+        ];
+        let page = self
+            .pages
+            .get_mut(texture.page)
+            .ok_or_else(|| format_err!("No atlas page {}", texture.page))?;
+        let index = page
+            .used_rects
+            .iter()
+            .position(|r| *r == padded)
+            .ok_or_else(|| format_err!("Texture {:?} is not in the atlas", texture))?;
+        page.used_rects.swap_remove(index);
+        page.free_rects.push(padded);
+        page.prune_free_rects();
+        Ok(())
+    }
+}
+
+impl Page {
+    fn new(size: (u32, u32)) -> Page {
+        Page {
+            free_rects: vec![[0, 0, size.0, size.1]],
+            used_rects: Vec::new(),
+        }
+    }
+
+    // Best short side fit: picks the free rect leaving the least space along its tighter side.
+    fn find_position(&self, size: (u32, u32)) -> Option<(TextureRect, (u32, u32))> {
+        self.free_rects
+            .iter()
+            .filter(|free| free[2] - free[0] >= size.0 && free[3] - free[1] >= size.1)
+            .map(|free| {
+                let leftover_x = free[2] - free[0] - size.0;
+                let leftover_y = free[3] - free[1] - size.1;
+                let rect = [free[0], free[1], free[0] + size.0, free[1] + size.1];
+                (
+                    rect,
+                    (leftover_x.min(leftover_y), leftover_x.max(leftover_y)),
+                )
+            })
+            .min_by_key(|(_, score)| *score)
+    }
+
+    fn place(&mut self, rect: TextureRect) {
+        let mut split = Vec::new();
+        self.free_rects.retain(|free| {
+            if !overlaps(free, &rect) {
+                return true;
+            }
+            // keep the parts of the free rect on each side of the placed rect
+            if rect[0] > free[0] {
+                split.push([free[0], free[1], rect[0], free[3]]);
+            }
+            if rect[2] < free[2] {
+                split.push([rect[2], free[1], free[2], free[3]]);
+            }
+            if rect[1] > free[1] {
+                split.push([free[0], free[1], free[2], rect[1]]);
+            }
+            if rect[3] < free[3] {
+                split.push([free[0], rect[3], free[2], free[3]]);
+            }
+            false
+        });
+        self.free_rects.extend(split);
+        self.prune_free_rects();
+        self.used_rects.push(rect);
+    }
 
-        match tex_coords {
-            Some(coords) => {
-                self.texture_rects.push(coords);
-                Ok(coords)
+    // removes free rects that are entirely inside another one
+    fn prune_free_rects(&mut self) {
+        let mut i = 0;
+        while i < self.free_rects.len() {
+            let rect = self.free_rects[i];
+            let contained =
+                self.free_rects.iter().enumerate().any(|(j, other)| {
+                    j != i && contains(other, &rect) && (rect != *other || j < i)
+                });
+            if contained {
+                self.free_rects.swap_remove(i);
+            } else {
+                i += 1;
             }
-            None => Err(format_err!("Texture atlas overflow")),
         }
     }
 }
+
+fn overlaps(a: &TextureRect, b: &TextureRect) -> bool {
+    a[0] < b[2] && b[0] < a[2] && a[1] < b[3] && b[1] < a[3]
+}
+
+fn contains(outer: &TextureRect, inner: &TextureRect) -> bool {
+    outer[0] <= inner[0] && outer[1] <= inner[1] && outer[2] >= inner[2] && outer[3] >= inner[3]
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    use super::{overlaps, AtlasRect, TextureAtlas};
+
+    fn assert_valid(atlas: &TextureAtlas, rects: &[AtlasRect]) {
+        let padding = atlas.padding;
+        for (i, a) in rects.iter().enumerate() {
+            assert!(a.rect[0] >= padding && a.rect[1] >= padding);
+            assert!(a.rect[2] + padding <= atlas.size.0 && a.rect[3] + padding <= atlas.size.1);
+            let padded = |r: AtlasRect| {
+                [
+                    r.rect[0] - padding,
+                    r.rect[1] - padding,
+                    r.rect[2] + padding,
+                    r.rect[3] + padding,
+                ]
+            };
+            for b in rects[i + 1..].iter().filter(|b| b.page == a.page) {
+                assert!(
+                    !overlaps(&padded(*a), &padded(*b)),
+                    "{:?} and {:?} overlap",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rects_never_overlap() {
+        let mut rng = SmallRng::seed_from_u64(47);
+        let mut atlas = TextureAtlas::new((256, 256), 1);
+        let mut rects = Vec::new();
+        for _ in 0..500 {
+            let size = (rng.gen_range(1, 40), rng.gen_range(1, 40));
+            let rect = atlas.add_texture(size).unwrap();
+            assert_eq!(
+                (rect.rect[2] - rect.rect[0], rect.rect[3] - rect.rect[1]),
+                size
+            );
+            rects.push(rect);
+        }
+        assert!(atlas.page_count() > 1);
+        assert_valid(&atlas, &rects);
+    }
+
+    #[test]
+    fn rects_never_overlap_after_removal() {
+        let mut rng = SmallRng::seed_from_u64(1047);
+        let mut atlas = TextureAtlas::new((128, 128), 2);
+        let mut rects = Vec::new();
+        for _ in 0..1000 {
+            if !rects.is_empty() && rng.gen_bool(0.4) {
+                let rect = rects.swap_remove(rng.gen_range(0, rects.len()));
+                atlas.remove_texture(rect).unwrap();
+            } else {
+                let size = (rng.gen_range(1, 30), rng.gen_range(1, 30));
+                rects.push(atlas.add_texture(size).unwrap());
+            }
+            assert_valid(&atlas, &rects);
+        }
+    }
+
+    #[test]
+    fn spills_onto_new_pages() {
+        let mut atlas = TextureAtlas::new((64, 64), 0);
+        let rects: Vec<AtlasRect> = (0..5)
+            .map(|_| atlas.add_texture((32, 32)).unwrap())
+            .collect();
+        assert!(rects[..4].iter().all(|r| r.page == 0));
+        assert_eq!(rects[4].page, 1);
+        assert_eq!(atlas.page_count(), 2);
+        assert_valid(&atlas, &rects);
+    }
+
+    #[test]
+    fn removed_space_is_reused() {
+        let mut atlas = TextureAtlas::new((64, 64), 1);
+        let rects: Vec<AtlasRect> = (0..4)
+            .map(|_| atlas.add_texture((30, 30)).unwrap())
+            .collect();
+        assert_eq!(atlas.page_count(), 1);
+
+        atlas.remove_texture(rects[2]).unwrap();
+        assert_eq!(atlas.add_texture((30, 30)).unwrap(), rects[2]);
+        assert_eq!(atlas.page_count(), 1);
+
+        assert!(atlas.remove_texture(rects[1]).is_ok());
+        assert!(atlas.remove_texture(rects[1]).is_err());
+    }
+
+    #[test]
+    fn texture_larger_than_a_page_is_an_error() {
+        let mut atlas = TextureAtlas::new((64, 64), 1);
+        assert!(atlas.add_texture((63, 10)).is_err());
+        assert!(atlas.add_texture((62, 62)).is_ok());
+    }
+}
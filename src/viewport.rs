@@ -0,0 +1,99 @@
+use euclid::{
+    default::{Point2D, Size2D},
+    point2,
+};
+
+/// Where the fixed size game screen ends up inside a window, in physical pixels. The screen is
+/// scaled by the largest integer multiple that fits and centered, leaving black bars around it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    /// Offset of the screen from the bottom left of the window, as GL expects it.
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    window_height: u32,
+    scale: f32,
+}
+
+impl Viewport {
+    pub fn letterbox(window: Size2D<u32>, screen: Size2D<u32>) -> Viewport {
+        let fit = (window.width as f32 / screen.width as f32)
+            .min(window.height as f32 / screen.height as f32);
+        // windows smaller than the screen can't be pixel perfect, shrink to fit instead
+        let scale = if fit >= 1. { fit.floor() } else { fit.max(0.) };
+
+        let width = (screen.width as f32 * scale).round() as u32;
+        let height = (screen.height as f32 * scale).round() as u32;
+        Viewport {
+            x: (window.width as i32 - width as i32) / 2,
+            y: (window.height as i32 - height as i32) / 2,
+            width,
+            height,
+            window_height: window.height,
+            scale,
+        }
+    }
+
+    /// Maps a window position (physical pixels, origin top left) to screen pixels.
+    pub fn window_to_screen(&self, position: Point2D<f32>) -> Point2D<f32> {
+        let top = self.window_height as i32 - self.y - self.height as i32;
+        if self.scale > 0. {
+            point2(
+                (position.x - self.x as f32) / self.scale,
+                (position.y - top as f32) / self.scale,
+            )
+        } else {
+            point2(0., 0.)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use euclid::{point2, size2};
+
+    use super::Viewport;
+
+    #[test]
+    fn screens_scale_by_whole_multiples_and_center() {
+        let viewport = Viewport::letterbox(size2(1000, 700), size2(400, 300));
+        assert_eq!(
+            (viewport.x, viewport.y, viewport.width, viewport.height),
+            (100, 50, 800, 600)
+        );
+    }
+
+    #[test]
+    fn small_windows_shrink_the_screen_to_fit() {
+        let viewport = Viewport::letterbox(size2(300, 300), size2(400, 300));
+        assert_eq!(
+            (viewport.x, viewport.y, viewport.width, viewport.height),
+            (0, 37, 300, 225)
+        );
+    }
+
+    #[test]
+    fn minimized_windows_have_an_empty_viewport() {
+        let viewport = Viewport::letterbox(size2(0, 0), size2(400, 300));
+        assert_eq!((viewport.width, viewport.height), (0, 0));
+        assert_eq!(viewport.window_to_screen(point2(10., 10.)), point2(0., 0.));
+    }
+
+    #[test]
+    fn window_positions_map_to_screen_pixels() {
+        // 2x, with bars of 100 pixels left and right, 26 above and 25 below
+        let viewport = Viewport::letterbox(size2(1000, 651), size2(400, 300));
+        assert_eq!(viewport.y, 25);
+        // window positions are from the top left, so the bar above is what is skipped
+        assert_eq!(viewport.window_to_screen(point2(100., 26.)), point2(0., 0.));
+        assert_eq!(
+            viewport.window_to_screen(point2(900., 626.)),
+            point2(400., 300.)
+        );
+        assert_eq!(
+            viewport.window_to_screen(point2(0., 0.)),
+            point2(-50., -13.)
+        );
+    }
+}